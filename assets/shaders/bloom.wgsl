@@ -0,0 +1,103 @@
+// Blurs the bright parts of the HDR scene by downsampling it into a mip chain and
+// adding each level back onto the one above it on the way up.
+
+struct Bloom {
+    // Brightness where pixels start to glow
+    threshold: f32,
+    // Width of the soft transition around the threshold
+    knee: f32,
+}
+
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> bloom: Bloom;
+
+fn sample_source(uv: vec2<f32>, offset: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_source));
+    return textureSampleLevel(t_source, s_source, uv + offset * texel, 0.0).rgb;
+}
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// Average of four samples, weighted so a single very bright pixel cannot flicker
+// across the whole bloom as it moves
+fn karis_average(a: vec3<f32>, b: vec3<f32>, c: vec3<f32>, d: vec3<f32>) -> vec4<f32> {
+    let color = (a + b + c + d) * 0.25;
+    let weight = 1.0 / (1.0 + luminance(color));
+    return vec4<f32>(color * weight, weight);
+}
+
+// Quadratic falloff below the threshold instead of a hard cut
+fn soft_threshold(color: vec3<f32>) -> vec3<f32> {
+    let brightness = max(color.r, max(color.g, color.b));
+    var soft = clamp(brightness - bloom.threshold + bloom.knee, 0.0, 2.0 * bloom.knee);
+    soft = soft * soft / (4.0 * bloom.knee + 1e-4);
+    let contribution = max(brightness - bloom.threshold, soft) / max(brightness, 1e-4);
+    return color * contribution;
+}
+
+// The 13 tap downsample from Jimenez' "Next Generation Post Processing in Call of Duty:
+// Advanced Warfare", five overlapping boxes of four samples around the center
+@fragment
+fn fs_prefilter(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let uv = in.tex_coords;
+    let a = sample_source(uv, vec2<f32>(-2.0, -2.0));
+    let b = sample_source(uv, vec2<f32>(0.0, -2.0));
+    let c = sample_source(uv, vec2<f32>(2.0, -2.0));
+    let d = sample_source(uv, vec2<f32>(-2.0, 0.0));
+    let e = sample_source(uv, vec2<f32>(0.0, 0.0));
+    let f = sample_source(uv, vec2<f32>(2.0, 0.0));
+    let g = sample_source(uv, vec2<f32>(-2.0, 2.0));
+    let h = sample_source(uv, vec2<f32>(0.0, 2.0));
+    let i = sample_source(uv, vec2<f32>(2.0, 2.0));
+    let j = sample_source(uv, vec2<f32>(-1.0, -1.0));
+    let k = sample_source(uv, vec2<f32>(1.0, -1.0));
+    let l = sample_source(uv, vec2<f32>(-1.0, 1.0));
+    let m = sample_source(uv, vec2<f32>(1.0, 1.0));
+
+    // Only the first downsample sees single bright pixels, so only it needs the Karis average
+    let sum = karis_average(j, k, l, m) * 0.5
+        + karis_average(a, b, d, e) * 0.125
+        + karis_average(b, c, e, f) * 0.125
+        + karis_average(d, e, g, h) * 0.125
+        + karis_average(e, f, h, i) * 0.125;
+    return vec4<f32>(soft_threshold(sum.rgb / sum.a), 1.0);
+}
+
+@fragment
+fn fs_downsample(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let uv = in.tex_coords;
+    let a = sample_source(uv, vec2<f32>(-2.0, -2.0));
+    let b = sample_source(uv, vec2<f32>(0.0, -2.0));
+    let c = sample_source(uv, vec2<f32>(2.0, -2.0));
+    let d = sample_source(uv, vec2<f32>(-2.0, 0.0));
+    let e = sample_source(uv, vec2<f32>(0.0, 0.0));
+    let f = sample_source(uv, vec2<f32>(2.0, 0.0));
+    let g = sample_source(uv, vec2<f32>(-2.0, 2.0));
+    let h = sample_source(uv, vec2<f32>(0.0, 2.0));
+    let i = sample_source(uv, vec2<f32>(2.0, 2.0));
+    let j = sample_source(uv, vec2<f32>(-1.0, -1.0));
+    let k = sample_source(uv, vec2<f32>(1.0, -1.0));
+    let l = sample_source(uv, vec2<f32>(-1.0, 1.0));
+    let m = sample_source(uv, vec2<f32>(1.0, 1.0));
+
+    let color = e * 0.125 + (a + c + g + i) * 0.03125 + (b + d + f + h) * 0.0625 + (j + k + l + m) * 0.125;
+    return vec4<f32>(color, 1.0);
+}
+
+// 3x3 tent filter, the result is added onto the larger level by the blend state
+@fragment
+fn fs_upsample(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let uv = in.tex_coords;
+    var color = sample_source(uv, vec2<f32>(0.0, 0.0)) * 4.0;
+    color += (sample_source(uv, vec2<f32>(-1.0, 0.0)) + sample_source(uv, vec2<f32>(1.0, 0.0))
+        + sample_source(uv, vec2<f32>(0.0, -1.0)) + sample_source(uv, vec2<f32>(0.0, 1.0))) * 2.0;
+    color += sample_source(uv, vec2<f32>(-1.0, -1.0)) + sample_source(uv, vec2<f32>(1.0, -1.0))
+        + sample_source(uv, vec2<f32>(-1.0, 1.0)) + sample_source(uv, vec2<f32>(1.0, 1.0));
+    return vec4<f32>(color / 16.0, 1.0);
+}
@@ -0,0 +1,95 @@
+// Bins the lights into a grid of clusters that splits the view frustum into screen
+// tiles and exponentially growing depth slices, one invocation per cluster.
+
+const LIGHT_DIRECTIONAL: u32 = 1u;
+// Keep in sync with MAX_LIGHTS_PER_CLUSTER in light.rs
+const MAX_LIGHTS_PER_CLUSTER: u32 = 128u;
+
+struct Light {
+    position: vec3<f32>,
+    kind: u32,
+    direction: vec3<f32>,
+    range: f32,
+    color: vec3<f32>,
+    spot_scale: f32,
+    spot_offset: f32,
+    shadow: i32,
+}
+
+struct Lights {
+    count: u32,
+    lights: array<Light>,
+}
+
+struct Clusters {
+    view: mat4x4<f32>,
+    grid_size: vec3<u32>,
+    debug_view: u32,
+    screen_size: vec2<f32>,
+    znear: f32,
+    zfar: f32,
+    tan_half_fovy: f32,
+    aspect: f32,
+}
+
+@group(0) @binding(0)
+var<storage, read> lights: Lights;
+@group(0) @binding(1)
+var<uniform> clusters: Clusters;
+@group(0) @binding(2)
+var<storage, read_write> cluster_light_counts: array<u32>;
+@group(0) @binding(3)
+var<storage, read_write> cluster_light_indices: array<u32>;
+
+// Distance in front of the camera where depth slice `slice` starts
+fn slice_depth(slice: u32) -> f32 {
+    return clusters.znear * pow(clusters.zfar / clusters.znear, f32(slice) / f32(clusters.grid_size.z));
+}
+
+fn sphere_intersects_aabb(center: vec3<f32>, radius: f32, aabb_min: vec3<f32>, aabb_max: vec3<f32>) -> bool {
+    let closest = clamp(center, aabb_min, aabb_max);
+    let offset = center - closest;
+    return dot(offset, offset) <= radius * radius;
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let grid = clusters.grid_size;
+    if any(id >= grid) {
+        return;
+    }
+    let cluster = id.x + grid.x * (id.y + grid.y * id.z);
+
+    // Tile bounds in NDC, tiles are numbered from the top left like pixels
+    let tile_min = vec2<f32>(id.xy) / vec2<f32>(grid.xy);
+    let tile_max = vec2<f32>(id.xy + 1u) / vec2<f32>(grid.xy);
+    let ndc_min = vec2<f32>(tile_min.x * 2.0 - 1.0, 1.0 - tile_max.y * 2.0);
+    let ndc_max = vec2<f32>(tile_max.x * 2.0 - 1.0, 1.0 - tile_min.y * 2.0);
+
+    // The view space bounds of the tile at both ends of the slice, the camera looks down -Z
+    let near = slice_depth(id.z);
+    let far = slice_depth(id.z + 1u);
+    let extent = vec2<f32>(clusters.tan_half_fovy * clusters.aspect, clusters.tan_half_fovy);
+    let xy_near_min = ndc_min * extent * near;
+    let xy_near_max = ndc_max * extent * near;
+    let xy_far_min = ndc_min * extent * far;
+    let xy_far_max = ndc_max * extent * far;
+    let aabb_min = vec3<f32>(min(xy_near_min, xy_far_min), -far);
+    let aabb_max = vec3<f32>(max(xy_near_max, xy_far_max), -near);
+
+    var count = 0u;
+    let offset = cluster * MAX_LIGHTS_PER_CLUSTER;
+    for (var i = 0u; i < lights.count && count < MAX_LIGHTS_PER_CLUSTER; i++) {
+        let light = lights.lights[i];
+        var visible = light.kind == LIGHT_DIRECTIONAL;
+        if !visible {
+            let center = (clusters.view * vec4<f32>(light.position, 1.0)).xyz;
+            visible = sphere_intersects_aabb(center, light.range, aabb_min, aabb_max);
+        }
+        if visible {
+            cluster_light_indices[offset + count] = i;
+            count++;
+        }
+    }
+    cluster_light_counts[cluster] = count;
+}
@@ -1,23 +1,97 @@
 struct Camera {
     view_pos: vec4<f32>,
     view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    // Without the TAA jitter, for motion vectors
+    unjittered_view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
 }
 
 @group(1) @binding(0)
 var<uniform> camera: Camera;
 
+const LIGHT_POINT: u32 = 0u;
+const LIGHT_DIRECTIONAL: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+
 struct Light {
     position: vec3<f32>,
+    kind: u32,
+    direction: vec3<f32>,
+    // Zero for directional lights, which reach everywhere
+    range: f32,
+    // Premultiplied by the intensity
     color: vec3<f32>,
+    // Fades spot lights between the cones, clamp(cos_angle * scale + offset)
+    spot_scale: f32,
+    spot_offset: f32,
+    // Index into `shadows`, negative when the light casts no shadow
+    shadow: i32,
+}
+
+struct Lights {
+    count: u32,
+    lights: array<Light>,
+}
+
+// View space clusters the lights are binned into by cluster.wgsl
+struct Clusters {
+    view: mat4x4<f32>,
+    grid_size: vec3<u32>,
+    debug_view: u32,
+    screen_size: vec2<f32>,
+    znear: f32,
+    zfar: f32,
+    tan_half_fovy: f32,
+    aspect: f32,
 }
 
+const MAX_LIGHTS_PER_CLUSTER: u32 = 128u;
+const DEBUG_VIEW_LIGHT_CLUSTERS: u32 = 1u;
+const DEBUG_VIEW_SHADOW_CASCADES: u32 = 2u;
+
 @group(2) @binding(0)
-var<uniform> light: Light;
+var<storage, read> lights: Lights;
+@group(2) @binding(1)
+var<uniform> clusters: Clusters;
+@group(2) @binding(2)
+var<storage, read> cluster_light_counts: array<u32>;
+@group(2) @binding(3)
+var<storage, read> cluster_light_indices: array<u32>;
+
+// Directional lights have one entry per cascade, spot lights a single one
+struct Shadow {
+    view_proj: mat4x4<f32>,
+    // Lights with a lower resolution than the array only use a corner of a tile in their layer
+    uv_scale: f32,
+    layer: u32,
+    bias: f32,
+    normal_offset: f32,
+    // View depth where this cascade ends
+    cascade_far: f32,
+    cascade_count: u32,
+    // Corner of the tile
+    uv_offset: vec2<f32>,
+    // Point lights store the distance to the light divided by its range instead of the depth
+    light_position: vec3<f32>,
+    linear_range: f32,
+}
+
+// Fraction at the end of each cascade that fades into the next one
+const CASCADE_BLEND: f32 = 0.1;
+
+@group(2) @binding(4)
+var<storage, read> shadows: array<Shadow>;
+@group(2) @binding(5)
+var t_shadow: texture_depth_2d_array;
+@group(2) @binding(6)
+var s_shadow: sampler_comparison;
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
     @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec4<f32>,
 }
 
 struct InstanceInput {
@@ -35,6 +109,7 @@ struct VertexOutput {
     @location(0) tex_coords: vec2<f32>,
     @location(1) world_normal: vec3<f32>,
     @location(2) world_position: vec3<f32>,
+    @location(3) world_tangent: vec4<f32>,
 }
 
 @vertex
@@ -44,6 +119,7 @@ fn vs_main(model: VertexInput, instance: InstanceInput,) -> VertexOutput {
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
     out.world_normal = normal_matrix * model.normal;
+    out.world_tangent = vec4<f32>((model_matrix * vec4<f32>(model.tangent.xyz, 0.0)).xyz, model.tangent.w);
     var world_position: vec4<f32> = model_matrix * vec4<f32>(model.position, 1.0);
     out.world_position = world_position.xyz;
     out.clip_position = camera.view_proj * world_position;
@@ -52,30 +128,361 @@ fn vs_main(model: VertexInput, instance: InstanceInput,) -> VertexOutput {
 
 // Fragment shader
 
+struct Material {
+    base_color_factor: vec4<f32>,
+    emissive_factor: vec3<f32>,
+    metallic_factor: f32,
+    roughness_factor: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+    // NORMAL_* flags of how the normal map is stored
+    normal_encoding: u32,
+}
+
+// Only x and y are stored, z is reconstructed
+const NORMAL_TWO_CHANNEL: u32 = 1u;
+// Stored in -1..1 instead of 0..1
+const NORMAL_SIGNED: u32 = 2u;
+
 @group(0) @binding(0)
-var t_diffuse: texture_2d<f32>;
+var<uniform> material: Material;
 @group(0) @binding(1)
-var s_diffuse: sampler;
+var t_base_color: texture_2d<f32>;
+@group(0) @binding(2)
+var s_base_color: sampler;
+@group(0) @binding(3)
+var t_metallic_roughness: texture_2d<f32>;
+@group(0) @binding(4)
+var s_metallic_roughness: sampler;
+@group(0) @binding(5)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(6)
+var s_normal: sampler;
+@group(0) @binding(7)
+var t_occlusion: texture_2d<f32>;
+@group(0) @binding(8)
+var s_occlusion: sampler;
+@group(0) @binding(9)
+var t_emissive: texture_2d<f32>;
+@group(0) @binding(10)
+var s_emissive: sampler;
+
+@group(3) @binding(0)
+var t_irradiance: texture_cube<f32>;
+@group(3) @binding(1)
+var t_prefiltered: texture_cube<f32>;
+@group(3) @binding(2)
+var t_brdf_lut: texture_2d<f32>;
+@group(3) @binding(3)
+var s_environment: sampler;
+
+const PI: f32 = 3.14159265359;
+
+// Trowbridge-Reitz GGX normal distribution
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Smith's method with Schlick-GGX for both view and light directions
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let r = roughness + 1.0;
+    let k = r * r / 8.0;
+    let g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return g_v * g_l;
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Smooth cutoff at the light range from KHR_lights_punctual, keeping the inverse square falloff inside
+fn range_attenuation(distance_squared: f32, range: f32) -> f32 {
+    if range <= 0.0 {
+        return 1.0;
+    }
+    let ratio = distance_squared / (range * range);
+    let window = clamp(1.0 - ratio * ratio, 0.0, 1.0);
+    return window * window;
+}
+
+// Direction towards the light in `l` and the irradiance it casts on a surface facing it
+fn incident_light(light: Light, world_position: vec3<f32>, l: ptr<function, vec3<f32>>) -> vec3<f32> {
+    if light.kind == LIGHT_DIRECTIONAL {
+        *l = -light.direction;
+        return light.color;
+    }
+
+    let to_light = light.position - world_position;
+    let distance_squared = max(dot(to_light, to_light), 1e-4);
+    *l = to_light * inverseSqrt(distance_squared);
+    var attenuation = range_attenuation(distance_squared, light.range) / distance_squared;
+    if light.kind == LIGHT_SPOT {
+        let spot = clamp(dot(light.direction, -*l) * light.spot_scale + light.spot_offset, 0.0, 1.0);
+        attenuation *= spot * spot;
+    }
+    return light.color * attenuation;
+}
+
+// Fraction of the light reaching `world_position` in one shadow map, filtered over
+// 3x3 bilinear comparisons. `l` points towards the light and `n` is the surface normal.
+// Moving the receiver towards the light and off the surface keeps it from shadowing itself
+fn shadow_receiver(shadow: Shadow, world_position: vec3<f32>, n: vec3<f32>, l: vec3<f32>) -> vec3<f32> {
+    let n_dot_l = clamp(dot(n, l), 0.0, 1.0);
+    return world_position + l * shadow.bias + n * shadow.normal_offset * (1.0 - n_dot_l);
+}
+
+fn sample_shadow(shadow: Shadow, position: vec3<f32>) -> f32 {
+    let clip = shadow.view_proj * vec4<f32>(position, 1.0);
+    let ndc = clip.xyz / clip.w;
+    if clip.w <= 0.0 || any(abs(ndc.xy) > vec2<f32>(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+
+    var reference = ndc.z;
+    if shadow.linear_range > 0.0 {
+        reference = distance(position, shadow.light_position) / shadow.linear_range;
+    }
+
+    let uv = (ndc.xy * vec2<f32>(0.5, -0.5) + 0.5) * shadow.uv_scale + shadow.uv_offset;
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_shadow));
+    // The filter stays inside the tile, its neighbors belong to other lights
+    let tile_min = shadow.uv_offset + 0.5 * texel;
+    let tile_max = shadow.uv_offset + shadow.uv_scale - 0.5 * texel;
+    var visibility = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            let sample_uv = clamp(uv + offset, tile_min, tile_max);
+            visibility += textureSampleCompareLevel(t_shadow, s_shadow, sample_uv, shadow.layer, reference);
+        }
+    }
+    return visibility / 9.0;
+}
+
+// Cascade of the light with shadows starting at `index` covering the view depth `depth`
+fn shadow_cascade(index: i32, depth: f32) -> u32 {
+    let count = shadows[index].cascade_count;
+    var cascade = 0u;
+    while cascade + 1u < count && depth > shadows[index + i32(cascade)].cascade_far {
+        cascade++;
+    }
+    return cascade;
+}
+
+// Cube face order of the point light entries, matching CUBE_FACES in shadow.rs
+fn cube_face(direction: vec3<f32>) -> i32 {
+    let a = abs(direction);
+    if a.x >= a.y && a.x >= a.z {
+        return select(1, 0, direction.x > 0.0);
+    }
+    if a.y >= a.z {
+        return select(3, 2, direction.y > 0.0);
+    }
+    return select(5, 4, direction.z > 0.0);
+}
+
+fn shadow_visibility(light: Light, world_position: vec3<f32>, n: vec3<f32>, l: vec3<f32>) -> f32 {
+    let index = light.shadow;
+    if index < 0 {
+        return 1.0;
+    }
+
+    if light.kind == LIGHT_POINT {
+        // The faces share their settings, the one to sample is the one the receiver falls into
+        let position = shadow_receiver(shadows[index], world_position, n, l);
+        let face = cube_face(position - light.position);
+        return sample_shadow(shadows[index + face], position);
+    }
+
+    let depth = -(clusters.view * vec4<f32>(world_position, 1.0)).z;
+    let cascade = shadow_cascade(index, depth);
+    let shadow = shadows[index + i32(cascade)];
+    var visibility = sample_shadow(shadow, shadow_receiver(shadow, world_position, n, l));
+
+    // Fading into the next cascade hides the seam, past the last one the shadow fades out
+    let blend_start = shadow.cascade_far * (1.0 - CASCADE_BLEND);
+    if depth > blend_start {
+        var next = 1.0;
+        if cascade + 1u < shadow.cascade_count {
+            let next_shadow = shadows[index + i32(cascade) + 1];
+            next = sample_shadow(next_shadow, shadow_receiver(next_shadow, world_position, n, l));
+        }
+        visibility = mix(visibility, next, smoothstep(blend_start, shadow.cascade_far, depth));
+    }
+    return visibility;
+}
+
+// Color of the cascade of the first shadowed directional light covering `world_position`
+fn cascade_debug_color(world_position: vec3<f32>) -> vec3<f32> {
+    let colors = array<vec3<f32>, 4>(
+        vec3<f32>(1.0, 0.2, 0.2),
+        vec3<f32>(0.2, 1.0, 0.2),
+        vec3<f32>(0.2, 0.4, 1.0),
+        vec3<f32>(1.0, 1.0, 0.2),
+    );
+    for (var i = 0u; i < lights.count; i++) {
+        let light = lights.lights[i];
+        if light.kind == LIGHT_DIRECTIONAL && light.shadow >= 0 {
+            let depth = -(clusters.view * vec4<f32>(world_position, 1.0)).z;
+            return colors[shadow_cascade(light.shadow, depth) % 4u];
+        }
+    }
+    return vec3<f32>(1.0);
+}
+
+// Cook-Torrance specular plus Lambertian diffuse for light arriving from `l`, times the cosine term
+fn direct_light(n: vec3<f32>, v: vec3<f32>, l: vec3<f32>, base_color: vec3<f32>, f0: vec3<f32>, metallic: f32, roughness: f32) -> vec3<f32> {
+    let h = normalize(v + l);
+    let n_dot_v = max(dot(n, v), 1e-4);
+    let n_dot_l = max(dot(n, l), 0.0);
+    let n_dot_h = max(dot(n, h), 0.0);
+
+    let f = fresnel_schlick(max(dot(h, v), 0.0), f0);
+    let d = distribution_ggx(n_dot_h, roughness);
+    let g = geometry_smith(n_dot_v, n_dot_l, roughness);
+    let specular = d * g * f / (4.0 * n_dot_v * max(n_dot_l, 1e-4));
+
+    let k_diffuse = (1.0 - f) * (1.0 - metallic);
+    let diffuse = k_diffuse * base_color / PI;
+    return (diffuse + specular) * n_dot_l;
+}
+
+// Fresnel averaged over the microfacets, rough surfaces reflect less at grazing angles
+fn fresnel_schlick_roughness(cos_theta: f32, f0: vec3<f32>, roughness: f32) -> vec3<f32> {
+    return f0 + (max(vec3<f32>(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// Diffuse and specular light from the environment, using the split sum approximation
+// with the prefiltered radiance and the BRDF lookup table for the specular part
+fn environment_light(n: vec3<f32>, v: vec3<f32>, base_color: vec3<f32>, f0: vec3<f32>, metallic: f32, roughness: f32) -> vec3<f32> {
+    let n_dot_v = max(dot(n, v), 1e-4);
+    let f = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+    let k_diffuse = (1.0 - f) * (1.0 - metallic);
+    let diffuse = k_diffuse * base_color * textureSampleLevel(t_irradiance, s_environment, n, 0.0).rgb;
+
+    let r = reflect(-v, n);
+    let max_lod = f32(textureNumLevels(t_prefiltered) - 1u);
+    let prefiltered = textureSampleLevel(t_prefiltered, s_environment, r, roughness * max_lod).rgb;
+    let brdf = textureSampleLevel(t_brdf_lut, s_environment, vec2<f32>(n_dot_v, roughness), 0.0).rg;
+    let specular = prefiltered * (f0 * brdf.x + brdf.y);
+
+    return diffuse + specular;
+}
+
+// Index of the cluster containing a fragment, the inverse of the slicing in cluster.wgsl
+fn cluster_index(frag_coord: vec2<f32>, world_position: vec3<f32>) -> u32 {
+    let grid = clusters.grid_size;
+    let tile = min(vec2<u32>(frag_coord / clusters.screen_size * vec2<f32>(grid.xy)), grid.xy - 1u);
+    let depth = -(clusters.view * vec4<f32>(world_position, 1.0)).z;
+    let slice_f = log(max(depth, clusters.znear) / clusters.znear) / log(clusters.zfar / clusters.znear) * f32(grid.z);
+    let slice = min(u32(max(slice_f, 0.0)), grid.z - 1u);
+    return tile.x + grid.x * (tile.y + grid.y * slice);
+}
+
+// Blue through green and yellow to red as `t` goes from 0 to 1
+fn heatmap(t: f32) -> vec3<f32> {
+    let x = clamp(t, 0.0, 1.0) * 3.0;
+    return clamp(vec3<f32>(x - 1.0, min(x, 3.0 - x), 1.0 - x), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// Applies the normal map, building a tangent frame from screen space
+// derivatives when the mesh does not provide tangents
+fn surface_normal(in: VertexOutput) -> vec3<f32> {
+    let n = normalize(in.world_normal);
+    var tangent_normal = textureSample(t_normal, s_normal, in.tex_coords).xyz;
+    if (material.normal_encoding & NORMAL_SIGNED) == 0u {
+        tangent_normal = tangent_normal * 2.0 - 1.0;
+    }
+    if (material.normal_encoding & NORMAL_TWO_CHANNEL) != 0u {
+        // Unit length before the scale, like the z a three channel map stores
+        let xy = tangent_normal.xy;
+        tangent_normal.z = sqrt(max(1.0 - dot(xy, xy), 0.0));
+    }
+    tangent_normal = vec3<f32>(tangent_normal.xy * material.normal_scale, tangent_normal.z);
+
+    // Derivatives are only defined in uniform control flow, so they are taken before
+    // the per-fragment choice of the tangent frame
+    let dp_dx = dpdx(in.world_position);
+    let dp_dy = dpdy(in.world_position);
+    let duv_dx = dpdx(in.tex_coords);
+    let duv_dy = dpdy(in.tex_coords);
+
+    var t: vec3<f32>;
+    var b: vec3<f32>;
+    if dot(in.world_tangent.xyz, in.world_tangent.xyz) > 0.0 {
+        t = normalize(in.world_tangent.xyz - n * dot(n, in.world_tangent.xyz));
+        b = cross(n, t) * in.world_tangent.w;
+    } else {
+        let dp_dy_perp = cross(dp_dy, n);
+        let dp_dx_perp = cross(n, dp_dx);
+        t = dp_dy_perp * duv_dx.x + dp_dx_perp * duv_dy.x;
+        b = dp_dy_perp * duv_dx.y + dp_dx_perp * duv_dy.y;
+        let scale = inverseSqrt(max(dot(t, t), dot(b, b)));
+        if scale > 1e16 {
+            // Degenerate UVs, there is no frame to apply the normal map in
+            return n;
+        }
+        t *= scale;
+        b *= scale;
+    }
+
+    return normalize(mat3x3<f32>(t, b, n) * tangent_normal);
+}
 
 @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    var out: FragmentOutput;
+    out.color = shade(in);
+    out.motion = motion_vector(vec4<f32>(in.world_position, 1.0));
+    return out;
+}
+
+fn shade(in: VertexOutput) -> vec4<f32> {
+    let base_color = textureSample(t_base_color, s_base_color, in.tex_coords) * material.base_color_factor;
+    let metallic_roughness = textureSample(t_metallic_roughness, s_metallic_roughness, in.tex_coords);
+    let metallic = clamp(metallic_roughness.b * material.metallic_factor, 0.0, 1.0);
+    // Fully smooth surfaces make the specular highlight vanish, so keep a little roughness
+    let roughness = clamp(metallic_roughness.g * material.roughness_factor, 0.04, 1.0);
+    let occlusion = mix(1.0, textureSample(t_occlusion, s_occlusion, in.tex_coords).r, material.occlusion_strength);
+    let emissive = textureSample(t_emissive, s_emissive, in.tex_coords).rgb * material.emissive_factor;
 
-    // We don't need (or want) much ambient light, so 0.1 is fine
-    let ambient_strength = 0.1;
-    let ambient_color = light.color * ambient_strength;
+    let n = surface_normal(in);
+    // Normal maps would make the shadow offset follow surface detail the shadow map does not have
+    let geometric_normal = normalize(in.world_normal);
+    let v = normalize(camera.view_pos.xyz - in.world_position);
 
-    let light_dir = normalize(light.position - in.world_position);
-    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
-    let half_dir = normalize(view_dir + light_dir);
+    // Dielectrics reflect about 4% head on, metals tint the reflection with their base color
+    let f0 = mix(vec3<f32>(0.04), base_color.rgb, metallic);
 
-    let diffuse_strength = max(dot(in.world_normal, light_dir), 0.0);
-    let diffuse_color = light.color * diffuse_strength;
+    // Only the lights the culling pass found to reach this fragment's cluster
+    let cluster = cluster_index(in.clip_position.xy, in.world_position);
+    let light_count = cluster_light_counts[cluster];
+    let offset = cluster * MAX_LIGHTS_PER_CLUSTER;
+    var direct = vec3<f32>(0.0);
+    for (var i = 0u; i < light_count; i++) {
+        let light = lights.lights[cluster_light_indices[offset + i]];
+        var l: vec3<f32>;
+        let irradiance = incident_light(light, in.world_position, &l) * shadow_visibility(light, in.world_position, geometric_normal, l);
+        direct += direct_light(n, v, l, base_color.rgb, f0, metallic, roughness) * irradiance;
+    }
 
-    let specular_strength = pow(max(dot(in.world_normal, half_dir), 0.0), 32.0);
-    let specular_color = specular_strength * light.color;
+    let ambient = environment_light(n, v, base_color.rgb, f0, metallic, roughness) * occlusion;
+    let color = ambient + direct + emissive;
 
-    let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
+    if clusters.debug_view == DEBUG_VIEW_LIGHT_CLUSTERS {
+        // Saturates at a quarter of the cluster capacity, keeping some of the shading for orientation
+        let heat = heatmap(f32(light_count) / f32(MAX_LIGHTS_PER_CLUSTER / 4u));
+        let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+        return vec4<f32>(mix(heat, heat * clamp(luminance, 0.0, 1.0), 0.3), 1.0);
+    }
 
-    return vec4<f32>(result, object_color.a);
-}
\ No newline at end of file
+    if clusters.debug_view == DEBUG_VIEW_SHADOW_CASCADES {
+        return vec4<f32>(mix(color, cascade_debug_color(in.world_position), 0.5), 1.0);
+    }
+
+    return vec4<f32>(color, base_color.a);
+}
@@ -0,0 +1,46 @@
+// Projects an equirectangular environment map onto the six faces of one mip
+// level of a cubemap.
+
+@group(0) @binding(0)
+var t_equirect: texture_2d<f32>;
+@group(0) @binding(1)
+var s_equirect: sampler;
+@group(0) @binding(2)
+var t_cube: texture_storage_2d_array<rgba16float, write>;
+
+const PI: f32 = 3.14159265359;
+
+// Direction through `uv` on cube face `face`, using the face orientation the
+// GPU samples cubemaps with so that looking up the direction finds the texel
+fn cube_direction(face: u32, uv: vec2<f32>) -> vec3<f32> {
+    let st = uv * 2.0 - 1.0;
+    switch face {
+        case 0u: { return vec3<f32>(1.0, -st.y, -st.x); }
+        case 1u: { return vec3<f32>(-1.0, -st.y, st.x); }
+        case 2u: { return vec3<f32>(st.x, 1.0, st.y); }
+        case 3u: { return vec3<f32>(st.x, -1.0, -st.y); }
+        case 4u: { return vec3<f32>(st.x, -st.y, 1.0); }
+        default: { return vec3<f32>(-st.x, -st.y, -1.0); }
+    }
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(t_cube);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+
+    let uv = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(size);
+    let direction = normalize(cube_direction(id.z, uv));
+    // Z is up, the center of the image faces +X and the image runs to the right when turning right
+    let equirect_uv = vec2<f32>(
+        0.5 + atan2(-direction.y, direction.x) / (2.0 * PI),
+        0.5 - asin(direction.z) / PI,
+    );
+    // The equirectangular mip with about four times the width of the face
+    let lod = max(log2(f32(textureDimensions(t_equirect).x) / (4.0 * f32(size.x))), 0.0);
+    let color = textureSampleLevel(t_equirect, s_equirect, equirect_uv, lod);
+    textureStore(t_cube, id.xy, id.z, vec4<f32>(color.rgb, 1.0));
+}
+
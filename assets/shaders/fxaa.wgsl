@@ -0,0 +1,63 @@
+// Fast approximate anti-aliasing on the tonemapped image, blurs along the edges it finds
+// in the luma of the neighboring pixels.
+
+const REDUCE_MIN: f32 = 1.0 / 128.0;
+const REDUCE_MUL: f32 = 1.0 / 8.0;
+// Longest distance in pixels the blur reaches along an edge
+const SPAN_MAX: f32 = 8.0;
+
+struct Fxaa {
+    // Set when the input holds linear colors, decoded from an sRGB format by the sampler
+    linear_input: u32,
+}
+
+@group(0) @binding(0)
+var t_input: texture_2d<f32>;
+@group(0) @binding(1)
+var s_input: sampler;
+@group(0) @binding(2)
+var<uniform> fxaa: Fxaa;
+
+fn sample_input(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(t_input, s_input, uv, 0.0).rgb;
+}
+
+// Edges are found on perceived brightness, so linear input is roughly gamma encoded first
+fn luma(color: vec3<f32>) -> f32 {
+    let l = dot(color, vec3<f32>(0.299, 0.587, 0.114));
+    return select(l, sqrt(l), fxaa.linear_input != 0u);
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let uv = in.tex_coords;
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_input));
+
+    let rgb_m = sample_input(uv);
+    let luma_nw = luma(sample_input(uv + vec2<f32>(-1.0, -1.0) * texel));
+    let luma_ne = luma(sample_input(uv + vec2<f32>(1.0, -1.0) * texel));
+    let luma_sw = luma(sample_input(uv + vec2<f32>(-1.0, 1.0) * texel));
+    let luma_se = luma(sample_input(uv + vec2<f32>(1.0, 1.0) * texel));
+    let luma_m = luma(rgb_m);
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    // The blur runs perpendicular to the luma gradient, along the edge
+    var direction = vec2<f32>(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let direction_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * REDUCE_MUL, REDUCE_MIN);
+    let scale = 1.0 / (min(abs(direction.x), abs(direction.y)) + direction_reduce);
+    direction = clamp(direction * scale, vec2<f32>(-SPAN_MAX), vec2<f32>(SPAN_MAX)) * texel;
+
+    let rgb_a = 0.5 * (sample_input(uv + direction * (1.0 / 3.0 - 0.5)) + sample_input(uv + direction * (2.0 / 3.0 - 0.5)));
+    let rgb_b = rgb_a * 0.5 + 0.25 * (sample_input(uv - direction * 0.5) + sample_input(uv + direction * 0.5));
+
+    // The wider blur ran past the edge when it picked up brightness from outside the neighborhood
+    let luma_b = luma(rgb_b);
+    if luma_b < luma_min || luma_b > luma_max {
+        return vec4<f32>(rgb_a, 1.0);
+    }
+    return vec4<f32>(rgb_b, 1.0);
+}
@@ -0,0 +1,177 @@
+// Precomputes the maps used for image based lighting from an environment cubemap:
+// the diffuse irradiance, the GGX prefiltered specular mips and the BRDF lookup table.
+
+@group(0) @binding(0)
+var t_environment: texture_cube<f32>;
+@group(0) @binding(1)
+var s_environment: sampler;
+@group(0) @binding(2)
+var t_output: texture_storage_2d_array<rgba16float, write>;
+
+struct Prefilter {
+    roughness: f32,
+}
+
+@group(0) @binding(3)
+var<uniform> prefilter: Prefilter;
+@group(0) @binding(4)
+var t_brdf_lut: texture_storage_2d<rgba16float, write>;
+
+const PI: f32 = 3.14159265359;
+const IRRADIANCE_SAMPLES: u32 = 512u;
+const PREFILTER_SAMPLES: u32 = 256u;
+const BRDF_SAMPLES: u32 = 512u;
+
+// Same face orientation as in equirect_to_cube.wgsl
+fn cube_direction(face: u32, uv: vec2<f32>) -> vec3<f32> {
+    let st = uv * 2.0 - 1.0;
+    switch face {
+        case 0u: { return vec3<f32>(1.0, -st.y, -st.x); }
+        case 1u: { return vec3<f32>(-1.0, -st.y, st.x); }
+        case 2u: { return vec3<f32>(st.x, 1.0, st.y); }
+        case 3u: { return vec3<f32>(st.x, -1.0, -st.y); }
+        case 4u: { return vec3<f32>(st.x, -st.y, 1.0); }
+        default: { return vec3<f32>(-st.x, -st.y, -1.0); }
+    }
+}
+
+// Low discrepancy point `i` of `n` in the unit square
+fn hammersley(i: u32, n: u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(n), f32(reverseBits(i)) * 2.3283064365386963e-10);
+}
+
+// Rotates a direction around +Z onto the hemisphere around `n`
+fn tangent_to_world(v: vec3<f32>, n: vec3<f32>) -> vec3<f32> {
+    let up = select(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 0.0, 1.0), abs(n.z) < 0.999);
+    let t = normalize(cross(up, n));
+    let b = cross(n, t);
+    return t * v.x + b * v.y + n * v.z;
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Half vector distributed by the GGX normal distribution around `n`
+fn importance_sample_ggx(xi: vec2<f32>, n: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return tangent_to_world(vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta), n);
+}
+
+// Environment mip whose texels cover about the solid angle of one sample,
+// which averages out the noise of bright spots that a few samples would hit
+fn sample_lod(pdf: f32, sample_count: u32) -> f32 {
+    let size = f32(textureDimensions(t_environment).x);
+    let texel_solid_angle = 4.0 * PI / (6.0 * size * size);
+    let sample_solid_angle = 1.0 / (f32(sample_count) * pdf + 1e-4);
+    return max(0.5 * log2(sample_solid_angle / texel_solid_angle), 0.0);
+}
+
+fn output_direction(id: vec3<u32>) -> vec3<f32> {
+    let uv = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(textureDimensions(t_output));
+    return normalize(cube_direction(id.z, uv));
+}
+
+// Cosine weighted average of the incoming radiance, the diffuse light reflected by a white surface
+@compute @workgroup_size(8, 8, 1)
+fn cs_irradiance(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(t_output);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+
+    let n = output_direction(id);
+    var irradiance = vec3<f32>(0.0);
+    for (var i = 0u; i < IRRADIANCE_SAMPLES; i++) {
+        let xi = hammersley(i, IRRADIANCE_SAMPLES);
+        let phi = 2.0 * PI * xi.x;
+        let cos_theta = sqrt(1.0 - xi.y);
+        let sin_theta = sqrt(xi.y);
+        let l = tangent_to_world(vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta), n);
+        // One mip more than the sample footprint, diffuse light has no detail to lose
+        let lod = sample_lod(cos_theta / PI, IRRADIANCE_SAMPLES) + 1.0;
+        irradiance += textureSampleLevel(t_environment, s_environment, l, lod).rgb;
+    }
+    // The samples already follow the cosine, so their mean is the convolution divided by PI
+    irradiance /= f32(IRRADIANCE_SAMPLES);
+
+    textureStore(t_output, id.xy, id.z, vec4<f32>(irradiance, 1.0));
+}
+
+// Radiance convolved with the GGX lobe for `prefilter.roughness`, assuming that
+// the view direction equals the normal and the reflection direction
+@compute @workgroup_size(8, 8, 1)
+fn cs_prefilter(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(t_output);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+
+    let n = output_direction(id);
+    let roughness = prefilter.roughness;
+    if roughness == 0.0 {
+        textureStore(t_output, id.xy, id.z, textureSampleLevel(t_environment, s_environment, n, 0.0));
+        return;
+    }
+
+    var color = vec3<f32>(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < PREFILTER_SAMPLES; i++) {
+        let h = importance_sample_ggx(hammersley(i, PREFILTER_SAMPLES), n, roughness);
+        let l = normalize(2.0 * dot(n, h) * h - n);
+        let n_dot_l = dot(n, l);
+        if n_dot_l > 0.0 {
+            // With v == n the pdf of the reflected direction reduces to D / 4
+            let n_dot_h = max(dot(n, h), 0.0);
+            let lod = sample_lod(distribution_ggx(n_dot_h, roughness) / 4.0, PREFILTER_SAMPLES);
+            color += textureSampleLevel(t_environment, s_environment, l, lod).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+
+    textureStore(t_output, id.xy, id.z, vec4<f32>(color / max(weight, 1e-4), 1.0));
+}
+
+// Scale and bias applied to F0 by the specular BRDF integrated over the hemisphere,
+// indexed by the cosine between normal and view direction and the roughness
+@compute @workgroup_size(8, 8, 1)
+fn cs_brdf_lut(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(t_brdf_lut);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+
+    let coords = (vec2<f32>(id.xy) + 0.5) / vec2<f32>(size);
+    let n_dot_v = coords.x;
+    let roughness = coords.y;
+    let v = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    let n = vec3<f32>(0.0, 0.0, 1.0);
+    // Schlick-GGX remaps roughness differently for image based lighting
+    let k = roughness * roughness / 2.0;
+
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < BRDF_SAMPLES; i++) {
+        let h = importance_sample_ggx(hammersley(i, BRDF_SAMPLES), n, roughness);
+        let l = normalize(2.0 * dot(v, h) * h - v);
+        let n_dot_l = max(l.z, 0.0);
+        if n_dot_l > 0.0 {
+            let n_dot_h = max(h.z, 0.0);
+            let v_dot_h = max(dot(v, h), 0.0);
+            let g = n_dot_v / (n_dot_v * (1.0 - k) + k) * n_dot_l / (n_dot_l * (1.0 - k) + k);
+            let g_visibility = g * v_dot_h / (n_dot_h * n_dot_v);
+            let fc = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fc) * g_visibility;
+            bias += fc * g_visibility;
+        }
+    }
+
+    let lut = vec2<f32>(scale, bias) / f32(BRDF_SAMPLES);
+    textureStore(t_brdf_lut, id.xy, vec4<f32>(lut, 0.0, 1.0));
+}
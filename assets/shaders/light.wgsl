@@ -1,17 +1,35 @@
 struct Camera {
-    mvp: mat4x4<f32>,
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    // Without the TAA jitter, for motion vectors
+    unjittered_view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
 }
 
 @group(0) @binding(0)
 var<uniform> camera: Camera;
 
+const LIGHT_DIRECTIONAL: u32 = 1u;
+
 struct Light {
     position: vec3<f32>,
+    kind: u32,
+    direction: vec3<f32>,
+    range: f32,
     color: vec3<f32>,
+    spot_scale: f32,
+    spot_offset: f32,
+    shadow: i32,
+}
+
+struct Lights {
+    count: u32,
+    lights: array<Light>,
 }
 
 @group(1) @binding(0)
-var<uniform> light: Light;
+var<storage, read> lights: Lights;
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
@@ -20,20 +38,32 @@ struct VertexInput {
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) color: vec3<f32>,
+    @location(1) world_position: vec3<f32>,
 }
 
+// Draws a small copy of the model at each light, one instance per light
 @vertex
-fn vs_main(model: VertexInput,) -> VertexOutput {
+fn vs_main(model: VertexInput, @builtin(instance_index) instance_index: u32) -> VertexOutput {
+    let light = lights.lights[instance_index];
     let scale = 0.25;
     var out: VertexOutput;
-    out.clip_position = camera.mvp * vec4<f32>(model.position * scale + light.position, 1.0);
-    out.color = light.color;
+    out.world_position = model.position * scale + light.position;
+    out.clip_position = camera.view_proj * vec4<f32>(out.world_position, 1.0);
+    // Directional lights have no position, their gizmo is moved outside the clip volume
+    if light.kind == LIGHT_DIRECTIONAL {
+        out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+    }
+    // Normalized so that bright lights do not blow out the gizmo
+    out.color = light.color / max(max(light.color.r, max(light.color.g, light.color.b)), 1e-4);
     return out;
 }
 
 // Fragment shader
 
 @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4<f32>(in.color, 1.0);
-}
\ No newline at end of file
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    var out: FragmentOutput;
+    out.color = vec4<f32>(in.color, 1.0);
+    out.motion = motion_vector(vec4<f32>(in.world_position, 1.0));
+    return out;
+}
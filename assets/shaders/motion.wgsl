@@ -0,0 +1,15 @@
+// Output of the passes drawing into the HDR target, the shader including it
+// declares the `camera` uniform with the unjittered and previous view projections.
+
+// Motion vectors go into a second target, for temporal anti-aliasing
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+    @location(1) motion: vec2<f32>,
+}
+
+// Movement of a point since the previous frame in UV units, `w` is 0 for directions
+fn motion_vector(point: vec4<f32>) -> vec2<f32> {
+    let current = camera.unjittered_view_proj * point;
+    let previous = camera.prev_view_proj * point;
+    return (current.xy / current.w - previous.xy / previous.w) * vec2<f32>(0.5, -0.5);
+}
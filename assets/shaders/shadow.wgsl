@@ -0,0 +1,40 @@
+// Renders the depth of the scene as seen from a shadow casting light.
+
+struct ShadowView {
+    view_proj: mat4x4<f32>,
+    light_position: vec3<f32>,
+    // Point light range, zero when the plain depth is stored
+    linear_range: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> shadow_view: ShadowView;
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+}
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>, instance: InstanceInput) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(instance.model_matrix_0, instance.model_matrix_1, instance.model_matrix_2, instance.model_matrix_3,);
+    let world_position = model_matrix * vec4<f32>(position, 1.0);
+    var out: VertexOutput;
+    out.clip_position = shadow_view.view_proj * world_position;
+    out.world_position = world_position.xyz;
+    return out;
+}
+
+// Point lights store the distance to the light, which unlike the depth
+// is the same for a point in every cube face it falls into
+@fragment
+fn fs_distance(in: VertexOutput) -> @builtin(frag_depth) f32 {
+    return distance(in.world_position, shadow_view.light_position) / shadow_view.linear_range;
+}
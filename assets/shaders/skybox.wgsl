@@ -0,0 +1,44 @@
+// Draws the environment cubemap at the far plane, behind everything drawn before it.
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    // Without the TAA jitter, for motion vectors
+    unjittered_view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@group(1) @binding(0)
+var t_environment: texture_cube<f32>;
+@group(1) @binding(1)
+var s_environment: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+// A single triangle covering the whole target
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    var out: VertexOutput;
+    out.ndc = uv * 2.0 - 1.0;
+    out.clip_position = vec4<f32>(out.ndc, 1.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    let far = camera.inv_view_proj * vec4<f32>(in.ndc, 1.0, 1.0);
+    let direction = far.xyz / far.w - camera.view_pos.xyz;
+    var out: FragmentOutput;
+    out.color = vec4<f32>(textureSample(t_environment, s_environment, direction).rgb, 1.0);
+    // The sky is infinitely far away, only camera rotation moves it
+    out.motion = motion_vector(vec4<f32>(direction, 0.0));
+    return out;
+}
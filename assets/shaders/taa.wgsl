@@ -0,0 +1,100 @@
+// Temporal anti-aliasing, blends the jittered frame into the reprojected history of the
+// previous frames so that each pixel ends up averaged over several sub-pixel positions.
+
+struct Taa {
+    // Weight of the current frame, 1 when there is no usable history
+    current_weight: f32,
+}
+
+@group(0) @binding(0)
+var t_current: texture_2d<f32>;
+@group(0) @binding(1)
+var t_history: texture_2d<f32>;
+@group(0) @binding(2)
+var s_history: sampler;
+@group(0) @binding(3)
+var t_motion: texture_2d<f32>;
+@group(0) @binding(4)
+var<uniform> taa: Taa;
+
+// Blending compressed colors keeps single bright pixels from dominating the average
+fn compress(color: vec3<f32>) -> vec3<f32> {
+    return color / (1.0 + max(color.r, max(color.g, color.b)));
+}
+
+fn uncompress(color: vec3<f32>) -> vec3<f32> {
+    return color / max(1.0 - max(color.r, max(color.g, color.b)), 1e-4);
+}
+
+// Luminance and chroma are separated, so the neighborhood box fits the colors more tightly
+fn rgb_to_ycocg(color: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(
+        dot(color, vec3<f32>(0.25, 0.5, 0.25)),
+        dot(color, vec3<f32>(0.5, 0.0, -0.5)),
+        dot(color, vec3<f32>(-0.25, 0.5, -0.25)),
+    );
+}
+
+fn ycocg_to_rgb(color: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(
+        color.x + color.y - color.z,
+        color.x + color.z,
+        color.x - color.y - color.z,
+    );
+}
+
+fn load_current(pixel: vec2<i32>) -> vec3<f32> {
+    let size = vec2<i32>(textureDimensions(t_current));
+    return rgb_to_ycocg(compress(textureLoad(t_current, clamp(pixel, vec2<i32>(0), size - 1), 0).rgb));
+}
+
+// Moves the history towards the center of the box until it lies inside it
+fn clip_to_box(history: vec3<f32>, box_min: vec3<f32>, box_max: vec3<f32>) -> vec3<f32> {
+    let center = 0.5 * (box_max + box_min);
+    let extents = 0.5 * (box_max - box_min) + 1e-5;
+    let offset = history - center;
+    let units = abs(offset / extents);
+    let outside = max(units.x, max(units.y, units.z));
+    if outside > 1.0 {
+        return center + offset / outside;
+    }
+    return history;
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(position.xy);
+    let uv = position.xy / vec2<f32>(textureDimensions(t_current));
+
+    // Mean and deviation of the 3x3 neighborhood, history outside of what the current
+    // frame shows around this pixel is stale and would ghost
+    var moment1 = vec3<f32>(0.0);
+    var moment2 = vec3<f32>(0.0);
+    var current = vec3<f32>(0.0);
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let color = load_current(pixel + vec2<i32>(x, y));
+            moment1 += color;
+            moment2 += color * color;
+            if x == 0 && y == 0 {
+                current = color;
+            }
+        }
+    }
+    let mean = moment1 / 9.0;
+    let deviation = sqrt(max(moment2 / 9.0 - mean * mean, vec3<f32>(0.0)));
+    let box_min = mean - 1.25 * deviation;
+    let box_max = mean + 1.25 * deviation;
+
+    // The motion vector points from where this surface was in the previous frame
+    let history_uv = uv - textureLoad(t_motion, pixel, 0).xy;
+    var history = rgb_to_ycocg(compress(textureSampleLevel(t_history, s_history, history_uv, 0.0).rgb));
+    history = clip_to_box(history, box_min, box_max);
+
+    var weight = taa.current_weight;
+    if any(history_uv < vec2<f32>(0.0)) || any(history_uv > vec2<f32>(1.0)) {
+        weight = 1.0;
+    }
+    let color = mix(history, current, weight);
+    return vec4<f32>(uncompress(ycocg_to_rgb(color)), 1.0);
+}
@@ -0,0 +1,113 @@
+// Adds the bloom onto the HDR scene, compresses the result into the displayable range
+// and writes it to the output target.
+
+const TONEMAPPING_ACES: u32 = 0u;
+const TONEMAPPING_REINHARD: u32 = 1u;
+
+struct Tonemap {
+    // Linear scale applied before the curve
+    exposure: f32,
+    tonemapping: u32,
+    // Set when the output target does not encode to sRGB by itself
+    encode_srgb: u32,
+    // Zero while bloom is disabled
+    bloom_intensity: f32,
+}
+
+@group(0) @binding(0)
+var t_hdr: texture_2d<f32>;
+@group(0) @binding(1)
+var t_bloom: texture_2d<f32>;
+@group(0) @binding(2)
+var s_bloom: sampler;
+@group(0) @binding(3)
+var<uniform> tonemap: Tonemap;
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// Stephen Hill's fit of the ACES reference rendering and output transforms
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let input = mat3x3<f32>(
+        vec3<f32>(0.59719, 0.07600, 0.02840),
+        vec3<f32>(0.35458, 0.90834, 0.13383),
+        vec3<f32>(0.04823, 0.01566, 0.83777),
+    );
+    let output = mat3x3<f32>(
+        vec3<f32>(1.60475, -0.10208, -0.00327),
+        vec3<f32>(-0.53108, 1.10813, -0.07276),
+        vec3<f32>(-0.07367, -0.00605, 1.07602),
+    );
+    let v = input * color;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return output * (a / b);
+}
+
+// Scales by the luminance instead of per channel, so bright colors keep their hue
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (1.0 + luminance(color));
+}
+
+// Sixth order fit of the AgX base contrast curve
+fn agx_contrast(x: vec3<f32>) -> vec3<f32> {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+// Troy Sobotka's AgX, highlights desaturate towards white instead of skewing their hue
+fn agx(color: vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3<f32>(
+        vec3<f32>(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3<f32>(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3<f32>(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let outset = mat3x3<f32>(
+        vec3<f32>(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3<f32>(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3<f32>(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    // The curve works on the log encoded exposure, 16.5 stops around middle grey
+    var v = log2(max(inset * color, vec3<f32>(1e-10)));
+    v = (clamp(v, vec3<f32>(min_ev), vec3<f32>(max_ev)) - min_ev) / (max_ev - min_ev);
+    v = agx_contrast(v);
+    return pow(max(outset * v, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let uv = position.xy / vec2<f32>(textureDimensions(t_hdr));
+    let bloom = textureSampleLevel(t_bloom, s_bloom, uv, 0.0).rgb;
+    let scene = textureLoad(t_hdr, vec2<i32>(position.xy), 0).rgb;
+    let hdr = (scene + bloom * tonemap.bloom_intensity) * tonemap.exposure;
+
+    var color: vec3<f32>;
+    switch tonemap.tonemapping {
+        case TONEMAPPING_ACES: {
+            color = aces(hdr);
+        }
+        case TONEMAPPING_REINHARD: {
+            color = reinhard(hdr);
+        }
+        default: {
+            color = agx(hdr);
+        }
+    }
+    color = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+
+    if tonemap.encode_srgb != 0u {
+        color = linear_to_srgb(color);
+    }
+    return vec4<f32>(color, 1.0);
+}
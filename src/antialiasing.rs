@@ -0,0 +1,545 @@
+use bevy_math::Vec2;
+use wgpu::util::DeviceExt;
+
+use crate::shader;
+use crate::tonemap::HDR_FORMAT;
+
+/// Format of the motion vectors the scene writes next to its color, the movement of
+/// each pixel since the previous frame in UV units.
+pub const MOTION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Float;
+
+/// Jitter positions TAA cycles through before repeating.
+const JITTER_SAMPLES: u32 = 8;
+/// Weight of the current frame in the TAA history, lower is smoother but slower to react.
+const TAA_CURRENT_WEIGHT: f32 = 0.1;
+
+/// Post-process anti-aliasing, on top of or instead of MSAA.
+///
+/// Unlike MSAA both also smooth aliasing inside of surfaces, like flickering specular
+/// highlights, which MSAA only shades once per pixel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AntiAliasing {
+    #[default]
+    None,
+    /// Blurs along the edges found in the tonemapped image, cheap but softens textures.
+    Fxaa,
+    /// Jitters the projection every frame and accumulates the frames in a history,
+    /// reprojected with motion vectors.
+    Taa,
+}
+
+/// Target the scene writes its motion vectors into, resolved when MSAA is enabled.
+pub(crate) fn create_motion_texture(
+    device: &wgpu::Device,
+    size: wgpu::Extent3d,
+) -> wgpu::TextureView {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Motion Texture"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: MOTION_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+/// Point `index` of the Halton low discrepancy sequence with the given base, in `0..1`.
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TaaUniform {
+    current_weight: f32,
+    _padding: [u32; 3],
+}
+
+/// Temporal anti-aliasing of the HDR target, before bloom and tonemapping.
+///
+/// The result of each frame becomes the history of the next, the two history
+/// textures take turns being read and written.
+pub(crate) struct Taa {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+    history: [wgpu::TextureView; 2],
+    /// Bind group `i` reads history `i` and is drawn into the other one.
+    bind_groups: [wgpu::BindGroup; 2],
+    /// History the next frame reads from.
+    current: usize,
+    frame: u32,
+    /// Cleared when the history no longer matches the scene, e.g. after a resize.
+    history_valid: bool,
+}
+
+impl Taa {
+    pub fn new(
+        device: &wgpu::Device,
+        hdr_view: &wgpu::TextureView,
+        motion_view: &wgpu::TextureView,
+        size: wgpu::Extent3d,
+    ) -> Self {
+        let texture_entry = |binding, filterable| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("TAA Bind Group Layout"),
+            entries: &[
+                texture_entry(0, false),
+                texture_entry(1, true),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                texture_entry(3, false),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(size_of::<TaaUniform>() as u64),
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("TAA Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("TAA Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                shader::with_preludes(
+                    &[shader::FULLSCREEN],
+                    include_str!("../assets/shaders/taa.wgsl"),
+                )
+                .into(),
+            ),
+        });
+        let pipeline =
+            create_fullscreen_pipeline(device, "TAA Pipeline", &layout, &shader, HDR_FORMAT);
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("TAA History Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("TAA Buffer"),
+            contents: bytemuck::bytes_of(&TaaUniform {
+                current_weight: 1.0,
+                _padding: [0; 3],
+            }),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let history = Self::create_history(device, size);
+        let bind_groups = Self::create_bind_groups(
+            device,
+            &bind_group_layout,
+            &sampler,
+            &uniform_buffer,
+            &history,
+            hdr_view,
+            motion_view,
+        );
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            sampler,
+            uniform_buffer,
+            history,
+            bind_groups,
+            current: 0,
+            frame: 0,
+            history_valid: false,
+        }
+    }
+
+    fn create_history(device: &wgpu::Device, size: wgpu::Extent3d) -> [wgpu::TextureView; 2] {
+        [0, 1].map(|_| {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("TAA History Texture"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: HDR_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            });
+            texture.create_view(&wgpu::TextureViewDescriptor::default())
+        })
+    }
+
+    fn create_bind_groups(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        uniform_buffer: &wgpu::Buffer,
+        history: &[wgpu::TextureView; 2],
+        hdr_view: &wgpu::TextureView,
+        motion_view: &wgpu::TextureView,
+    ) -> [wgpu::BindGroup; 2] {
+        history.each_ref().map(|history_view| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("TAA Bind Group"),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(hdr_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(history_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(motion_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: uniform_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        })
+    }
+
+    /// Recreates the history for targets of a new size, the old one is dropped.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        hdr_view: &wgpu::TextureView,
+        motion_view: &wgpu::TextureView,
+        size: wgpu::Extent3d,
+    ) {
+        self.history = Self::create_history(device, size);
+        self.bind_groups = Self::create_bind_groups(
+            device,
+            &self.bind_group_layout,
+            &self.sampler,
+            &self.uniform_buffer,
+            &self.history,
+            hdr_view,
+            motion_view,
+        );
+        self.history_valid = false;
+    }
+
+    /// Drops the history, so the next frame starts over from the current one alone.
+    pub fn invalidate(&mut self) {
+        self.history_valid = false;
+    }
+
+    /// Advances to the next frame and returns the sub-pixel jitter in NDC that its
+    /// projection has to be shifted by, for a target of `size`.
+    pub fn prepare(&mut self, queue: &wgpu::Queue, size: wgpu::Extent3d) -> Vec2 {
+        let uniform = TaaUniform {
+            current_weight: if self.history_valid {
+                TAA_CURRENT_WEIGHT
+            } else {
+                1.0
+            },
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+        self.history_valid = true;
+        self.current = 1 - self.current;
+
+        // The sequence starts at 1, its first point is the pixel corner
+        self.frame = (self.frame + 1) % JITTER_SAMPLES;
+        let offset = Vec2::new(halton(self.frame + 1, 2), halton(self.frame + 1, 3)) - 0.5;
+        offset * 2.0 / Vec2::new(size.width as f32, size.height as f32)
+    }
+
+    /// History the current frame is resolved into, which the passes after TAA read
+    /// instead of the HDR target. Changes with every [`Taa::prepare`].
+    pub fn output(&self) -> &wgpu::TextureView {
+        &self.history[1 - self.current]
+    }
+
+    /// Records the TAA pass into [`Taa::output`].
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("TAA Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: self.output(),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_groups[self.current], &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FxaaUniform {
+    linear_input: u32,
+    _padding: [u32; 3],
+}
+
+/// Fast approximate anti-aliasing of the tonemapped image.
+///
+/// The tonemapper draws into [`Fxaa::input`] instead of the output view, which
+/// has the same format as the output so the tonemapper pipeline works for both.
+pub(crate) struct Fxaa {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+    format: wgpu::TextureFormat,
+    input: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Fxaa {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, size: wgpu::Extent3d) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("FXAA Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(size_of::<FxaaUniform>() as u64),
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("FXAA Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("FXAA Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                shader::with_preludes(
+                    &[shader::FULLSCREEN],
+                    include_str!("../assets/shaders/fxaa.wgsl"),
+                )
+                .into(),
+            ),
+        });
+        let pipeline =
+            create_fullscreen_pipeline(device, "FXAA Pipeline", &layout, &shader, format);
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("FXAA Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("FXAA Buffer"),
+            contents: bytemuck::bytes_of(&FxaaUniform {
+                linear_input: u32::from(format.is_srgb()),
+                _padding: [0; 3],
+            }),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let input = Self::create_input(device, format, size);
+        let bind_group = Self::create_bind_group(
+            device,
+            &bind_group_layout,
+            &sampler,
+            &uniform_buffer,
+            &input,
+        );
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            sampler,
+            uniform_buffer,
+            format,
+            input,
+            bind_group,
+        }
+    }
+
+    fn create_input(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        size: wgpu::Extent3d,
+    ) -> wgpu::TextureView {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("FXAA Input Texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        texture.create_view(&wgpu::TextureViewDescriptor::default())
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        uniform_buffer: &wgpu::Buffer,
+        input: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("FXAA Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(input),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, size: wgpu::Extent3d) {
+        self.input = Self::create_input(device, self.format, size);
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.sampler,
+            &self.uniform_buffer,
+            &self.input,
+        );
+    }
+
+    /// Target the tonemapper draws into while FXAA is enabled.
+    pub fn input(&self) -> &wgpu::TextureView {
+        &self.input
+    }
+
+    /// Records the FXAA pass from [`Fxaa::input`] into `view`.
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("FXAA Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+/// Pipeline drawing the single triangle of `vs_main` with `fs_main` into a `format` target.
+fn create_fullscreen_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some("vs_main"),
+            compilation_options: Default::default(),
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: Some("fs_main"),
+            compilation_options: Default::default(),
+            targets: &[Some(format.into())],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
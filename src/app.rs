@@ -0,0 +1,311 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use winit::{
+    application::ApplicationHandler,
+    dpi::PhysicalSize,
+    event::{ElementState, KeyEvent, WindowEvent},
+    event_loop::ActiveEventLoop,
+    keyboard::{KeyCode, PhysicalKey},
+    window::{Fullscreen, Window, WindowAttributes},
+};
+
+use crate::{
+    bloom::BloomSettings,
+    camera::CameraController,
+    renderer::{self, Renderer, RendererConfig},
+};
+
+/// How the window is placed on screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowMode {
+    Windowed,
+    BorderlessFullscreen,
+}
+
+/// Startup options for [`crate::run`].
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    pub title: String,
+    pub window_mode: WindowMode,
+    /// Inner size of the window in physical pixels, the platform default when `None`.
+    pub resolution: Option<(u32, u32)>,
+    /// Preferred present mode, falls back to `Fifo` when the surface does not support it.
+    pub present_mode: wgpu::PresentMode,
+    pub backends: wgpu::Backends,
+    pub renderer: RendererConfig,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            title: "Hello WGPU!".to_string(),
+            window_mode: WindowMode::BorderlessFullscreen,
+            resolution: None,
+            present_mode: wgpu::PresentMode::Mailbox,
+            backends: wgpu::Backends::all(),
+            renderer: RendererConfig::default(),
+        }
+    }
+}
+
+/// Presents a [`Renderer`] to a winit window and feeds it input.
+struct State {
+    window: Arc<Window>,
+    surface: wgpu::Surface<'static>,
+    surface_config: wgpu::SurfaceConfiguration,
+    renderer: Renderer,
+    camera_controller: CameraController,
+    /// Restored when bloom is toggled back on.
+    bloom: BloomSettings,
+}
+
+impl State {
+    pub async fn new(window: Arc<Window>, config: &AppConfig) -> anyhow::Result<Self> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: config.backends,
+            ..Default::default()
+        });
+        let surface = instance.create_surface(window.clone())?;
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                compatible_surface: Some(&surface),
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                force_fallback_adapter: false,
+            })
+            .await
+            .with_context(|| format!("No adapter found for backends {:?}", config.backends))?;
+
+        let (device, queue) = renderer::request_device(&adapter).await?;
+
+        let surface_capabilities = surface.get_capabilities(&adapter);
+        let surface_format = surface_capabilities
+            .formats
+            .iter()
+            .copied()
+            .find(|f| f.is_srgb())
+            .unwrap_or(surface_capabilities.formats[0]);
+
+        let renderer_config = RendererConfig {
+            sample_count: renderer::select_sample_count(&adapter, config.renderer.sample_count),
+            ..config.renderer.clone()
+        };
+
+        // The automatic modes are resolved by wgpu itself and are always available
+        let present_mode = match config.present_mode {
+            mode @ (wgpu::PresentMode::AutoVsync | wgpu::PresentMode::AutoNoVsync) => mode,
+            mode if surface_capabilities.present_modes.contains(&mode) => mode,
+            mode => {
+                log::warn!("Present mode {mode:?} is not supported, falling back to Fifo");
+                wgpu::PresentMode::Fifo
+            }
+        };
+
+        let size = window.inner_size();
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: surface_format,
+            width: size.width,
+            height: size.height,
+            present_mode,
+            desired_maximum_frame_latency: 2,
+            alpha_mode: surface_capabilities.alpha_modes[0],
+            view_formats: vec![],
+        };
+
+        let renderer = Renderer::new(
+            device,
+            queue,
+            surface_format,
+            surface_config.width,
+            surface_config.height,
+            &renderer_config,
+        )?;
+
+        Ok(Self {
+            window,
+            surface,
+            surface_config,
+            renderer,
+            camera_controller: CameraController::new(4.0, 0.8),
+            bloom: config.renderer.bloom.unwrap_or_default(),
+        })
+    }
+
+    fn resize(&mut self, size: winit::dpi::PhysicalSize<u32>) {
+        // Minimized windows report a zero size, which no surface or attachment can have
+        if size.width == 0 || size.height == 0 {
+            return;
+        }
+        self.surface_config.width = size.width;
+        self.surface_config.height = size.height;
+        self.surface
+            .configure(self.renderer.device(), &self.surface_config);
+        self.renderer.resize(size.width, size.height);
+    }
+
+    fn toggle_bloom(&mut self) {
+        let bloom = match self.renderer.bloom() {
+            Some(settings) => {
+                self.bloom = settings;
+                None
+            }
+            None => Some(self.bloom),
+        };
+        self.renderer.set_bloom(bloom);
+    }
+
+    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        let output = self.surface.get_current_texture()?;
+
+        let view = output
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        self.renderer.render(&view);
+        output.present();
+
+        Ok(())
+    }
+}
+
+pub(crate) struct Application {
+    config: AppConfig,
+    state: Option<State>,
+    /// Set when startup fails so [`crate::run`] can report it after the loop exits.
+    pub(crate) error: Option<anyhow::Error>,
+    last_update: std::time::Instant,
+}
+
+impl Application {
+    pub(crate) fn new(config: AppConfig) -> Self {
+        Self {
+            config,
+            state: None,
+            error: None,
+            last_update: std::time::Instant::now(),
+        }
+    }
+
+    fn create_state(&self, event_loop: &ActiveEventLoop) -> anyhow::Result<State> {
+        let mut attributes = WindowAttributes::default().with_title(self.config.title.as_str());
+        if let Some((width, height)) = self.config.resolution {
+            attributes = attributes.with_inner_size(PhysicalSize::new(width, height));
+        }
+        let window = Arc::new(event_loop.create_window(attributes)?);
+
+        window.set_cursor_visible(false);
+        if self.config.window_mode == WindowMode::BorderlessFullscreen {
+            window.set_fullscreen(Some(Fullscreen::Borderless(window.current_monitor())));
+        }
+
+        if let Err(err) = window.set_cursor_grab(winit::window::CursorGrabMode::Confined) {
+            log::warn!("Could not confine the cursor: {err}");
+        }
+
+        pollster::block_on(State::new(window, &self.config))
+    }
+}
+
+impl ApplicationHandler for Application {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        match self.create_state(event_loop) {
+            Ok(state) => self.state = Some(state),
+            Err(err) => {
+                self.error = Some(err);
+                event_loop.exit();
+            }
+        }
+    }
+
+    fn window_event(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+        _window_id: winit::window::WindowId,
+        event: winit::event::WindowEvent,
+    ) {
+        if let Some(state) = &mut self.state {
+            match event {
+                WindowEvent::CloseRequested
+                | WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            physical_key: PhysicalKey::Code(KeyCode::Escape),
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                } => {
+                    event_loop.exit();
+                }
+                WindowEvent::RedrawRequested => {
+                    let now = std::time::Instant::now();
+                    let dt = now - self.last_update;
+                    state
+                        .camera_controller
+                        .update_camera(state.renderer.camera_mut(), dt);
+                    state.renderer.update(dt);
+                    self.last_update = now;
+                    match state.render() {
+                        Ok(_) => (),
+                        Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+                            state.resize(state.window.inner_size());
+                        }
+                        // The system is out of memory, we should probably quit
+                        Err(wgpu::SurfaceError::OutOfMemory | wgpu::SurfaceError::Other) => {
+                            log::error!("OutOfMemory");
+                            event_loop.exit();
+                        }
+
+                        // This happens when the a frame takes too long to present
+                        Err(wgpu::SurfaceError::Timeout) => {
+                            log::warn!("Surface timeout")
+                        }
+                    }
+                    state.window.request_redraw();
+                }
+                WindowEvent::Resized(size) => {
+                    state.resize(size);
+                }
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            physical_key: PhysicalKey::Code(KeyCode::KeyB),
+                            state: ElementState::Pressed,
+                            repeat: false,
+                            ..
+                        },
+                    ..
+                } => state.toggle_bloom(),
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            physical_key: PhysicalKey::Code(key),
+                            state: key_state,
+                            ..
+                        },
+                    ..
+                } => state.camera_controller.process_keyboard(key, key_state),
+                _ => (),
+            }
+        }
+    }
+
+    fn device_event(
+        &mut self,
+        _event_loop: &ActiveEventLoop,
+        _device_id: winit::event::DeviceId,
+        event: winit::event::DeviceEvent,
+    ) {
+        if let Some(state) = &mut self.state {
+            match event {
+                winit::event::DeviceEvent::MouseMotion { delta } => {
+                    state.camera_controller.process_mouse(delta.0, delta.1);
+                }
+                winit::event::DeviceEvent::MouseWheel { delta } => {
+                    state.camera_controller.process_scroll(&delta);
+                }
+                _ => (),
+            }
+        }
+    }
+}
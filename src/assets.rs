@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+
+use anyhow::Context;
+
+use crate::{
+    environment::Environment,
+    model::{Model, ModelMaterial},
+    texture::{MipmapGenerator, SamplerDesc, Texture, TextureRole},
+};
+
+/// Shared reference to a loaded asset.
+///
+/// Cloning a handle is cheap, the GPU resources of the asset are freed once
+/// the last handle to it is dropped.
+#[derive(Debug)]
+pub struct Handle<T>(Arc<T>);
+
+impl<T> Handle<T> {
+    /// Wraps an asset that is not loaded from a file and therefore not cached.
+    pub fn new(asset: T) -> Self {
+        Self(Arc::new(asset))
+    }
+
+    /// Whether both handles refer to the same asset.
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        Arc::ptr_eq(&this.0, &other.0)
+    }
+
+    /// Number of handles currently referring to this asset.
+    pub fn handle_count(this: &Self) -> usize {
+        Arc::strong_count(&this.0)
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<T> std::ops::Deref for Handle<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+/// Loads models and textures, handing out the existing asset when the same
+/// file is requested again.
+///
+/// Files are identified by their canonical path. The cache only keeps weak
+/// references, so it never keeps an asset alive on its own.
+pub struct AssetServer {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    material_layout: wgpu::BindGroupLayout,
+    mipmaps: MipmapGenerator,
+    anisotropy: u16,
+    samplers: HashMap<SamplerDesc, wgpu::Sampler>,
+    models: HashMap<PathBuf, Weak<Model>>,
+    /// Keyed by role as well, the same file is uploaded once as sRGB and once as linear.
+    textures: HashMap<(PathBuf, TextureRole), Weak<Texture>>,
+}
+
+impl AssetServer {
+    /// Material samplers use up to `anisotropy` samples, 1 disables anisotropic filtering.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, anisotropy: u16) -> Self {
+        Self {
+            device: device.clone(),
+            queue: queue.clone(),
+            material_layout: ModelMaterial::bind_group_layout(device),
+            mipmaps: MipmapGenerator::new(device),
+            anisotropy,
+            samplers: HashMap::new(),
+            models: HashMap::new(),
+            textures: HashMap::new(),
+        }
+    }
+
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    /// Layout of the bind group every [`ModelMaterial`] is created with.
+    pub fn material_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.material_layout
+    }
+
+    /// Loads a glTF/GLB or OBJ model, see [`Model::load`].
+    pub fn load_model<P: AsRef<Path>>(&mut self, path: P) -> anyhow::Result<Handle<Model>> {
+        let path = canonicalize(path.as_ref())?;
+        if let Some(model) = get(&self.models, &path) {
+            return Ok(model);
+        }
+
+        let model = Handle::new(Model::load(&path, self)?);
+        insert(&mut self.models, path, &model);
+
+        Ok(model)
+    }
+
+    /// Loads a PNG or JPEG image converted to RGBA8, or a KTX2 container that
+    /// keeps its compressed format where the device supports it, see
+    /// [`Texture::from_ktx2`].
+    pub fn load_texture<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        role: TextureRole,
+    ) -> anyhow::Result<Handle<Texture>> {
+        let path = canonicalize(path.as_ref())?;
+        self.load_texture_with(&path, role, |assets| {
+            let is_ktx2 = path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("ktx2"));
+            if is_ktx2 {
+                let bytes = std::fs::read(&path)?;
+                return assets.create_ktx2_texture(&bytes, role, path.to_str());
+            }
+
+            let image = image::open(&path)?.to_rgba8();
+            let size = wgpu::Extent3d {
+                width: image.width(),
+                height: image.height(),
+                depth_or_array_layers: 1,
+            };
+
+            assets.create_texture(size, &image, role, path.to_str())
+        })
+        .with_context(|| format!("Failed to load texture {}", path.display()))
+    }
+
+    /// Returns the cached texture for `path`, or creates it with `create` when
+    /// it is not loaded yet. Used for images that were already decoded by a
+    /// model loader.
+    pub(crate) fn load_texture_with(
+        &mut self,
+        path: &Path,
+        role: TextureRole,
+        create: impl FnOnce(&mut AssetServer) -> anyhow::Result<Texture>,
+    ) -> anyhow::Result<Handle<Texture>> {
+        let key = (canonicalize(path)?, role);
+        if let Some(texture) = get(&self.textures, &key) {
+            return Ok(texture);
+        }
+
+        let texture = Handle::new(create(self)?);
+        insert(&mut self.textures, key, &texture);
+
+        Ok(texture)
+    }
+
+    /// Uploads RGBA8 pixels as an uncached texture with a full mip chain.
+    pub fn create_texture(
+        &mut self,
+        size: wgpu::Extent3d,
+        bytes: &[u8],
+        role: TextureRole,
+        label: Option<&str>,
+    ) -> anyhow::Result<Texture> {
+        Texture::from_image_mipmapped(
+            &self.device,
+            &self.queue,
+            &mut self.mipmaps,
+            size,
+            bytes,
+            role,
+            label,
+        )
+    }
+
+    /// Uploads a KTX2 container as an uncached texture.
+    pub fn create_ktx2_texture(
+        &mut self,
+        bytes: &[u8],
+        role: TextureRole,
+        label: Option<&str>,
+    ) -> anyhow::Result<Texture> {
+        Texture::from_ktx2(
+            &self.device,
+            &self.queue,
+            &mut self.mipmaps,
+            bytes,
+            role,
+            label,
+        )
+    }
+
+    /// Loads an equirectangular `.hdr` or `.exr` image as an uncached environment cubemap.
+    pub fn load_environment<P: AsRef<Path>>(&mut self, path: P) -> anyhow::Result<Environment> {
+        Environment::load(&self.device, &self.queue, &mut self.mipmaps, path)
+    }
+
+    /// An environment with the same radiance in every direction.
+    pub fn create_constant_environment(&mut self, color: [f32; 3]) -> Environment {
+        Environment::from_color(&self.device, &self.queue, &mut self.mipmaps, color)
+    }
+
+    /// A sampler for material textures with the configured anisotropy, shared
+    /// by every texture using the same settings.
+    pub fn sampler(&mut self, desc: SamplerDesc) -> wgpu::Sampler {
+        self.samplers
+            .entry(desc)
+            .or_insert_with(|| desc.create(&self.device, self.anisotropy))
+            .clone()
+    }
+
+    /// Number of cached models that are still referenced by a handle.
+    pub fn loaded_models(&self) -> usize {
+        alive(&self.models)
+    }
+
+    /// Number of cached textures that are still referenced by a handle.
+    pub fn loaded_textures(&self) -> usize {
+        alive(&self.textures)
+    }
+}
+
+fn canonicalize(path: &Path) -> anyhow::Result<PathBuf> {
+    std::fs::canonicalize(path).with_context(|| format!("Failed to resolve {}", path.display()))
+}
+
+fn get<K: Eq + Hash, T>(cache: &HashMap<K, Weak<T>>, key: &K) -> Option<Handle<T>> {
+    cache.get(key).and_then(Weak::upgrade).map(Handle)
+}
+
+fn alive<K, T>(cache: &HashMap<K, Weak<T>>) -> usize {
+    cache
+        .values()
+        .filter(|weak| weak.strong_count() > 0)
+        .count()
+}
+
+fn insert<K: Eq + Hash, T>(cache: &mut HashMap<K, Weak<T>>, key: K, asset: &Handle<T>) {
+    // Drop entries of assets that were freed in the meantime
+    cache.retain(|_, weak| weak.strong_count() > 0);
+    cache.insert(key, Arc::downgrade(&asset.0));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_hits_while_a_handle_is_alive() {
+        let mut cache = HashMap::new();
+        let asset = Handle::new("asset");
+        insert(&mut cache, "key", &asset);
+
+        let cached = get(&cache, &"key").unwrap();
+        assert!(Handle::ptr_eq(&asset, &cached));
+        assert_eq!(Handle::handle_count(&asset), 2);
+        assert!(get(&cache, &"other").is_none());
+    }
+
+    #[test]
+    fn cache_misses_after_the_last_handle_is_dropped() {
+        let mut cache = HashMap::new();
+        let asset = Handle::new("asset");
+        let clone = asset.clone();
+        insert(&mut cache, "key", &asset);
+
+        drop(asset);
+        assert!(get(&cache, &"key").is_some());
+        assert_eq!(alive(&cache), 1);
+
+        drop(clone);
+        assert!(get(&cache, &"key").is_none());
+        assert_eq!(alive(&cache), 0);
+    }
+
+    #[test]
+    fn insert_replaces_and_prunes_freed_entries() {
+        let mut cache = HashMap::new();
+        insert(&mut cache, "freed", &Handle::new("freed"));
+        let first = Handle::new("first");
+        insert(&mut cache, "key", &first);
+        assert_eq!(cache.len(), 1);
+
+        // A reload after a drop caches the new asset under the same key
+        drop(first);
+        let second = Handle::new("second");
+        insert(&mut cache, "key", &second);
+        let cached = get(&cache, &"key").unwrap();
+        assert!(Handle::ptr_eq(&second, &cached));
+        assert_eq!(*cached, "second");
+    }
+}
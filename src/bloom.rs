@@ -0,0 +1,353 @@
+use wgpu::util::DeviceExt;
+
+use crate::shader;
+use crate::tonemap::HDR_FORMAT;
+
+/// Levels below the HDR resolution the bloom blurs down to, each one doubles its reach.
+const MAX_MIP_LEVELS: u32 = 8;
+/// Width of the soft transition below the threshold, relative to the threshold.
+const KNEE: f32 = 0.5;
+
+/// How much bright parts of the scene glow into their surroundings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BloomSettings {
+    /// Brightness in the HDR scene, before exposure, above which pixels start to glow.
+    pub threshold: f32,
+    /// Fraction of the blurred highlights added back onto the scene.
+    pub intensity: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            threshold: 1.0,
+            intensity: 0.3,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomUniform {
+    threshold: f32,
+    knee: f32,
+    _padding: [u32; 2],
+}
+
+/// Mip chain the highlights of the HDR target are blurred through.
+///
+/// Each level is downsampled from the one above it, starting at half the HDR
+/// resolution, then every level is upsampled and added onto the one above it again.
+/// Level 0 ends up holding the bloom that gets composited before tonemapping.
+pub(crate) struct Bloom {
+    prefilter_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+    upsample_pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+    uniform: BloomUniform,
+    mip_views: Vec<wgpu::TextureView>,
+    /// Sources of the downsample passes, the HDR target followed by every level but the last.
+    downsample_bind_groups: Vec<wgpu::BindGroup>,
+    /// Sources of the upsample passes, every level but the first.
+    upsample_bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl Bloom {
+    pub fn new(device: &wgpu::Device, hdr_view: &wgpu::TextureView, size: wgpu::Extent3d) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Bloom Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(size_of::<BloomUniform>() as u64),
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Bloom Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Bloom Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                shader::with_preludes(
+                    &[shader::FULLSCREEN],
+                    include_str!("../assets/shaders/bloom.wgsl"),
+                )
+                .into(),
+            ),
+        });
+        let create_pipeline = |label, entry_point, blend| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    compilation_options: Default::default(),
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some(entry_point),
+                    compilation_options: Default::default(),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: HDR_FORMAT,
+                        blend,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let prefilter_pipeline = create_pipeline("Bloom Prefilter Pipeline", "fs_prefilter", None);
+        let downsample_pipeline =
+            create_pipeline("Bloom Downsample Pipeline", "fs_downsample", None);
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let upsample_pipeline = create_pipeline(
+            "Bloom Upsample Pipeline",
+            "fs_upsample",
+            Some(wgpu::BlendState {
+                color: additive,
+                alpha: additive,
+            }),
+        );
+
+        // Clamping keeps the wide filters from pulling in the opposite edge
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Bloom Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let uniform = BloomUniform::from(BloomSettings::default());
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bloom Buffer"),
+            contents: bytemuck::bytes_of(&uniform),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let mut bloom = Self {
+            prefilter_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+            bind_group_layout,
+            sampler,
+            uniform_buffer,
+            uniform,
+            mip_views: Vec::new(),
+            downsample_bind_groups: Vec::new(),
+            upsample_bind_groups: Vec::new(),
+        };
+        bloom.resize(device, hdr_view, size);
+        bloom
+    }
+
+    /// Recreates the mip chain for an HDR target of a new size.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        hdr_view: &wgpu::TextureView,
+        size: wgpu::Extent3d,
+    ) {
+        let size = wgpu::Extent3d {
+            width: (size.width / 2).max(1),
+            height: (size.height / 2).max(1),
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Bloom Texture"),
+            size,
+            mip_level_count: size.width.min(size.height).ilog2().clamp(1, MAX_MIP_LEVELS),
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        self.mip_views = (0..texture.mip_level_count())
+            .map(|mip| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Bloom Mip View"),
+                    base_mip_level: mip,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        let sources = std::iter::once(hdr_view).chain(&self.mip_views);
+        self.downsample_bind_groups = sources
+            .take(self.mip_views.len())
+            .map(|view| self.create_bind_group(device, view))
+            .collect();
+        self.upsample_bind_groups = self.mip_views[1..]
+            .iter()
+            .map(|view| self.create_bind_group(device, view))
+            .collect();
+    }
+
+    /// Blurs `hdr_view` instead of the target given on resize, which has to be the same size.
+    pub fn set_input(&mut self, device: &wgpu::Device, hdr_view: &wgpu::TextureView) {
+        self.downsample_bind_groups[0] = self.create_bind_group(device, hdr_view);
+    }
+
+    fn create_bind_group(
+        &self,
+        device: &wgpu::Device,
+        view: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Bloom Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    /// The finished bloom, half the resolution of the HDR target.
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.mip_views[0]
+    }
+
+    pub fn sampler(&self) -> &wgpu::Sampler {
+        &self.sampler
+    }
+
+    /// Scale that turns the summed up levels in [`Bloom::view`] into `settings.intensity`.
+    pub fn composite_scale(&self, settings: &BloomSettings) -> f32 {
+        settings.intensity / self.mip_views.len() as f32
+    }
+
+    /// Uploads the threshold, only when it changed.
+    pub fn upload(&mut self, queue: &wgpu::Queue, settings: &BloomSettings) {
+        let uniform = BloomUniform::from(*settings);
+        if bytemuck::bytes_of(&uniform) != bytemuck::bytes_of(&self.uniform) {
+            self.uniform = uniform;
+            queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&uniform));
+        }
+    }
+
+    /// Records the passes down and back up the mip chain.
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder) {
+        for (mip, bind_group) in self.downsample_bind_groups.iter().enumerate() {
+            let pipeline = if mip == 0 {
+                &self.prefilter_pipeline
+            } else {
+                &self.downsample_pipeline
+            };
+            let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+            self.draw(
+                encoder,
+                "Bloom Downsample Pass",
+                mip,
+                clear,
+                pipeline,
+                bind_group,
+            );
+        }
+        for (mip, bind_group) in self.upsample_bind_groups.iter().enumerate().rev() {
+            let pipeline = &self.upsample_pipeline;
+            let load = wgpu::LoadOp::Load;
+            self.draw(
+                encoder,
+                "Bloom Upsample Pass",
+                mip,
+                load,
+                pipeline,
+                bind_group,
+            );
+        }
+    }
+
+    /// One fullscreen triangle into level `mip`.
+    fn draw(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        mip: usize,
+        load: wgpu::LoadOp<wgpu::Color>,
+        pipeline: &wgpu::RenderPipeline,
+        bind_group: &wgpu::BindGroup,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.mip_views[mip],
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+impl From<BloomSettings> for BloomUniform {
+    fn from(settings: BloomSettings) -> Self {
+        Self {
+            threshold: settings.threshold,
+            knee: settings.threshold * KNEE,
+            _padding: [0; 2],
+        }
+    }
+}
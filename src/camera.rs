@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use bevy_math::{Mat4, Vec3};
+use bevy_math::{Mat4, Vec2, Vec3};
 use winit::{
     dpi::PhysicalPosition,
     event::{ElementState, MouseScrollDelta},
@@ -143,11 +143,9 @@ impl CameraController {
         self.rotate_vertical = 0.0;
 
         // Keep the camera's angle from going too high/low.
-        if camera.pitch < -std::f32::consts::FRAC_PI_2 {
-            camera.pitch = -std::f32::consts::FRAC_PI_2;
-        } else if camera.pitch > std::f32::consts::FRAC_PI_2 {
-            camera.pitch = std::f32::consts::FRAC_PI_2;
-        }
+        camera.pitch = camera
+            .pitch
+            .clamp(-std::f32::consts::FRAC_PI_2, std::f32::consts::FRAC_PI_2);
     }
 }
 
@@ -156,6 +154,7 @@ pub struct Projection {
     fovy: f32,
     znear: f32,
     zfar: f32,
+    jitter: Vec2,
 }
 
 impl Projection {
@@ -165,6 +164,7 @@ impl Projection {
             fovy,
             znear,
             zfar,
+            jitter: Vec2::ZERO,
         }
     }
 
@@ -172,7 +172,35 @@ impl Projection {
         self.aspect = width as f32 / height as f32;
     }
 
+    pub fn aspect(&self) -> f32 {
+        self.aspect
+    }
+
+    /// Vertical field of view in radians.
+    pub fn fovy(&self) -> f32 {
+        self.fovy
+    }
+
+    pub fn znear(&self) -> f32 {
+        self.znear
+    }
+
+    pub fn zfar(&self) -> f32 {
+        self.zfar
+    }
+
+    /// Sub-pixel offset in NDC that [`Projection::to_mat4`] shifts the image by,
+    /// temporal anti-aliasing moves it every frame.
+    pub fn set_jitter(&mut self, jitter: Vec2) {
+        self.jitter = jitter;
+    }
+
     pub fn to_mat4(&self) -> Mat4 {
+        Mat4::from_translation(self.jitter.extend(0.0)) * self.to_unjittered_mat4()
+    }
+
+    /// The projection without the jitter, for motion vectors that only follow real movement.
+    pub fn to_unjittered_mat4(&self) -> Mat4 {
         Mat4::perspective_rh(self.fovy, self.aspect, self.znear, self.zfar)
     }
 }
@@ -0,0 +1,631 @@
+use std::path::Path;
+
+use anyhow::Context;
+use wgpu::util::DeviceExt;
+
+use crate::{antialiasing::MOTION_FORMAT, shader, texture::MipmapGenerator};
+
+/// Format of the environment cubemap, radiance does not fit into 8 bit channels.
+pub const ENVIRONMENT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// Face size of the irradiance cubemap, diffuse lighting varies slowly with the normal.
+const IRRADIANCE_SIZE: u32 = 32;
+/// Face size of the sharpest prefiltered specular mip.
+const PREFILTERED_SIZE: u32 = 128;
+/// Prefiltered mips, spread evenly from a roughness of 0 to 1.
+const PREFILTERED_MIP_LEVELS: u32 = 5;
+const BRDF_LUT_SIZE: u32 = 256;
+
+/// Radiance arriving from every direction, stored as a cubemap.
+pub struct Environment {
+    pub texture: wgpu::Texture,
+    /// Cube view over all six faces.
+    pub view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+}
+
+impl Environment {
+    /// Loads an equirectangular Radiance `.hdr` or OpenEXR image and projects it onto a cubemap.
+    pub fn load<P: AsRef<Path>>(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mipmaps: &mut MipmapGenerator,
+        path: P,
+    ) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let mut image = image::open(path)
+            .with_context(|| format!("Failed to load environment {}", path.display()))?
+            .into_rgba32f();
+
+        // Images larger than a texture can be are downscaled to fit
+        let (width, height) = image.dimensions();
+        let max_size = device.limits().max_texture_dimension_2d;
+        let largest = width.max(height);
+        if largest > max_size {
+            let scale = |size: u32| (size as u64 * max_size as u64 / largest as u64).max(1) as u32;
+            let (scaled_width, scaled_height) = (scale(width), scale(height));
+            log::warn!(
+                "Environment {} is {width}x{height}, downscaling it to {scaled_width}x{scaled_height} to fit the device",
+                path.display()
+            );
+            image = image::imageops::resize(
+                &image,
+                scaled_width,
+                scaled_height,
+                image::imageops::FilterType::Triangle,
+            );
+        }
+
+        // Radiance past the half float range would become infinite and turn
+        // the prefiltered mips into NaN
+        let max = half::f16::MAX.to_f32();
+        let (width, height) = image.dimensions();
+        let pixels = image
+            .into_raw()
+            .into_iter()
+            .map(|channel| half::f16::from_f32(channel.clamp(-max, max)).to_bits())
+            .collect::<Vec<_>>();
+
+        Self::from_equirect(
+            device,
+            queue,
+            mipmaps,
+            width,
+            height,
+            &pixels,
+            path.to_str(),
+        )
+    }
+
+    /// An environment with the same radiance in every direction.
+    pub fn from_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mipmaps: &mut MipmapGenerator,
+        color: [f32; 3],
+    ) -> Self {
+        let [r, g, b] = color.map(|channel| half::f16::from_f32(channel).to_bits());
+        let pixels = [r, g, b, half::f16::ONE.to_bits()].repeat(8);
+        Self::from_equirect(
+            device,
+            queue,
+            mipmaps,
+            4,
+            2,
+            &pixels,
+            Some("Constant Environment"),
+        )
+        .expect("a 4x2 image fits every device")
+    }
+
+    /// Projects an equirectangular image of RGBA half floats onto a cubemap on the GPU.
+    ///
+    /// The center of the image faces +X and its top +Z. Each face gets a
+    /// quarter of the image width, which keeps the resolution at the horizon.
+    /// Every mip level of the cubemap is projected from the matching mip of the image.
+    /// Fails when the image is larger than the device's texture size limit.
+    pub fn from_equirect(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mipmaps: &mut MipmapGenerator,
+        width: u32,
+        height: u32,
+        pixels: &[u16],
+        label: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        let max_size = device.limits().max_texture_dimension_2d;
+        anyhow::ensure!(
+            width <= max_size && height <= max_size,
+            "Equirectangular image of {width}x{height} exceeds the texture size limit of {max_size}"
+        );
+
+        let equirect_size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let equirect = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Equirectangular Environment"),
+            size: equirect_size,
+            mip_level_count: equirect_size.max_mips(wgpu::TextureDimension::D2),
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: ENVIRONMENT_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            equirect.as_image_copy(),
+            bytemuck::cast_slice(pixels),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(8 * width),
+                rows_per_image: Some(height),
+            },
+            equirect_size,
+        );
+        mipmaps.generate(device, queue, &equirect);
+
+        let face_size = (width / 4).clamp(16, device.limits().max_texture_dimension_2d);
+        let mip_level_count = face_size.ilog2() + 1;
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size: wgpu::Extent3d {
+                width: face_size,
+                height: face_size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: ENVIRONMENT_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+            view_formats: &[],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Equirect To Cube Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                include_str!("../assets/shaders/equirect_to_cube.wgsl").into(),
+            ),
+        });
+        let pipeline = create_compute_pipeline(device, &shader, "cs_main");
+
+        // Wrap around horizontally so the seam at the back is filtered too
+        let equirect_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Equirect Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let equirect_view = equirect.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_groups = (0..mip_level_count)
+            .map(|level| {
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Equirect To Cube Bind Group"),
+                    layout: &pipeline.get_bind_group_layout(0),
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&equirect_view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&equirect_sampler),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(&face_view(
+                                &texture, level,
+                            )),
+                        },
+                    ],
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Equirect To Cube Encoder"),
+        });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Equirect To Cube Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&pipeline);
+            for (level, bind_group) in bind_groups.iter().enumerate() {
+                compute_pass.set_bind_group(0, bind_group, &[]);
+                dispatch_faces(&mut compute_pass, (face_size >> level).max(1));
+            }
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Environment View"),
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Environment Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+        })
+    }
+}
+
+/// Precomputed maps to light surfaces by an [`Environment`], bound as a single bind group.
+///
+/// Diffuse light comes from the irradiance cubemap. Specular light follows the split sum
+/// approximation, the prefiltered cubemap holds the radiance blurred by the GGX lobe of
+/// increasing roughness per mip and the BRDF lookup table the scale and bias for F0.
+pub struct EnvironmentLighting {
+    pub irradiance: wgpu::Texture,
+    pub prefiltered: wgpu::Texture,
+    pub brdf_lut: wgpu::Texture,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl EnvironmentLighting {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, environment: &Environment) -> Self {
+        let cube_texture = |label, size, mip_level_count| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: size,
+                    height: size,
+                    depth_or_array_layers: 6,
+                },
+                mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: ENVIRONMENT_FORMAT,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+                view_formats: &[],
+            })
+        };
+        let irradiance = cube_texture("Irradiance", IRRADIANCE_SIZE, 1);
+        // Small environments have no detail for a larger sharpest mip
+        let prefiltered_size = PREFILTERED_SIZE.min(environment.texture.width());
+        let prefiltered_mip_levels = PREFILTERED_MIP_LEVELS.min(prefiltered_size.ilog2() + 1);
+        let prefiltered = cube_texture(
+            "Prefiltered Environment",
+            prefiltered_size,
+            prefiltered_mip_levels,
+        );
+        let brdf_lut = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("BRDF Lookup Table"),
+            size: wgpu::Extent3d {
+                width: BRDF_LUT_SIZE,
+                height: BRDF_LUT_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: ENVIRONMENT_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING,
+            view_formats: &[],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("IBL Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../assets/shaders/ibl.wgsl").into()),
+        });
+        let irradiance_pipeline = create_compute_pipeline(device, &shader, "cs_irradiance");
+        let prefilter_pipeline = create_compute_pipeline(device, &shader, "cs_prefilter");
+        let brdf_lut_pipeline = create_compute_pipeline(device, &shader, "cs_brdf_lut");
+
+        let irradiance_view = face_view(&irradiance, 0);
+        let irradiance_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Irradiance Bind Group"),
+            layout: &irradiance_pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&environment.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&environment.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&irradiance_view),
+                },
+            ],
+        });
+
+        let prefilter_bind_groups = (0..prefiltered_mip_levels)
+            .map(|level| {
+                let roughness = level as f32 / (prefiltered_mip_levels - 1).max(1) as f32;
+                let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Prefilter Buffer"),
+                    contents: bytemuck::cast_slice(&[roughness, 0.0, 0.0, 0.0]),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+                let view = face_view(&prefiltered, level);
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Prefilter Bind Group"),
+                    layout: &prefilter_pipeline.get_bind_group_layout(0),
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&environment.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::Sampler(&environment.sampler),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(&view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: buffer.as_entire_binding(),
+                        },
+                    ],
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let brdf_lut_view = brdf_lut.create_view(&wgpu::TextureViewDescriptor::default());
+        let brdf_lut_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("BRDF Lookup Table Bind Group"),
+            layout: &brdf_lut_pipeline.get_bind_group_layout(0),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::TextureView(&brdf_lut_view),
+            }],
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("IBL Encoder"),
+        });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("IBL Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&irradiance_pipeline);
+            compute_pass.set_bind_group(0, &irradiance_bind_group, &[]);
+            dispatch_faces(&mut compute_pass, IRRADIANCE_SIZE);
+
+            compute_pass.set_pipeline(&prefilter_pipeline);
+            for (level, bind_group) in prefilter_bind_groups.iter().enumerate() {
+                compute_pass.set_bind_group(0, bind_group, &[]);
+                dispatch_faces(&mut compute_pass, (prefiltered_size >> level).max(1));
+            }
+
+            compute_pass.set_pipeline(&brdf_lut_pipeline);
+            compute_pass.set_bind_group(0, &brdf_lut_bind_group, &[]);
+            let groups = BRDF_LUT_SIZE.div_ceil(8);
+            compute_pass.dispatch_workgroups(groups, groups, 1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let cube_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::Cube,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Environment Lighting Bind Group Layout"),
+            entries: &[
+                cube_entry(0),
+                cube_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let cube_view = |texture: &wgpu::Texture| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::Cube),
+                ..Default::default()
+            })
+        };
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Environment Lighting Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Environment Lighting Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&cube_view(&irradiance)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&cube_view(&prefiltered)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&brdf_lut_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        Self {
+            irradiance,
+            prefiltered,
+            brdf_lut,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+}
+
+fn create_compute_pipeline(
+    device: &wgpu::Device,
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+) -> wgpu::ComputePipeline {
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some(entry_point),
+        layout: None,
+        module: shader,
+        entry_point: Some(entry_point),
+        compilation_options: Default::default(),
+        cache: None,
+    })
+}
+
+/// All six faces of one mip level, for writing through a storage binding.
+fn face_view(texture: &wgpu::Texture, level: u32) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        label: Some("Cube Faces"),
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        base_mip_level: level,
+        mip_level_count: Some(1),
+        ..Default::default()
+    })
+}
+
+/// Runs the bound 8x8 workgroup shader once for every texel of six faces of `size` texels.
+fn dispatch_faces(compute_pass: &mut wgpu::ComputePass, size: u32) {
+    let groups = size.div_ceil(8);
+    compute_pass.dispatch_workgroups(groups, groups, 6);
+}
+
+/// Draws an [`Environment`] at the far plane, in place of the clear color.
+///
+/// It is drawn after the opaque geometry so that covered pixels fail the depth test.
+/// Like the other scene pipelines it also writes motion vectors into a second
+/// [`MOTION_FORMAT`] target.
+pub struct Skybox {
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Skybox {
+    pub fn new(
+        device: &wgpu::Device,
+        environment: &Environment,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Skybox Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Skybox Bind Group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&environment.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&environment.sampler),
+                },
+            ],
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skybox Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Skybox Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                shader::with_preludes(
+                    &[shader::MOTION],
+                    include_str!("../assets/shaders/skybox.wgsl"),
+                )
+                .into(),
+            ),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Skybox Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                targets: &[Some(color_format.into()), Some(MOTION_FORMAT.into())],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            // The sky sits exactly on the cleared depth, so it needs to pass on equal
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: depth_format,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            pipeline,
+            bind_group,
+        }
+    }
+
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,152 @@
+use anyhow::Context;
+
+use crate::renderer::{self, Renderer, RendererConfig};
+
+const HEADLESS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// A [`Renderer`] without a window that draws into an offscreen `Rgba8UnormSrgb`
+/// texture, so frames can be read back with [`HeadlessRenderer::render_to_image`].
+pub struct HeadlessRenderer {
+    renderer: Renderer,
+    texture: wgpu::Texture,
+}
+
+impl HeadlessRenderer {
+    pub async fn new(
+        width: u32,
+        height: u32,
+        force_fallback_adapter: bool,
+        config: &RendererConfig,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(
+            width > 0 && height > 0,
+            "Headless render target must not be empty, got {width}x{height}"
+        );
+
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                compatible_surface: None,
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                force_fallback_adapter,
+            })
+            .await
+            .context("No suitable adapter found for headless rendering")?;
+
+        let config = RendererConfig {
+            sample_count: renderer::select_sample_count(&adapter, config.sample_count),
+            ..config.clone()
+        };
+
+        let (device, queue) = renderer::request_device(&adapter).await?;
+        let texture = create_target(&device, width, height);
+        let renderer = Renderer::new(device, queue, HEADLESS_FORMAT, width, height, &config)?;
+
+        Ok(Self { renderer, texture })
+    }
+
+    pub fn renderer(&self) -> &Renderer {
+        &self.renderer
+    }
+
+    pub fn renderer_mut(&mut self) -> &mut Renderer {
+        &mut self.renderer
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            width > 0 && height > 0,
+            "Headless render target must not be empty, got {width}x{height}"
+        );
+        self.texture = create_target(self.renderer.device(), width, height);
+        self.renderer.resize(width, height);
+
+        Ok(())
+    }
+
+    /// Renders a single frame into the headless target and copies it back to the CPU.
+    pub fn render_to_image(&self) -> anyhow::Result<image::RgbaImage> {
+        let device = self.renderer.device();
+        let size = self.renderer.size();
+        let view = self
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Headless Render Encoder"),
+        });
+        self.renderer.encode(&mut encoder, &view);
+
+        // Rows in a texture to buffer copy have to be aligned, the padding is stripped below
+        let unpadded_bytes_per_row = 4 * size.width;
+        let padded_bytes_per_row = unpadded_bytes_per_row
+            .div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: (padded_bytes_per_row * size.height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                aspect: wgpu::TextureAspect::All,
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &readback_buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(size.height),
+                },
+            },
+            size,
+        );
+
+        self.renderer
+            .queue()
+            .submit(std::iter::once(encoder.finish()));
+
+        let buffer_slice = readback_buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+
+        let pixels = {
+            let data = buffer_slice.get_mapped_range();
+            data.chunks(padded_bytes_per_row as usize)
+                .flat_map(|row| &row[..unpadded_bytes_per_row as usize])
+                .copied()
+                .collect::<Vec<_>>()
+        };
+        readback_buffer.unmap();
+
+        image::RgbaImage::from_raw(size.width, size.height, pixels)
+            .context("Readback buffer does not match the target size")
+    }
+}
+
+fn create_target(device: &wgpu::Device, width: u32, height: u32) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Headless Target"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: HEADLESS_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    })
+}
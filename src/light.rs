@@ -0,0 +1,537 @@
+use bevy_math::{Mat4, Vec3};
+
+use crate::{
+    camera::{Camera, Projection},
+    renderer::DebugView,
+    scene::{LightDesc, LightKind},
+    shadow::{ShadowMaps, ShadowSettings},
+};
+
+/// Lights the buffer has room for before it is first grown.
+const INITIAL_CAPACITY: usize = 16;
+/// Clusters along the screen width, height and the depth between the near and far plane.
+const CLUSTER_GRID: [u32; 3] = [16, 9, 24];
+/// Lights beyond this are dropped from a cluster, keep in sync with `cluster.wgsl`.
+const MAX_LIGHTS_PER_CLUSTER: u32 = 128;
+/// Irradiance where lights without a range are cut off.
+const MIN_IRRADIANCE: f32 = 0.002;
+
+/// A punctual light, following the conventions of `KHR_lights_punctual`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    /// Ignored by directional lights.
+    pub position: Vec3,
+    /// Direction the light shines in, ignored by point lights.
+    pub direction: Vec3,
+    /// Linear RGB color, scaled by `intensity`.
+    pub color: Vec3,
+    /// Candela for point and spot lights, lux for directional lights.
+    pub intensity: f32,
+    /// Distance at which the light has faded out completely, `None` derives it
+    /// from where the irradiance drops below a barely visible level.
+    pub range: Option<f32>,
+    /// Angle from the spot direction in radians where the falloff starts.
+    pub inner_cone_angle: f32,
+    /// Angle from the spot direction in radians where the light ends.
+    pub outer_cone_angle: f32,
+    /// Renders shadow maps, cascades for directional lights and a cube for point lights.
+    pub shadow: Option<ShadowSettings>,
+}
+
+impl Light {
+    pub fn point(position: Vec3, color: Vec3, intensity: f32) -> Self {
+        Self {
+            kind: LightKind::Point,
+            position,
+            direction: Vec3::NEG_Z,
+            color,
+            intensity,
+            range: None,
+            inner_cone_angle: 0.0,
+            outer_cone_angle: std::f32::consts::FRAC_PI_4,
+            shadow: None,
+        }
+    }
+
+    pub fn directional(direction: Vec3, color: Vec3, intensity: f32) -> Self {
+        Self {
+            kind: LightKind::Directional,
+            direction,
+            ..Self::point(Vec3::ZERO, color, intensity)
+        }
+    }
+
+    pub fn spot(
+        position: Vec3,
+        direction: Vec3,
+        color: Vec3,
+        intensity: f32,
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    ) -> Self {
+        Self {
+            kind: LightKind::Spot,
+            direction,
+            inner_cone_angle,
+            outer_cone_angle,
+            ..Self::point(position, color, intensity)
+        }
+    }
+
+    /// Distance the light reaches, zero for directional lights which reach everywhere.
+    pub(crate) fn range(&self) -> f32 {
+        match self.kind {
+            LightKind::Directional => 0.0,
+            _ => self
+                .range
+                .unwrap_or_else(|| {
+                    (self.color.max_element() * self.intensity / MIN_IRRADIANCE).sqrt()
+                })
+                .max(1e-3),
+        }
+    }
+
+    fn to_raw(self, shadow: i32) -> LightRaw {
+        // Precomputed so the shader only needs a multiply add to fade between the cones
+        let cos_inner = self.inner_cone_angle.cos();
+        let cos_outer = self.outer_cone_angle.cos();
+        let spot_scale = 1.0 / (cos_inner - cos_outer).max(1e-3);
+
+        LightRaw {
+            position: self.position.into(),
+            kind: match self.kind {
+                LightKind::Point => 0,
+                LightKind::Directional => 1,
+                LightKind::Spot => 2,
+            },
+            direction: self.direction.normalize_or(Vec3::NEG_Z).into(),
+            // A finite range lets the light be culled from clusters it cannot reach
+            range: self.range(),
+            color: (self.color * self.intensity).into(),
+            spot_scale,
+            spot_offset: -cos_outer * spot_scale,
+            shadow,
+            _padding: [0; 2],
+        }
+    }
+}
+
+impl From<&LightDesc> for Light {
+    fn from(desc: &LightDesc) -> Self {
+        Self {
+            kind: desc.kind,
+            position: desc.position.into(),
+            direction: desc.direction.into(),
+            color: desc.color.into(),
+            intensity: desc.intensity,
+            range: desc.range,
+            inner_cone_angle: desc.inner_cone_angle.to_radians(),
+            outer_cone_angle: desc.outer_cone_angle.to_radians(),
+            shadow: desc.shadow.as_ref().map(ShadowSettings::from),
+        }
+    }
+}
+
+/// Identifies a light added to the renderer, stays valid until the light is removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LightId(u64);
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightRaw {
+    position: [f32; 3],
+    kind: u32,
+    direction: [f32; 3],
+    /// Zero for directional lights, which reach everywhere.
+    range: f32,
+    /// Color premultiplied by the intensity.
+    color: [f32; 3],
+    spot_scale: f32,
+    spot_offset: f32,
+    /// Index into the shadow data, -1 when the light casts no shadow.
+    shadow: i32,
+    _padding: [u32; 2],
+}
+
+/// Light count in front of the array, padded to the alignment of [`LightRaw`].
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightsHeader {
+    count: u32,
+    _padding: [u32; 3],
+}
+
+/// What the culling and shading need to know to map positions onto clusters.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct ClusterUniform {
+    view: Mat4,
+    grid_size: [u32; 3],
+    debug_view: u32,
+    screen_size: [f32; 2],
+    znear: f32,
+    zfar: f32,
+    tan_half_fovy: f32,
+    aspect: f32,
+    _padding: [u32; 2],
+}
+
+/// Light lists per cluster, written by the culling pass and read when shading.
+struct ClusterBuffers {
+    uniform: wgpu::Buffer,
+    light_counts: wgpu::Buffer,
+    light_indices: wgpu::Buffer,
+}
+
+/// Every light in the scene, mirrored into a storage buffer that grows as lights are added.
+///
+/// Each frame a compute pass bins the lights into a grid of view space clusters,
+/// so shading only loops over the lights reaching the cluster of a fragment.
+pub(crate) struct Lights {
+    lights: Vec<(LightId, Light)>,
+    next_id: u64,
+    dirty: bool,
+    capacity: usize,
+    buffer: wgpu::Buffer,
+    shadows: ShadowMaps,
+    clusters: ClusterBuffers,
+    cluster_uniform: ClusterUniform,
+    cull_pipeline: wgpu::ComputePipeline,
+    cull_bind_group: wgpu::BindGroup,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Lights {
+    /// `vertex_layouts` describe the model vertex and instance buffers the shadow maps are drawn with.
+    pub fn new(device: &wgpu::Device, vertex_layouts: &[wgpu::VertexBufferLayout]) -> Self {
+        let storage_entry = |binding, visibility| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Lights Bind Group Layout"),
+            entries: &[
+                storage_entry(0, wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(2, wgpu::ShaderStages::FRAGMENT),
+                storage_entry(3, wgpu::ShaderStages::FRAGMENT),
+                storage_entry(4, wgpu::ShaderStages::FRAGMENT),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Depth,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+            ],
+        });
+
+        let cluster_count = CLUSTER_GRID.iter().product::<u32>() as u64;
+        let clusters = ClusterBuffers {
+            uniform: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Cluster Uniform Buffer"),
+                size: size_of::<ClusterUniform>() as u64,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            light_counts: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Cluster Light Counts"),
+                size: cluster_count * size_of::<u32>() as u64,
+                usage: wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            }),
+            light_indices: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Cluster Light Indices"),
+                size: cluster_count * MAX_LIGHTS_PER_CLUSTER as u64 * size_of::<u32>() as u64,
+                usage: wgpu::BufferUsages::STORAGE,
+                mapped_at_creation: false,
+            }),
+        };
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Light Culling Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../assets/shaders/cluster.wgsl").into()),
+        });
+        let cull_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Light Culling Pipeline"),
+            layout: None,
+            module: &shader,
+            entry_point: Some("cs_main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        let buffer = Self::create_buffer(device, INITIAL_CAPACITY);
+        let shadows = ShadowMaps::new(device, vertex_layouts);
+        let (bind_group, cull_bind_group) = Self::create_bind_groups(
+            device,
+            &bind_group_layout,
+            &cull_pipeline,
+            &buffer,
+            &shadows,
+            &clusters,
+        );
+
+        Self {
+            lights: Vec::new(),
+            next_id: 0,
+            dirty: true,
+            capacity: INITIAL_CAPACITY,
+            buffer,
+            shadows,
+            clusters,
+            cluster_uniform: ClusterUniform {
+                grid_size: CLUSTER_GRID,
+                ..Default::default()
+            },
+            cull_pipeline,
+            cull_bind_group,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Lights Buffer"),
+            size: (size_of::<LightsHeader>() + capacity * size_of::<LightRaw>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Bind groups for shading and for the culling pass, which binds the light lists writable.
+    fn create_bind_groups(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        cull_pipeline: &wgpu::ComputePipeline,
+        buffer: &wgpu::Buffer,
+        shadows: &ShadowMaps,
+        clusters: &ClusterBuffers,
+    ) -> (wgpu::BindGroup, wgpu::BindGroup) {
+        let entries = [
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: clusters.uniform.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: clusters.light_counts.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: clusters.light_indices.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: shadows.buffer().as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::TextureView(&shadows.texture().view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 6,
+                resource: wgpu::BindingResource::Sampler(&shadows.texture().sampler),
+            },
+        ];
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Lights Bind Group"),
+            layout,
+            entries: &entries,
+        });
+        // Culling only needs the lights and the cluster lists
+        let cull_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Light Culling Bind Group"),
+            layout: &cull_pipeline.get_bind_group_layout(0),
+            entries: &entries[..4],
+        });
+        (bind_group, cull_bind_group)
+    }
+
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn add(&mut self, light: Light) -> LightId {
+        let id = LightId(self.next_id);
+        self.next_id += 1;
+        self.lights.push((id, light));
+        self.dirty = true;
+        id
+    }
+
+    pub fn remove(&mut self, id: LightId) -> Option<Light> {
+        let index = self
+            .lights
+            .iter()
+            .position(|(light_id, _)| *light_id == id)?;
+        self.dirty = true;
+        Some(self.lights.remove(index).1)
+    }
+
+    pub fn get(&self, id: LightId) -> Option<&Light> {
+        self.lights
+            .iter()
+            .find(|(light_id, _)| *light_id == id)
+            .map(|(_, light)| light)
+    }
+
+    /// Marks the lights as changed, they are uploaded again by the next [`Lights::upload`].
+    pub fn get_mut(&mut self, id: LightId) -> Option<&mut Light> {
+        let light = self
+            .lights
+            .iter_mut()
+            .find(|(light_id, _)| *light_id == id)
+            .map(|(_, light)| light)?;
+        self.dirty = true;
+        Some(light)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (LightId, &Light)> {
+        self.lights.iter().map(|(id, light)| (*id, light))
+    }
+
+    /// Sets the box around every shadow caster, see [`ShadowMaps::set_scene_bounds`].
+    pub fn set_shadow_bounds(&mut self, min: Vec3, max: Vec3) {
+        self.shadows.set_scene_bounds(min, max);
+    }
+
+    /// Sets the view the lights are clustered and directional shadows are fit for,
+    /// taking effect with the next [`Lights::upload`].
+    pub fn set_view(
+        &mut self,
+        camera: &Camera,
+        projection: &Projection,
+        size: (u32, u32),
+        debug_view: DebugView,
+    ) {
+        self.cluster_uniform = ClusterUniform {
+            view: camera.to_mat4(),
+            grid_size: CLUSTER_GRID,
+            debug_view: match debug_view {
+                DebugView::None => 0,
+                DebugView::LightClusters => 1,
+                DebugView::ShadowCascades => 2,
+            },
+            screen_size: [size.0 as f32, size.1 as f32],
+            znear: projection.znear(),
+            zfar: projection.zfar(),
+            tan_half_fovy: (projection.fovy() / 2.0).tan(),
+            aspect: projection.aspect(),
+            _padding: [0; 2],
+        };
+        self.shadows.set_view(camera, projection);
+    }
+
+    /// Writes the cluster view and shadow projections to the GPU, along with the
+    /// lights if they changed, growing the buffers when they no longer fit.
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.clusters.uniform,
+            0,
+            bytemuck::bytes_of(&self.cluster_uniform),
+        );
+
+        // Cascades follow the camera, so the shadows are prepared every frame
+        let (shadow_indices, shadows_recreated) = self.shadows.prepare(device, queue, &self.lights);
+        let grow = self.lights.len() > self.capacity;
+        if grow {
+            self.capacity = self.lights.len().next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.capacity);
+        }
+        if grow || shadows_recreated {
+            (self.bind_group, self.cull_bind_group) = Self::create_bind_groups(
+                device,
+                &self.bind_group_layout,
+                &self.cull_pipeline,
+                &self.buffer,
+                &self.shadows,
+                &self.clusters,
+            );
+        }
+
+        if !self.dirty && !grow {
+            return;
+        }
+        self.dirty = false;
+
+        let header = LightsHeader {
+            count: self.lights.len() as u32,
+            _padding: [0; 3],
+        };
+        let lights = self
+            .lights
+            .iter()
+            .zip(shadow_indices)
+            .map(|((_, light), shadow)| light.to_raw(shadow))
+            .collect::<Vec<_>>();
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&header));
+        if !lights.is_empty() {
+            queue.write_buffer(
+                &self.buffer,
+                size_of::<LightsHeader>() as u64,
+                bytemuck::cast_slice(&lights),
+            );
+        }
+    }
+
+    /// Records the depth passes of every shadow map, see [`ShadowMaps::encode`].
+    pub fn encode_shadows(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        draw: impl FnMut(&mut wgpu::RenderPass),
+    ) {
+        self.shadows.encode(encoder, draw);
+    }
+
+    /// Records the pass that bins the lights into clusters, before anything is shaded with them.
+    pub fn cull(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Light Culling Pass"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.cull_pipeline);
+        pass.set_bind_group(0, &self.cull_bind_group, &[]);
+        let [x, y, z] = CLUSTER_GRID;
+        pass.dispatch_workgroups(x.div_ceil(4), y.div_ceil(4), z.div_ceil(4));
+    }
+}
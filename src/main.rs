@@ -1,3 +1,277 @@
-fn main() {
-    pollster::block_on(rs_vulkan::run());
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+use rs_vulkan::{AppConfig, RendererConfig, Scene, WindowMode, bloom::BloomSettings};
+
+/// Interactive viewer for glTF and OBJ models.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    /// glTF/GLB or OBJ files to load in a grid, the first one is also used to draw the light
+    #[arg(value_parser = parse_model, default_value = "assets/models/Dice.glb")]
+    models: Vec<PathBuf>,
+
+    /// RON scene file describing models, lights and camera, replaces MODELS
+    #[arg(long, conflicts_with = "models")]
+    scene: Option<PathBuf>,
+
+    /// Equirectangular .hdr or .exr image drawn as the sky, replaces the one from the scene
+    #[arg(long, value_parser = parse_environment)]
+    environment: Option<PathBuf>,
+
+    /// Open a regular window instead of borderless fullscreen
+    #[arg(long)]
+    windowed: bool,
+
+    /// Inner window size in physical pixels, e.g. 1280x720
+    #[arg(long, value_parser = parse_resolution)]
+    resolution: Option<(u32, u32)>,
+
+    /// Preferred present mode, falls back to fifo when unsupported
+    #[arg(long, value_enum, default_value_t = PresentMode::Mailbox)]
+    present_mode: PresentMode,
+
+    /// Graphics API used to talk to the GPU
+    #[arg(long, value_enum, default_value_t = Backend::Auto)]
+    backend: Backend,
+
+    /// Number of MSAA samples per pixel, lowered to what the GPU supports
+    #[arg(long, default_value_t = 1, value_parser = parse_sample_count)]
+    msaa: u32,
+
+    /// Maximum anisotropic filtering samples, 1 disables anisotropic filtering
+    #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(u16).range(1..=16))]
+    anisotropy: u16,
+
+    /// Post-process anti-aliasing, on top of MSAA
+    #[arg(long, value_enum, default_value_t = AntiAliasing::None)]
+    anti_aliasing: AntiAliasing,
+
+    /// Intermediate data to visualize instead of the shaded scene
+    #[arg(long, value_enum, default_value_t = DebugView::None)]
+    debug_view: DebugView,
+
+    /// Curve mapping the HDR scene to the display
+    #[arg(long, value_enum, default_value_t = Tonemapping::Aces)]
+    tonemapping: Tonemapping,
+
+    /// Exposure adjustment in stops, applied before tonemapping
+    #[arg(long, default_value_t = 0.0, allow_hyphen_values = true)]
+    exposure: f32,
+
+    /// Start without bloom, B toggles it at runtime
+    #[arg(long)]
+    no_bloom: bool,
+
+    /// Scene brightness above which pixels start to glow
+    #[arg(long, default_value_t = BloomSettings::default().threshold)]
+    bloom_threshold: f32,
+
+    /// Fraction of the blurred highlights added back onto the scene
+    #[arg(long, default_value_t = BloomSettings::default().intensity)]
+    bloom_intensity: f32,
+
+    /// Window title
+    #[arg(long, default_value = "Hello WGPU!")]
+    title: String,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum PresentMode {
+    /// Vsync on, using the lowest latency mode available
+    Vsync,
+    /// Vsync off, using the lowest latency mode available
+    NoVsync,
+    Fifo,
+    Mailbox,
+    Immediate,
+}
+
+impl From<PresentMode> for wgpu::PresentMode {
+    fn from(mode: PresentMode) -> Self {
+        match mode {
+            PresentMode::Vsync => wgpu::PresentMode::AutoVsync,
+            PresentMode::NoVsync => wgpu::PresentMode::AutoNoVsync,
+            PresentMode::Fifo => wgpu::PresentMode::Fifo,
+            PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+            PresentMode::Immediate => wgpu::PresentMode::Immediate,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Backend {
+    /// Let wgpu pick from every backend available on this platform
+    Auto,
+    Vulkan,
+    Metal,
+    Dx12,
+    Gl,
+}
+
+impl From<Backend> for wgpu::Backends {
+    fn from(backend: Backend) -> Self {
+        match backend {
+            Backend::Auto => wgpu::Backends::all(),
+            Backend::Vulkan => wgpu::Backends::VULKAN,
+            Backend::Metal => wgpu::Backends::METAL,
+            Backend::Dx12 => wgpu::Backends::DX12,
+            Backend::Gl => wgpu::Backends::GL,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum AntiAliasing {
+    None,
+    /// Fast approximate anti-aliasing, blurs the edges of the final image
+    Fxaa,
+    /// Temporal anti-aliasing, accumulates jittered frames and also smooths shading
+    Taa,
+}
+
+impl From<AntiAliasing> for rs_vulkan::antialiasing::AntiAliasing {
+    fn from(anti_aliasing: AntiAliasing) -> Self {
+        match anti_aliasing {
+            AntiAliasing::None => rs_vulkan::antialiasing::AntiAliasing::None,
+            AntiAliasing::Fxaa => rs_vulkan::antialiasing::AntiAliasing::Fxaa,
+            AntiAliasing::Taa => rs_vulkan::antialiasing::AntiAliasing::Taa,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum DebugView {
+    None,
+    /// Heatmap of the number of lights in each light cluster
+    LightClusters,
+    /// Color of the directional light shadow cascade each pixel samples
+    ShadowCascades,
+}
+
+impl From<DebugView> for rs_vulkan::DebugView {
+    fn from(view: DebugView) -> Self {
+        match view {
+            DebugView::None => rs_vulkan::DebugView::None,
+            DebugView::LightClusters => rs_vulkan::DebugView::LightClusters,
+            DebugView::ShadowCascades => rs_vulkan::DebugView::ShadowCascades,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Tonemapping {
+    /// Filmic curve fitted to ACES, contrasty with saturated highlights
+    Aces,
+    /// Luminance based Reinhard, soft and keeps the hue of bright colors
+    Reinhard,
+    /// Desaturates bright colors towards white instead of shifting their hue
+    Agx,
+}
+
+impl From<Tonemapping> for rs_vulkan::tonemap::Tonemapping {
+    fn from(tonemapping: Tonemapping) -> Self {
+        match tonemapping {
+            Tonemapping::Aces => rs_vulkan::tonemap::Tonemapping::Aces,
+            Tonemapping::Reinhard => rs_vulkan::tonemap::Tonemapping::Reinhard,
+            Tonemapping::Agx => rs_vulkan::tonemap::Tonemapping::AgX,
+        }
+    }
+}
+
+fn parse_model(value: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(value);
+    if !path.is_file() {
+        return Err(format!("{value} does not exist or is not a file"));
+    }
+
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext)
+            if ["glb", "gltf", "obj"]
+                .iter()
+                .any(|supported| ext.eq_ignore_ascii_case(supported)) =>
+        {
+            Ok(path)
+        }
+        _ => Err(format!("{value} is not a .glb, .gltf or .obj file")),
+    }
+}
+
+fn parse_environment(value: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(value);
+    if !path.is_file() {
+        return Err(format!("{value} does not exist or is not a file"));
+    }
+
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("hdr") || ext.eq_ignore_ascii_case("exr") => Ok(path),
+        _ => Err(format!("{value} is not a .hdr or .exr file")),
+    }
+}
+
+fn parse_resolution(value: &str) -> Result<(u32, u32), String> {
+    let (width, height) = value
+        .split_once(['x', 'X'])
+        .ok_or_else(|| format!("expected WIDTHxHEIGHT, got {value}"))?;
+    let width: u32 = width
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid width {width:?}"))?;
+    let height: u32 = height
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid height {height:?}"))?;
+
+    if width == 0 || height == 0 {
+        return Err(format!("resolution must be non-zero, got {width}x{height}"));
+    }
+
+    Ok((width, height))
+}
+
+fn parse_sample_count(value: &str) -> Result<u32, String> {
+    match value.parse() {
+        Ok(count @ (1 | 2 | 4 | 8)) => Ok(count),
+        _ => Err(format!("expected 1, 2, 4 or 8, got {value}")),
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let args = Args::parse();
+    let mut scene = match &args.scene {
+        Some(path) => Scene::load(path)?,
+        None => Scene::from_models(args.models),
+    };
+    if args.environment.is_some() {
+        scene.environment = args.environment;
+    }
+
+    let config = AppConfig {
+        title: args.title,
+        window_mode: if args.windowed {
+            WindowMode::Windowed
+        } else {
+            WindowMode::BorderlessFullscreen
+        },
+        resolution: args.resolution,
+        present_mode: args.present_mode.into(),
+        backends: args.backend.into(),
+        renderer: RendererConfig {
+            scene,
+            sample_count: args.msaa,
+            anisotropy: args.anisotropy,
+            anti_aliasing: args.anti_aliasing.into(),
+            debug_view: args.debug_view.into(),
+            tonemapping: args.tonemapping.into(),
+            exposure: args.exposure,
+            bloom: (!args.no_bloom).then_some(BloomSettings {
+                threshold: args.bloom_threshold,
+                intensity: args.bloom_intensity,
+            }),
+        },
+    };
+
+    pollster::block_on(rs_vulkan::run(config))
 }
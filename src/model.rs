@@ -1,8 +1,19 @@
+mod obj;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
 
+use bevy_math::{Mat4, Vec3};
 use wgpu::util::DeviceExt;
 
-use crate::texture::Texture;
+use crate::{
+    assets::{AssetServer, Handle},
+    texture::{SamplerDesc, Texture, TextureRole, gltf_to_rgba8},
+};
 
 pub trait Vertex {
     fn desc() -> wgpu::VertexBufferLayout<'static>;
@@ -14,6 +25,8 @@ pub struct ModelVertex {
     position: [f32; 3],
     tex_coords: [f32; 2],
     normals: [f32; 3],
+    /// Tangent with the bitangent sign in `w`, all zeros when the mesh has none.
+    tangent: [f32; 4],
 }
 
 impl Vertex for ModelVertex {
@@ -38,6 +51,11 @@ impl Vertex for ModelVertex {
                         + std::mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
                     shader_location: 2,
                 },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: std::mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                },
             ],
         }
     }
@@ -73,6 +91,15 @@ pub trait DrawModel<'a> {
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
+
+    fn draw_node_instanced(
+        &mut self,
+        model: &'a Model,
+        node: &'a ModelNode,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
 }
 
 impl<'a, 'b> DrawModel<'b> for wgpu::RenderPass<'a>
@@ -132,6 +159,27 @@ where
             );
         }
     }
+
+    fn draw_node_instanced(
+        &mut self,
+        model: &'b Model,
+        node: &'b ModelNode,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        for &mesh_index in &node.meshes {
+            let mesh = &model.meshes[mesh_index];
+            let material = &model.materials[mesh.material_index];
+            self.draw_mesh_instanced(
+                mesh,
+                material,
+                instances.clone(),
+                camera_bind_group,
+                light_bind_group,
+            );
+        }
+    }
 }
 
 pub trait DrawLight<'a> {
@@ -217,102 +265,506 @@ where
     }
 }
 
+/// Draws geometry only, for depth passes that need neither materials nor lights.
+pub trait DrawShadow<'a> {
+    fn draw_shadow_mesh_instanced(&mut self, mesh: &'a ModelMesh, instances: Range<u32>);
+
+    fn draw_shadow_node_instanced(
+        &mut self,
+        model: &'a Model,
+        node: &'a ModelNode,
+        instances: Range<u32>,
+    );
+}
+
+// Render passes no longer borrow what they draw, so any pass can draw any model
+impl<'b> DrawShadow<'b> for wgpu::RenderPass<'_> {
+    fn draw_shadow_mesh_instanced(&mut self, mesh: &'b ModelMesh, instances: Range<u32>) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.draw_indexed(0..mesh.num_indices, 0, instances);
+    }
+
+    fn draw_shadow_node_instanced(
+        &mut self,
+        model: &'b Model,
+        node: &'b ModelNode,
+        instances: Range<u32>,
+    ) {
+        for &mesh_index in &node.meshes {
+            self.draw_shadow_mesh_instanced(&model.meshes[mesh_index], instances.clone());
+        }
+    }
+}
+
 pub struct Model {
     pub meshes: Vec<ModelMesh>,
+    /// One material per glTF or MTL material in the same order, followed by the default material.
     pub materials: Vec<ModelMaterial>,
+    /// Flattened node hierarchy of the default scene, parents come before their children.
+    pub nodes: Vec<ModelNode>,
 }
 
 impl Model {
-    pub fn load_gltf<P: AsRef<std::path::Path>>(
-        file_name: P,
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        layout: &wgpu::BindGroupLayout,
-    ) -> anyhow::Result<Self> {
-        let (gltf, buffers, images) = gltf::import(file_name)?;
+    /// Loads a glTF/GLB or OBJ file, picking the loader from the file extension.
+    ///
+    /// Textures go through `assets`, so files shared between models are only
+    /// loaded once. Use [`AssetServer::load_model`] to share the model itself.
+    pub fn load<P: AsRef<Path>>(path: P, assets: &mut AssetServer) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_ascii_lowercase);
+
+        match extension.as_deref() {
+            Some("gltf" | "glb") => Self::load_gltf(path, assets),
+            Some("obj") => Self::load_obj(path, assets),
+            _ => anyhow::bail!(
+                "Unsupported model format {}, expected .gltf, .glb or .obj",
+                path.display()
+            ),
+        }
+    }
+
+    /// Loads a Wavefront OBJ file and the MTL libraries it references.
+    ///
+    /// Phong materials are approximated as dielectrics, see [`MaterialUniform`].
+    pub fn load_obj<P: AsRef<Path>>(path: P, assets: &mut AssetServer) -> anyhow::Result<Self> {
+        obj::load(path.as_ref(), assets)
+    }
+
+    pub fn load_gltf<P: AsRef<Path>>(path: P, assets: &mut AssetServer) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let device = assets.device().clone();
+        let queue = assets.queue().clone();
+        let layout = assets.material_layout().clone();
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        // Images are loaded on demand, the import of `gltf` cannot decode KTX2
+        let gltf::Gltf {
+            document: gltf,
+            blob,
+        } = gltf::Gltf::open(path)?;
+        let buffers = gltf::import_buffers(&gltf, Some(base), blob)?;
         let mut meshes = Vec::new();
         let mut materials = Vec::new();
+        // Indices into `meshes` for the primitives of every glTF mesh
+        let mut mesh_primitives = Vec::new();
 
         for mesh in gltf.meshes() {
+            let mut primitives = Vec::new();
             for primitive in mesh.primitives() {
+                let mesh_name = mesh.name().unwrap_or("No name");
                 let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
 
-                let vertices: Vec<ModelVertex> = reader
+                let positions: Vec<[f32; 3]> = reader
                     .read_positions()
-                    .unwrap()
-                    .zip(reader.read_tex_coords(0).unwrap().into_f32())
-                    .zip(reader.read_normals().unwrap())
-                    .map(|((pos, uv), normal)| ModelVertex {
-                        position: [pos[0], pos[1], pos[2]],
-                        tex_coords: [uv[0], uv[1]],
-                        normals: [normal[0], normal[1], normal[2]],
+                    .ok_or_else(|| ModelError::MissingPositions {
+                        mesh: mesh_name.to_string(),
+                        primitive: primitive.index(),
+                    })?
+                    .collect();
+                let tex_coords: Vec<[f32; 2]> = reader
+                    .read_tex_coords(0)
+                    .map(|tex_coords| tex_coords.into_f32().collect())
+                    .unwrap_or_default();
+                let normals: Option<Vec<[f32; 3]>> =
+                    reader.read_normals().map(|normals| normals.collect());
+                // Without tangents the shader derives them from screen space derivatives
+                let tangents: Vec<[f32; 4]> = reader
+                    .read_tangents()
+                    .map(|tangents| tangents.collect())
+                    .unwrap_or_default();
+
+                let mut vertices: Vec<ModelVertex> = positions
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &position)| ModelVertex {
+                        position,
+                        tex_coords: tex_coords.get(i).copied().unwrap_or_default(),
+                        normals: normals
+                            .as_ref()
+                            .and_then(|normals| normals.get(i).copied())
+                            .unwrap_or_default(),
+                        tangent: tangents.get(i).copied().unwrap_or_default(),
                     })
                     .collect();
 
-                let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Vertex Buffer"),
-                    contents: bytemuck::cast_slice(&vertices),
-                    usage: wgpu::BufferUsages::VERTEX,
-                });
-
-                let indices: Vec<u32> = reader.read_indices().unwrap().into_u32().collect();
-                let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Index Buffer"),
-                    contents: bytemuck::cast_slice(&indices),
-                    usage: wgpu::BufferUsages::INDEX,
-                });
-
-                let material_index = primitive.material().index().unwrap_or(0) as usize;
-                meshes.push(ModelMesh {
-                    name: mesh.name().unwrap_or("No name").to_string(),
-                    vertex_buffer,
-                    index_buffer,
-                    num_indices: indices.len() as u32,
+                // Non-indexed primitives draw their vertices in order
+                let indices: Vec<u32> = match reader.read_indices() {
+                    Some(indices) => indices.into_u32().collect(),
+                    None => (0..vertices.len() as u32).collect(),
+                };
+                let mut indices = triangle_list(primitive.mode(), indices).map_err(|mode| {
+                    ModelError::UnsupportedTopology {
+                        mesh: mesh_name.to_string(),
+                        primitive: primitive.index(),
+                        mode,
+                    }
+                })?;
+                // Fewer than three indices make no triangle, and empty buffers cannot be drawn
+                if indices.is_empty() {
+                    log::warn!(
+                        "{}: primitive {} of mesh {mesh_name} has no triangles, skipping it",
+                        path.display(),
+                        primitive.index()
+                    );
+                    continue;
+                }
+
+                if let Some(&index) = indices.iter().find(|&&i| i as usize >= vertices.len()) {
+                    return Err(ModelError::IndexOutOfBounds {
+                        mesh: mesh_name.to_string(),
+                        primitive: primitive.index(),
+                        index,
+                        vertex_count: vertices.len(),
+                    }
+                    .into());
+                }
+
+                if normals.is_none() {
+                    (vertices, indices) = with_flat_normals(&vertices, &indices);
+                }
+
+                // Primitives without a material use the default one appended after the glTF materials
+                let material_index = primitive
+                    .material()
+                    .index()
+                    .unwrap_or(gltf.materials().len());
+                primitives.push(meshes.len());
+                meshes.push(ModelMesh::new(
+                    &device,
+                    mesh_name.to_string(),
+                    &vertices,
+                    &indices,
                     material_index,
-                });
+                ));
             }
+            mesh_primitives.push(primitives);
         }
 
-        for material in gltf.materials() {
-            let diffuse_texture =
-                if let Some(texture) = material.pbr_metallic_roughness().base_color_texture() {
-                    let texture = texture.texture();
-                    let image = &images[texture.index()];
-
-                    let size = wgpu::Extent3d {
-                        width: image.width,
-                        height: image.height,
-                        depth_or_array_layers: 1,
+        // Images are shared between textures in the file, external ones also between files
+        let mut loaded_images: HashMap<(usize, TextureRole), Handle<Texture>> = HashMap::new();
+        let mut load_texture =
+            |texture: gltf::Texture, role: TextureRole| -> anyhow::Result<MaterialTexture> {
+                let sampler = assets.sampler(sampler_desc(&texture.sampler()));
+
+                // KHR_texture_basisu moves the KTX2 image into the extension, `source`
+                // then is an optional PNG or JPEG fallback
+                let basisu = texture
+                    .extension_value("KHR_texture_basisu")
+                    .and_then(|extension| extension.get("source")?.as_u64())
+                    .and_then(|index| gltf.images().nth(index as usize));
+                let mut images = basisu.into_iter().chain(texture.source()).peekable();
+
+                let handle = loop {
+                    let Some(image) = images.next() else {
+                        anyhow::bail!("Texture {} has no image", texture.index());
                     };
+                    if let Some(handle) = loaded_images.get(&(image.index(), role)) {
+                        break handle.clone();
+                    }
 
-                    Texture::from_image(device, queue, size, &image.pixels, texture.name())?
-                } else {
-                    continue;
+                    match load_image(assets, &image, base, &buffers, role, texture.name()) {
+                        Ok(handle) => {
+                            loaded_images.insert((image.index(), role), handle.clone());
+                            break handle;
+                        }
+                        Err(error) if images.peek().is_some() => {
+                            log::warn!("{error:#}, using the fallback image");
+                        }
+                        Err(error) => return Err(error),
+                    }
                 };
 
-            let bindgroup = device.create_bind_group(&wgpu::BindGroupDescriptor {
-                label: Some("Material Bind Group"),
-                layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                    },
-                ],
-            });
+                Ok(MaterialTexture {
+                    texture: handle,
+                    sampler,
+                })
+            };
 
-            materials.push(ModelMaterial {
-                name: material.name().unwrap_or("No name").to_string(),
-                diffuse_texture,
-                bindgroup,
-            });
+        let defaults = DefaultTextures::new(&device, &queue)?;
+
+        // Indices match the glTF material indices, so every material is pushed
+        for material in gltf.materials() {
+            let pbr = material.pbr_metallic_roughness();
+
+            let textures = MaterialTextures {
+                base_color: pbr
+                    .base_color_texture()
+                    .map(|info| load_texture(info.texture(), TextureRole::Color))
+                    .transpose()?,
+                metallic_roughness: pbr
+                    .metallic_roughness_texture()
+                    .map(|info| load_texture(info.texture(), TextureRole::Data))
+                    .transpose()?,
+                normal: material
+                    .normal_texture()
+                    .map(|normal| load_texture(normal.texture(), TextureRole::Data))
+                    .transpose()?,
+                occlusion: material
+                    .occlusion_texture()
+                    .map(|occlusion| load_texture(occlusion.texture(), TextureRole::Data))
+                    .transpose()?,
+                emissive: material
+                    .emissive_texture()
+                    .map(|info| load_texture(info.texture(), TextureRole::Color))
+                    .transpose()?,
+            };
+
+            let uniform = MaterialUniform {
+                base_color_factor: pbr.base_color_factor(),
+                emissive_factor: material.emissive_factor(),
+                metallic_factor: pbr.metallic_factor(),
+                roughness_factor: pbr.roughness_factor(),
+                normal_scale: material
+                    .normal_texture()
+                    .map_or(1.0, |normal| normal.scale()),
+                occlusion_strength: material
+                    .occlusion_texture()
+                    .map_or(1.0, |occlusion| occlusion.strength()),
+                normal_encoding: textures.normal.as_ref().map_or(0, |normal| {
+                    MaterialUniform::normal_encoding(normal.texture.stored_format)
+                }),
+            };
+
+            materials.push(ModelMaterial::new(
+                &device,
+                &layout,
+                &defaults,
+                material.name().unwrap_or("No name").to_string(),
+                uniform,
+                textures,
+            ));
+        }
+
+        materials.push(ModelMaterial::default_material(&device, &layout, &defaults));
+
+        let nodes = match gltf.default_scene().or_else(|| gltf.scenes().next()) {
+            Some(scene) => {
+                let mut nodes = Vec::new();
+                for root in scene.nodes() {
+                    ModelNode::collect(&root, None, Mat4::IDENTITY, &mesh_primitives, &mut nodes);
+                }
+                nodes
+            }
+            // Without a scene there is no hierarchy, so every mesh is placed at the origin
+            None => mesh_primitives
+                .into_iter()
+                .enumerate()
+                .map(|(index, meshes)| ModelNode {
+                    name: format!("Mesh {index}"),
+                    parent: None,
+                    local_transform: Mat4::IDENTITY,
+                    world_transform: Mat4::IDENTITY,
+                    meshes,
+                })
+                .collect(),
+        };
+
+        Ok(Model {
+            meshes,
+            materials,
+            nodes,
+        })
+    }
+}
+
+/// Geometry in a glTF file that cannot be turned into a [`ModelMesh`].
+#[derive(Debug, thiserror::Error)]
+pub enum ModelError {
+    #[error("Primitive {primitive} of mesh {mesh:?} has no positions")]
+    MissingPositions { mesh: String, primitive: usize },
+    #[error(
+        "Primitive {primitive} of mesh {mesh:?} uses {mode:?}, only triangles, strips and fans are supported"
+    )]
+    UnsupportedTopology {
+        mesh: String,
+        primitive: usize,
+        mode: gltf::mesh::Mode,
+    },
+    #[error(
+        "Primitive {primitive} of mesh {mesh:?} references vertex {index} but has {vertex_count} vertices"
+    )]
+    IndexOutOfBounds {
+        mesh: String,
+        primitive: usize,
+        index: u32,
+        vertex_count: usize,
+    },
+}
+
+/// Uploads a glTF image, KTX2 images are recognized by their MIME type or file extension.
+fn load_image(
+    assets: &mut AssetServer,
+    image: &gltf::Image,
+    base: &Path,
+    buffers: &[gltf::buffer::Data],
+    role: TextureRole,
+    label: Option<&str>,
+) -> anyhow::Result<Handle<Texture>> {
+    use gltf::image::Source;
+
+    let is_ktx2 = match image.source() {
+        Source::View { mime_type, .. } => mime_type == "image/ktx2",
+        Source::Uri { uri, mime_type } => {
+            mime_type == Some("image/ktx2") || uri.to_ascii_lowercase().ends_with(".ktx2")
+        }
+    };
+
+    let create = |assets: &mut AssetServer| {
+        if is_ktx2 {
+            let bytes = match image.source() {
+                Source::View { view, .. } => {
+                    let buffer = &buffers[view.buffer().index()];
+                    Cow::Borrowed(&buffer[view.offset()..view.offset() + view.length()])
+                }
+                Source::Uri { uri, .. } if uri.starts_with("data:") => {
+                    anyhow::bail!("KTX2 images in data URIs are not supported")
+                }
+                Source::Uri { uri, .. } => Cow::Owned(std::fs::read(uri_path(base, uri)?)?),
+            };
+            return assets.create_ktx2_texture(&bytes, role, label);
+        }
+
+        let data = gltf::image::Data::from_source(image.source(), Some(base), buffers)?;
+        let size = wgpu::Extent3d {
+            width: data.width,
+            height: data.height,
+            depth_or_array_layers: 1,
+        };
+        assets.create_texture(size, &gltf_to_rgba8(&data)?, role, label)
+    };
+
+    match image.source() {
+        Source::Uri { uri, .. } if !uri.starts_with("data:") => {
+            assets.load_texture_with(&uri_path(base, uri)?, role, create)
+        }
+        _ => create(assets).map(Handle::new),
+    }
+    .with_context(|| format!("Failed to load image {}", image.index()))
+}
+
+/// File an external glTF URI refers to. Relative URIs and `file:` URIs on the
+/// local host are percent-encoded, relative paths are resolved against `base`.
+/// Data URIs have no file and are rejected like other schemes.
+fn uri_path(base: &Path, uri: &str) -> anyhow::Result<PathBuf> {
+    anyhow::ensure!(
+        !uri.starts_with("data:"),
+        "Data URIs do not refer to a file"
+    );
+    let encoded = match uri.strip_prefix("file:") {
+        Some(path) => match path.strip_prefix("//") {
+            // An authority is followed by an absolute path, only the local host has files here
+            Some(authority) => {
+                let path = authority.strip_prefix("localhost").unwrap_or(authority);
+                anyhow::ensure!(path.starts_with('/'), "Unsupported host in {uri}");
+                path
+            }
+            None => path,
+        },
+        None => {
+            anyhow::ensure!(!uri.contains(':'), "Unsupported URI scheme in {uri}");
+            uri
         }
+    };
 
-        Ok(Model { meshes, materials })
+    let path = urlencoding::decode(encoded).with_context(|| format!("Invalid URI {uri}"))?;
+    // Windows paths are written with a leading slash, `file:///C:/textures`
+    let path = match path.as_bytes() {
+        [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => &path[1..],
+        _ => &path,
+    };
+    Ok(base.join(path))
+}
+
+/// Converts indices of any triangle topology into a triangle list, dropping a
+/// trailing incomplete triangle. Points and lines are returned as the error.
+fn triangle_list(mode: gltf::mesh::Mode, indices: Vec<u32>) -> Result<Vec<u32>, gltf::mesh::Mode> {
+    use gltf::mesh::Mode;
+
+    let triangle_count = indices.len().saturating_sub(2);
+    match mode {
+        Mode::Triangles => {
+            let mut indices = indices;
+            indices.truncate(indices.len() - indices.len() % 3);
+            Ok(indices)
+        }
+        // Every other triangle is flipped to keep the winding order consistent
+        Mode::TriangleStrip => Ok((0..triangle_count)
+            .flat_map(|i| {
+                if i % 2 == 0 {
+                    [indices[i], indices[i + 1], indices[i + 2]]
+                } else {
+                    [indices[i], indices[i + 2], indices[i + 1]]
+                }
+            })
+            .collect()),
+        Mode::TriangleFan => Ok((0..triangle_count)
+            .flat_map(|i| [indices[i + 1], indices[i + 2], indices[0]])
+            .collect()),
+        Mode::Points | Mode::Lines | Mode::LineLoop | Mode::LineStrip => Err(mode),
+    }
+}
+
+/// Gives every triangle its own vertices with the face normal, which is what
+/// the glTF spec asks for when a primitive has no normals.
+fn with_flat_normals(vertices: &[ModelVertex], indices: &[u32]) -> (Vec<ModelVertex>, Vec<u32>) {
+    let flat_vertices = indices
+        .chunks_exact(3)
+        .flat_map(|triangle| {
+            let [a, b, c] = [0, 1, 2].map(|i| vertices[triangle[i] as usize]);
+            let [pa, pb, pc] = [a, b, c].map(|vertex| Vec3::from(vertex.position));
+            let normal = (pb - pa).cross(pc - pa).normalize_or_zero().into();
+            [a, b, c].map(|vertex| ModelVertex {
+                normals: normal,
+                ..vertex
+            })
+        })
+        .collect::<Vec<_>>();
+    let flat_indices = (0..flat_vertices.len() as u32).collect();
+
+    (flat_vertices, flat_indices)
+}
+
+pub struct ModelNode {
+    pub name: String,
+    /// Index of the parent in [`Model::nodes`].
+    pub parent: Option<usize>,
+    pub local_transform: Mat4,
+    /// Transform from node space to model space, including all parents.
+    pub world_transform: Mat4,
+    /// Indices into [`Model::meshes`] drawn with this node's transform.
+    pub meshes: Vec<usize>,
+}
+
+impl ModelNode {
+    fn collect(
+        node: &gltf::Node,
+        parent: Option<usize>,
+        parent_transform: Mat4,
+        mesh_primitives: &[Vec<usize>],
+        nodes: &mut Vec<ModelNode>,
+    ) {
+        let local_transform = Mat4::from_cols_array_2d(&node.transform().matrix());
+        let world_transform = parent_transform * local_transform;
+        let index = nodes.len();
+
+        nodes.push(ModelNode {
+            name: node.name().unwrap_or("No name").to_string(),
+            parent,
+            local_transform,
+            world_transform,
+            meshes: node
+                .mesh()
+                .map(|mesh| mesh_primitives[mesh.index()].clone())
+                .unwrap_or_default(),
+        });
+
+        for child in node.children() {
+            Self::collect(&child, Some(index), world_transform, mesh_primitives, nodes);
+        }
     }
 }
 
@@ -322,10 +774,404 @@ pub struct ModelMesh {
     pub index_buffer: wgpu::Buffer,
     pub num_indices: u32,
     pub material_index: usize,
+    /// Corners of the box around the vertex positions, in mesh space.
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl ModelMesh {
+    fn new(
+        device: &wgpu::Device,
+        name: String,
+        vertices: &[ModelVertex],
+        indices: &[u32],
+        material_index: usize,
+    ) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex Buffer"),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Index Buffer"),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let (min, max) = vertices.iter().fold(
+            (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+            |(min, max), vertex| {
+                let position = Vec3::from(vertex.position);
+                (min.min(position), max.max(position))
+            },
+        );
+
+        Self {
+            name,
+            vertex_buffer,
+            index_buffer,
+            num_indices: indices.len() as u32,
+            material_index,
+            min,
+            max,
+        }
+    }
+}
+
+/// Factors of the glTF metallic-roughness material model, multiplied with the
+/// matching texture in the shader.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    pub base_color_factor: [f32; 4],
+    pub emissive_factor: [f32; 3],
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub normal_scale: f32,
+    pub occlusion_strength: f32,
+    /// How the normal map is stored, see [`MaterialUniform::normal_encoding`].
+    pub normal_encoding: u32,
+}
+
+impl MaterialUniform {
+    /// Flags for a normal map stored in `format`: 1 when it only holds x and y
+    /// so the shader reconstructs z, as in BC5 and EAC RG11, and 2 when it is
+    /// signed and already in -1..1 instead of 0..1.
+    pub fn normal_encoding(format: wgpu::TextureFormat) -> u32 {
+        let two_channel = format.components() == 2;
+        let signed = matches!(
+            format,
+            wgpu::TextureFormat::Bc5RgSnorm
+                | wgpu::TextureFormat::EacRg11Snorm
+                | wgpu::TextureFormat::Rg8Snorm
+                | wgpu::TextureFormat::Rgba8Snorm
+        );
+        u32::from(two_channel) | u32::from(signed) << 1
+    }
+}
+
+/// The glTF default material, an opaque white rough metal.
+impl Default for MaterialUniform {
+    fn default() -> Self {
+        Self {
+            base_color_factor: [1.0; 4],
+            emissive_factor: [0.0; 3],
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+            normal_encoding: 0,
+        }
+    }
+}
+
+/// Translates a glTF sampler, unspecified filters are linear.
+fn sampler_desc(sampler: &gltf::texture::Sampler) -> SamplerDesc {
+    use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+    use wgpu::FilterMode::{Linear, Nearest};
+
+    let address_mode = |mode| match mode {
+        WrappingMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+        WrappingMode::MirroredRepeat => wgpu::AddressMode::MirrorRepeat,
+        WrappingMode::Repeat => wgpu::AddressMode::Repeat,
+    };
+
+    let mag_filter = match sampler.mag_filter() {
+        Some(MagFilter::Nearest) => Nearest,
+        Some(MagFilter::Linear) | None => Linear,
+    };
+    let (min_filter, mipmap_filter) = match sampler.min_filter() {
+        Some(MinFilter::Nearest | MinFilter::NearestMipmapNearest) => (Nearest, Nearest),
+        Some(MinFilter::Linear | MinFilter::LinearMipmapNearest) => (Linear, Nearest),
+        Some(MinFilter::NearestMipmapLinear) => (Nearest, Linear),
+        Some(MinFilter::LinearMipmapLinear) | None => (Linear, Linear),
+    };
+
+    SamplerDesc {
+        address_mode_u: address_mode(sampler.wrap_s()),
+        address_mode_v: address_mode(sampler.wrap_t()),
+        mag_filter,
+        min_filter,
+        mipmap_filter,
+    }
+}
+
+/// A material map together with the sampler it is read with.
+pub struct MaterialTexture {
+    pub texture: Handle<Texture>,
+    pub sampler: wgpu::Sampler,
+}
+
+/// Maps of a material, `None` where the material has none.
+#[derive(Default)]
+pub struct MaterialTextures {
+    pub base_color: Option<MaterialTexture>,
+    /// Roughness in the green channel and metalness in the blue channel.
+    pub metallic_roughness: Option<MaterialTexture>,
+    pub normal: Option<MaterialTexture>,
+    pub occlusion: Option<MaterialTexture>,
+    pub emissive: Option<MaterialTexture>,
+}
+
+/// Stand-ins for missing maps, chosen so they leave the factors unchanged.
+struct DefaultTextures {
+    white: Texture,
+    flat_normal: Texture,
+}
+
+impl DefaultTextures {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<Self> {
+        Ok(Self {
+            // White is 1.0 in sRGB and linear alike, so it serves every role
+            white: Texture::from_color(
+                device,
+                queue,
+                [255, 255, 255, 255],
+                TextureRole::Data,
+                "White Texture",
+            )?,
+            flat_normal: Texture::from_color(
+                device,
+                queue,
+                [128, 128, 255, 255],
+                TextureRole::Data,
+                "Flat Normal Texture",
+            )?,
+        })
+    }
 }
 
 pub struct ModelMaterial {
     pub name: String,
-    pub diffuse_texture: Texture,
+    pub uniform: MaterialUniform,
+    pub textures: MaterialTextures,
     pub bindgroup: wgpu::BindGroup,
 }
+
+impl ModelMaterial {
+    fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        defaults: &DefaultTextures,
+        name: String,
+        uniform: MaterialUniform,
+        textures: MaterialTextures,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Material Uniform Buffer"),
+            contents: bytemuck::bytes_of(&uniform),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        fn binding<'a>(
+            texture: &'a Option<MaterialTexture>,
+            default: &'a Texture,
+        ) -> (&'a wgpu::TextureView, &'a wgpu::Sampler) {
+            texture
+                .as_ref()
+                .map_or((&default.view, &default.sampler), |texture| {
+                    (&texture.texture.view, &texture.sampler)
+                })
+        }
+        let bindgroup = Self::create_bind_group(
+            device,
+            layout,
+            &uniform_buffer,
+            [
+                binding(&textures.base_color, &defaults.white),
+                binding(&textures.metallic_roughness, &defaults.white),
+                binding(&textures.normal, &defaults.flat_normal),
+                binding(&textures.occlusion, &defaults.white),
+                binding(&textures.emissive, &defaults.white),
+            ],
+        );
+
+        Self {
+            name,
+            uniform,
+            textures,
+            bindgroup,
+        }
+    }
+
+    /// Used by meshes that do not reference a material.
+    fn default_material(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        defaults: &DefaultTextures,
+    ) -> Self {
+        Self::new(
+            device,
+            layout,
+            defaults,
+            "Default".to_string(),
+            MaterialUniform::default(),
+            MaterialTextures::default(),
+        )
+    }
+
+    /// Texture order of [`ModelMaterial::create_bind_group`], each texture is
+    /// followed by its sampler.
+    const TEXTURE_COUNT: u32 = 5;
+
+    /// Layout of the material bind group: the [`MaterialUniform`] at binding 0,
+    /// then base color, metallic-roughness, normal, occlusion and emissive
+    /// textures with their samplers.
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let mut entries = vec![wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: wgpu::BufferSize::new(
+                    std::mem::size_of::<MaterialUniform>() as u64
+                ),
+            },
+            count: None,
+        }];
+        for i in 0..Self::TEXTURE_COUNT {
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: 1 + 2 * i,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            });
+            entries.push(wgpu::BindGroupLayoutEntry {
+                binding: 2 + 2 * i,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            });
+        }
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Material Bind Group Layout"),
+            entries: &entries,
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        textures: [(&wgpu::TextureView, &wgpu::Sampler); Self::TEXTURE_COUNT as usize],
+    ) -> wgpu::BindGroup {
+        let mut entries = vec![wgpu::BindGroupEntry {
+            binding: 0,
+            resource: uniform_buffer.as_entire_binding(),
+        }];
+        for (i, (view, sampler)) in (0..).zip(textures) {
+            entries.push(wgpu::BindGroupEntry {
+                binding: 1 + 2 * i,
+                resource: wgpu::BindingResource::TextureView(view),
+            });
+            entries.push(wgpu::BindGroupEntry {
+                binding: 2 + 2 * i,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            });
+        }
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Material Bind Group"),
+            layout,
+            entries: &entries,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gltf::mesh::Mode;
+
+    use super::*;
+
+    #[test]
+    fn too_few_indices_make_no_triangles() {
+        for mode in [Mode::Triangles, Mode::TriangleStrip, Mode::TriangleFan] {
+            assert_eq!(triangle_list(mode, vec![]), Ok(vec![]), "{mode:?}");
+            assert_eq!(triangle_list(mode, vec![0, 1]), Ok(vec![]), "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn strips_and_fans_become_lists() {
+        assert_eq!(
+            triangle_list(Mode::Triangles, vec![0, 1, 2, 3]),
+            Ok(vec![0, 1, 2])
+        );
+        assert_eq!(
+            triangle_list(Mode::TriangleStrip, vec![0, 1, 2, 3]),
+            Ok(vec![0, 1, 2, 1, 3, 2])
+        );
+        assert_eq!(
+            triangle_list(Mode::TriangleFan, vec![0, 1, 2, 3]),
+            Ok(vec![1, 2, 0, 2, 3, 0])
+        );
+        assert_eq!(triangle_list(Mode::Lines, vec![0, 1]), Err(Mode::Lines));
+    }
+
+    #[test]
+    fn relative_uris_are_percent_decoded() {
+        let base = Path::new("models");
+        assert_eq!(
+            uri_path(base, "my%20tex.png").unwrap(),
+            base.join("my tex.png")
+        );
+        assert_eq!(
+            uri_path(base, "textures/%C3%A9t%C3%A9.ktx2").unwrap(),
+            base.join("textures/été.ktx2")
+        );
+    }
+
+    #[test]
+    fn file_uris_are_percent_decoded() {
+        let base = Path::new("models");
+        assert_eq!(
+            uri_path(base, "file:///tmp/tex.png").unwrap(),
+            PathBuf::from("/tmp/tex.png")
+        );
+        assert_eq!(
+            uri_path(base, "file:///tmp/my%20tex.png").unwrap(),
+            PathBuf::from("/tmp/my tex.png")
+        );
+        assert_eq!(
+            uri_path(base, "file://localhost/tmp/tex.png").unwrap(),
+            PathBuf::from("/tmp/tex.png")
+        );
+        assert_eq!(
+            uri_path(base, "file:my%20tex.png").unwrap(),
+            base.join("my tex.png")
+        );
+        // Absolute on Windows, where joining replaces the base
+        assert_eq!(
+            uri_path(base, "file:///C:/textures/tex.png").unwrap(),
+            base.join("C:/textures/tex.png")
+        );
+        assert!(uri_path(base, "file://server/share/tex.png").is_err());
+    }
+
+    #[test]
+    fn data_and_remote_uris_have_no_path() {
+        let base = Path::new("models");
+        assert!(uri_path(base, "data:image/png;base64,AAAA").is_err());
+        assert!(uri_path(base, "https://example.com/tex.png").is_err());
+        assert!(uri_path(base, "%FF.png").is_err());
+    }
+
+    #[test]
+    fn two_channel_normal_maps_reconstruct_z() {
+        use wgpu::TextureFormat as F;
+        assert_eq!(MaterialUniform::normal_encoding(F::Rgba8Unorm), 0);
+        assert_eq!(MaterialUniform::normal_encoding(F::Bc7RgbaUnorm), 0);
+        assert_eq!(MaterialUniform::normal_encoding(F::Bc5RgUnorm), 1);
+        assert_eq!(MaterialUniform::normal_encoding(F::EacRg11Unorm), 1);
+        assert_eq!(MaterialUniform::normal_encoding(F::Bc5RgSnorm), 3);
+        assert_eq!(MaterialUniform::normal_encoding(F::EacRg11Snorm), 3);
+    }
+}
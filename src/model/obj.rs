@@ -0,0 +1,548 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Context;
+use bevy_math::Mat4;
+
+use super::{
+    DefaultTextures, MaterialTexture, MaterialTextures, MaterialUniform, Model, ModelMaterial,
+    ModelMesh, ModelNode, ModelVertex, with_flat_normals,
+};
+use crate::{
+    assets::AssetServer,
+    texture::{SamplerDesc, TextureRole},
+};
+
+/// A `v/vt/vn` triple with zero based indices, texture coordinate and normal are optional.
+type FaceVertex = (usize, Option<usize>, Option<usize>);
+
+/// Triangles of one object or group that share a material.
+struct Group {
+    name: String,
+    material: Option<String>,
+    has_normals: bool,
+    vertices: Vec<ModelVertex>,
+    indices: Vec<u32>,
+    lookup: HashMap<FaceVertex, u32>,
+}
+
+impl Group {
+    fn new(name: String, material: Option<String>) -> Self {
+        Self {
+            name,
+            material,
+            has_normals: true,
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            lookup: HashMap::new(),
+        }
+    }
+}
+
+/// The parts of an MTL material that map onto the metallic-roughness model.
+struct MtlMaterial {
+    name: String,
+    diffuse: [f32; 3],
+    specular: [f32; 3],
+    shininess: f32,
+    dissolve: f32,
+    emissive: [f32; 3],
+    diffuse_map: Option<std::path::PathBuf>,
+    diffuse_map_address_mode: wgpu::AddressMode,
+}
+
+impl MtlMaterial {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            diffuse: [1.0; 3],
+            specular: [0.0; 3],
+            shininess: 0.0,
+            dissolve: 1.0,
+            emissive: [0.0; 3],
+            diffuse_map: None,
+            diffuse_map_address_mode: wgpu::AddressMode::Repeat,
+        }
+    }
+
+    /// Approximates the Phong parameters as a dielectric, the specular
+    /// exponent becomes roughness and surfaces without `Ks` are fully rough.
+    fn uniform(&self) -> MaterialUniform {
+        let [r, g, b] = self.diffuse;
+        let roughness = if self.specular.iter().all(|&ks| ks <= 0.0) {
+            1.0
+        } else {
+            (2.0 / (self.shininess.max(0.0) + 2.0)).sqrt()
+        };
+
+        MaterialUniform {
+            base_color_factor: [r, g, b, self.dissolve],
+            emissive_factor: self.emissive,
+            metallic_factor: 0.0,
+            roughness_factor: roughness,
+            ..Default::default()
+        }
+    }
+}
+
+pub(super) fn load(path: &Path, assets: &mut AssetServer) -> anyhow::Result<Model> {
+    let source = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut tex_coords: Vec<[f32; 2]> = Vec::new();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+    let mut mtl_materials = Vec::new();
+    let mut groups = vec![Group::new("Default".to_string(), None)];
+
+    for (line_index, line) in source.lines().enumerate() {
+        let error = |msg: &str| anyhow::anyhow!("{}:{}: {msg}", path.display(), line_index + 1);
+
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let rest = line[keyword.len()..].trim();
+
+        match keyword {
+            "v" => positions.push(parse_floats(tokens).ok_or_else(|| error("Invalid vertex"))?),
+            "vt" => {
+                let [u, v] = parse_floats(tokens.take(2))
+                    .ok_or_else(|| error("Invalid texture coordinate"))?;
+                // OBJ puts the origin in the bottom left, wgpu in the top left
+                tex_coords.push([u, 1.0 - v]);
+            }
+            "vn" => normals.push(parse_floats(tokens).ok_or_else(|| error("Invalid normal"))?),
+            "f" => {
+                let face = tokens
+                    .map(|token| {
+                        parse_face_vertex(token, positions.len(), tex_coords.len(), normals.len())
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| error("Invalid face"))?;
+                if face.len() < 3 {
+                    return Err(error("Faces need at least 3 vertices"));
+                }
+
+                let group = groups.last_mut().expect("there is always a group");
+                let mut face_indices = Vec::with_capacity(face.len());
+                for key in face {
+                    let (position, tex_coord, normal) = key;
+                    group.has_normals &= normal.is_some();
+                    let next_index = group.vertices.len() as u32;
+                    let index = *group.lookup.entry(key).or_insert(next_index);
+                    if index == next_index {
+                        group.vertices.push(ModelVertex {
+                            position: positions[position],
+                            tex_coords: tex_coord.map_or([0.0; 2], |i| tex_coords[i]),
+                            normals: normal.map_or([0.0; 3], |i| normals[i]),
+                            tangent: [0.0; 4],
+                        });
+                    }
+                    face_indices.push(index);
+                }
+
+                // Polygons are assumed to be convex and split into a fan
+                for i in 1..face_indices.len() - 1 {
+                    group
+                        .indices
+                        .extend([face_indices[0], face_indices[i], face_indices[i + 1]]);
+                }
+            }
+            "o" | "g" => {
+                let material = groups.last().and_then(|group| group.material.clone());
+                groups.push(Group::new(rest.to_string(), material));
+            }
+            "usemtl" => {
+                let name = groups
+                    .last()
+                    .map_or_else(String::new, |group| group.name.clone());
+                groups.push(Group::new(name, Some(rest.to_string())));
+            }
+            "mtllib" => {
+                let mtl_path = base.join(rest);
+                mtl_materials.extend(
+                    load_mtl(&mtl_path)
+                        .with_context(|| format!("Failed to load {}", mtl_path.display()))?,
+                );
+            }
+            // Smoothing groups, lines and points are not supported
+            _ => {}
+        }
+    }
+
+    let device = assets.device().clone();
+    let layout = assets.material_layout().clone();
+    let defaults = DefaultTextures::new(&device, assets.queue())?;
+    let mut materials = Vec::with_capacity(mtl_materials.len() + 1);
+    for material in &mtl_materials {
+        let base_color = material
+            .diffuse_map
+            .as_ref()
+            .map(|map_path| -> anyhow::Result<MaterialTexture> {
+                Ok(MaterialTexture {
+                    texture: assets.load_texture(map_path, TextureRole::Color)?,
+                    sampler: assets.sampler(SamplerDesc {
+                        address_mode_u: material.diffuse_map_address_mode,
+                        address_mode_v: material.diffuse_map_address_mode,
+                        ..Default::default()
+                    }),
+                })
+            })
+            .transpose()?;
+
+        materials.push(ModelMaterial::new(
+            &device,
+            &layout,
+            &defaults,
+            material.name.clone(),
+            material.uniform(),
+            MaterialTextures {
+                base_color,
+                ..Default::default()
+            },
+        ));
+    }
+    let default_material = materials.len();
+    materials.push(ModelMaterial::default_material(&device, &layout, &defaults));
+
+    let meshes = groups
+        .into_iter()
+        .filter(|group| !group.indices.is_empty())
+        .map(|mut group| {
+            let material_index = match &group.material {
+                Some(name) => mtl_materials
+                    .iter()
+                    .position(|material| &material.name == name)
+                    .unwrap_or_else(|| {
+                        log::warn!("{}: unknown material {name}", path.display());
+                        default_material
+                    }),
+                None => default_material,
+            };
+            if !group.has_normals {
+                (group.vertices, group.indices) =
+                    with_flat_normals(&group.vertices, &group.indices);
+            }
+
+            ModelMesh::new(
+                &device,
+                group.name,
+                &group.vertices,
+                &group.indices,
+                material_index,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    // OBJ has no hierarchy, everything is drawn from a single node at the origin
+    let nodes = vec![ModelNode {
+        name: path
+            .file_stem()
+            .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned()),
+        parent: None,
+        local_transform: Mat4::IDENTITY,
+        world_transform: Mat4::IDENTITY,
+        meshes: (0..meshes.len()).collect(),
+    }];
+
+    Ok(Model {
+        meshes,
+        materials,
+        nodes,
+    })
+}
+
+fn load_mtl(path: &Path) -> anyhow::Result<Vec<MtlMaterial>> {
+    let source = std::fs::read_to_string(path)?;
+    let base = path.parent().unwrap_or(Path::new(""));
+    let mut materials: Vec<MtlMaterial> = Vec::new();
+
+    for (line_index, line) in source.lines().enumerate() {
+        let error = |msg: &str| anyhow::anyhow!("{}:{}: {msg}", path.display(), line_index + 1);
+
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let rest = line[keyword.len()..].trim();
+
+        if keyword == "newmtl" {
+            materials.push(MtlMaterial::new(rest.to_string()));
+            continue;
+        }
+        let Some(material) = materials.last_mut() else {
+            return Err(error("Material property before newmtl"));
+        };
+
+        match keyword {
+            "Kd" => material.diffuse = parse_floats(tokens).ok_or_else(|| error("Invalid Kd"))?,
+            "Ks" => material.specular = parse_floats(tokens).ok_or_else(|| error("Invalid Ks"))?,
+            "Ke" => material.emissive = parse_floats(tokens).ok_or_else(|| error("Invalid Ke"))?,
+            "Ns" => {
+                [material.shininess] = parse_floats(tokens).ok_or_else(|| error("Invalid Ns"))?
+            }
+            "d" => [material.dissolve] = parse_floats(tokens).ok_or_else(|| error("Invalid d"))?,
+            "Tr" => {
+                let [transparency] = parse_floats(tokens).ok_or_else(|| error("Invalid Tr"))?;
+                material.dissolve = 1.0 - transparency;
+            }
+            "map_Kd" => {
+                let (address_mode, file) = parse_map(rest).map_err(error)?;
+                material.diffuse_map = Some(base.join(file));
+                if let Some(address_mode) = address_mode {
+                    material.diffuse_map_address_mode = address_mode;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(materials)
+}
+
+/// Splits the first whitespace separated token off `text`, and the rest after it.
+fn split_token(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    match text.find(char::is_whitespace) {
+        Some(end) => (&text[..end], text[end..].trim_start()),
+        None => (text, ""),
+    }
+}
+
+/// Parses the options in front of the file name of a texture map. Only `-clamp`
+/// is used, the others are skipped. The file name is the rest of the line, so
+/// it may contain spaces.
+fn parse_map(mut rest: &str) -> Result<(Option<wgpu::AddressMode>, &str), &'static str> {
+    let mut address_mode = None;
+    loop {
+        let (option, after) = split_token(rest);
+        let (min_arguments, max_arguments) = match option {
+            "-clamp" => {
+                let (value, after) = split_token(after);
+                address_mode = Some(match value {
+                    "on" => wgpu::AddressMode::ClampToEdge,
+                    "off" => wgpu::AddressMode::Repeat,
+                    _ => return Err("Expected on or off after -clamp"),
+                });
+                rest = after;
+                continue;
+            }
+            "-blendu" | "-blendv" | "-boost" | "-cc" | "-texres" | "-bm" | "-imfchan" | "-type" => {
+                (1, 1)
+            }
+            "-mm" => (2, 2),
+            // Offset, scale and turbulence take one to three numbers
+            "-o" | "-s" | "-t" => (1, 3),
+            _ => break,
+        };
+        rest = after;
+        for index in 0..max_arguments {
+            let (argument, after) = split_token(rest);
+            if index >= min_arguments && argument.parse::<f32>().is_err() {
+                break;
+            }
+            if argument.is_empty() {
+                return Err("Missing texture map option value");
+            }
+            rest = after;
+        }
+    }
+
+    if rest.is_empty() {
+        return Err("Missing texture map file");
+    }
+    Ok((address_mode, rest))
+}
+
+/// Parses exactly `N` floats, ignoring anything after them such as the `w` of a vertex.
+fn parse_floats<'a, const N: usize>(mut tokens: impl Iterator<Item = &'a str>) -> Option<[f32; N]> {
+    let mut values = [0.0; N];
+    for value in &mut values {
+        *value = tokens.next()?.parse().ok()?;
+    }
+    Some(values)
+}
+
+/// Resolves one `v`, `v/vt`, `v//vn` or `v/vt/vn` face element, negative
+/// indices count back from the most recent element.
+fn parse_face_vertex(
+    token: &str,
+    position_count: usize,
+    tex_coord_count: usize,
+    normal_count: usize,
+) -> Option<FaceVertex> {
+    let resolve = |index: &str, count: usize| -> Option<usize> {
+        let index: i64 = index.parse().ok()?;
+        let resolved = if index < 0 {
+            count as i64 + index
+        } else {
+            index - 1
+        };
+        (0..count as i64)
+            .contains(&resolved)
+            .then_some(resolved as usize)
+    };
+
+    let mut parts = token.split('/');
+    let position = resolve(parts.next()?, position_count)?;
+    let tex_coord = match parts.next() {
+        None | Some("") => None,
+        Some(index) => Some(resolve(index, tex_coord_count)?),
+    };
+    let normal = match parts.next() {
+        None | Some("") => None,
+        Some(index) => Some(resolve(index, normal_count)?),
+    };
+
+    Some((position, tex_coord, normal))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_mtl(name: &str, source: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join("rs-vulkan-obj-tests");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{name}.mtl"));
+        std::fs::write(&path, source).unwrap();
+        path
+    }
+
+    #[test]
+    fn face_vertex_forms() {
+        assert_eq!(parse_face_vertex("1", 3, 3, 3), Some((0, None, None)));
+        assert_eq!(parse_face_vertex("2/3", 3, 3, 3), Some((1, Some(2), None)));
+        assert_eq!(parse_face_vertex("3//1", 3, 3, 3), Some((2, None, Some(0))));
+        assert_eq!(
+            parse_face_vertex("1/2/3", 3, 3, 3),
+            Some((0, Some(1), Some(2)))
+        );
+    }
+
+    #[test]
+    fn face_vertex_negative_indices_count_from_the_end() {
+        assert_eq!(parse_face_vertex("-1", 3, 0, 0), Some((2, None, None)));
+        assert_eq!(
+            parse_face_vertex("-3/-1/-2", 3, 4, 5),
+            Some((0, Some(3), Some(3)))
+        );
+    }
+
+    #[test]
+    fn face_vertex_rejects_invalid_indices() {
+        // OBJ indices start at one
+        assert_eq!(parse_face_vertex("0", 3, 0, 0), None);
+        assert_eq!(parse_face_vertex("4", 3, 0, 0), None);
+        assert_eq!(parse_face_vertex("-4", 3, 0, 0), None);
+        assert_eq!(parse_face_vertex("1/2", 3, 1, 0), None);
+        assert_eq!(parse_face_vertex("1//1", 3, 0, 0), None);
+        assert_eq!(parse_face_vertex("a", 3, 0, 0), None);
+        assert_eq!(parse_face_vertex("", 3, 0, 0), None);
+    }
+
+    #[test]
+    fn materials_without_specular_are_fully_rough() {
+        let mut material = MtlMaterial::new("matte".to_string());
+        material.diffuse = [0.5, 0.25, 0.125];
+        material.shininess = 1000.0;
+        material.dissolve = 0.5;
+        let uniform = material.uniform();
+
+        assert_eq!(uniform.base_color_factor, [0.5, 0.25, 0.125, 0.5]);
+        assert_eq!(uniform.metallic_factor, 0.0);
+        assert_eq!(uniform.roughness_factor, 1.0);
+    }
+
+    #[test]
+    fn specular_exponent_becomes_roughness() {
+        let mut material = MtlMaterial::new("glossy".to_string());
+        material.specular = [0.5; 3];
+
+        material.shininess = 0.0;
+        assert_eq!(material.uniform().roughness_factor, 1.0);
+        material.shininess = 98.0;
+        assert!((material.uniform().roughness_factor - 0.02f32.sqrt()).abs() < 1e-6);
+        // Sharper highlights always mean smoother surfaces
+        let rough = material.uniform().roughness_factor;
+        material.shininess = 1000.0;
+        assert!(material.uniform().roughness_factor < rough);
+    }
+
+    #[test]
+    fn mtl_properties_and_clamp_option() {
+        let path = write_mtl(
+            "properties",
+            "newmtl first\n\
+             Kd 0.1 0.2 0.3\n\
+             Ks 1 1 1\n\
+             Ns 50 # comment\n\
+             Tr 0.25\n\
+             map_Kd -clamp on -bm 1.0 diffuse.png\n\
+             newmtl second\n\
+             map_Kd -clamp off other.png\n",
+        );
+        let materials = load_mtl(&path).unwrap();
+
+        assert_eq!(materials.len(), 2);
+        let first = &materials[0];
+        assert_eq!(first.name, "first");
+        assert_eq!(first.diffuse, [0.1, 0.2, 0.3]);
+        assert_eq!(first.specular, [1.0; 3]);
+        assert_eq!(first.shininess, 50.0);
+        assert_eq!(first.dissolve, 0.75);
+        assert_eq!(first.diffuse_map, Some(path.with_file_name("diffuse.png")));
+        assert_eq!(
+            first.diffuse_map_address_mode,
+            wgpu::AddressMode::ClampToEdge
+        );
+        assert_eq!(
+            materials[1].diffuse_map_address_mode,
+            wgpu::AddressMode::Repeat
+        );
+    }
+
+    #[test]
+    fn map_file_names_may_contain_spaces() {
+        assert_eq!(parse_map("my texture.png"), Ok((None, "my texture.png")));
+        assert_eq!(
+            parse_map("-o 0.5 0.5 -s 2 -clamp on -mm 0 1 my texture.png"),
+            Ok((Some(wgpu::AddressMode::ClampToEdge), "my texture.png"))
+        );
+        // Only the first number of an offset is required
+        assert_eq!(parse_map("-o 1 tex.png"), Ok((None, "tex.png")));
+        assert!(parse_map("-clamp on").is_err());
+        assert!(parse_map("-bm").is_err());
+
+        let path = write_mtl(
+            "spaced_map",
+            "newmtl spaced\nmap_Kd -bm 1.0 my diffuse texture.png\n",
+        );
+        let materials = load_mtl(&path).unwrap();
+        assert_eq!(
+            materials[0].diffuse_map,
+            Some(path.with_file_name("my diffuse texture.png"))
+        );
+    }
+
+    #[test]
+    fn mtl_errors_point_at_the_line() {
+        let path = write_mtl(
+            "clamp_error",
+            "newmtl broken\nmap_Kd -clamp maybe diffuse.png\n",
+        );
+        let err = load_mtl(&path).err().unwrap().to_string();
+        assert!(
+            err.starts_with(&format!("{}:2:", path.display())),
+            "unexpected error: {err}"
+        );
+
+        let path = write_mtl("before_newmtl", "Kd 1 1 1\n");
+        let err = load_mtl(&path).err().unwrap().to_string();
+        assert!(err.contains("before newmtl"), "unexpected error: {err}");
+    }
+}
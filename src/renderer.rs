@@ -0,0 +1,977 @@
+use anyhow::Context;
+use bevy_math::{Mat3, Mat4, Quat, Vec2, Vec3, Vec4};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    antialiasing::{self, AntiAliasing, Fxaa, MOTION_FORMAT, Taa},
+    assets::{AssetServer, Handle},
+    bloom::{Bloom, BloomSettings},
+    camera::{Camera, Projection},
+    environment::{EnvironmentLighting, Skybox},
+    light::{Light, LightId, Lights},
+    model::{self, DrawLight, DrawModel, DrawShadow, ModelVertex, Vertex},
+    scene::{InstanceDesc, Scene},
+    shader,
+    texture::{self, Texture},
+    tonemap::{self, HDR_FORMAT, Tonemapper, Tonemapping},
+};
+
+#[derive(Debug)]
+struct Instance {
+    translation: Vec3,
+    rotation: Quat,
+    scale: Vec3,
+}
+
+impl From<&InstanceDesc> for Instance {
+    fn from(desc: &InstanceDesc) -> Self {
+        Self {
+            translation: desc.translation.into(),
+            rotation: desc.rotation(),
+            scale: desc.scale.into(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct InstanceRaw {
+    transform: [[f32; 4]; 4],
+    normal: [[f32; 3]; 3],
+}
+
+impl Instance {
+    fn to_mat4(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+}
+
+/// Box around the box from `min` to `max` after transforming it.
+fn transform_bounds(transform: Mat4, min: Vec3, max: Vec3) -> (Vec3, Vec3) {
+    (0..8).fold(
+        (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+        |(bounds_min, bounds_max), corner| {
+            let corner = Vec3::select(
+                bevy_math::BVec3::new(corner & 1 != 0, corner & 2 != 0, corner & 4 != 0),
+                max,
+                min,
+            );
+            let corner = transform.transform_point3(corner);
+            (bounds_min.min(corner), bounds_max.max(corner))
+        },
+    )
+}
+
+impl InstanceRaw {
+    fn new(transform: Mat4) -> Self {
+        Self {
+            transform: transform.to_cols_array_2d(),
+            // Inverse transpose keeps normals perpendicular under non-uniform scaling
+            normal: Mat3::from_mat4(transform)
+                .inverse()
+                .transpose()
+                .to_cols_array_2d(),
+        }
+    }
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: 0,
+                    shader_location: 5,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: 16,
+                    shader_location: 6,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: 32,
+                    shader_location: 7,
+                },
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::Float32x4,
+                    offset: 48,
+                    shader_location: 8,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 19]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 22]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CameraUniform {
+    view_position: Vec4,
+    view_projection: Mat4,
+    /// Turns clip space positions back into world space, used to find the view ray of the sky.
+    inverse_view_projection: Mat4,
+    /// `view_projection` without the TAA jitter, motion vectors compare it with the previous one.
+    unjittered_view_projection: Mat4,
+    previous_view_projection: Mat4,
+}
+
+impl CameraUniform {
+    fn new(camera: &Camera, projection: &Projection) -> Self {
+        let mut uniform = Self::default();
+        uniform.update(camera, projection);
+        // Nothing has moved before the first frame
+        uniform.previous_view_projection = uniform.unjittered_view_projection;
+        uniform
+    }
+
+    fn update(&mut self, camera: &Camera, projection: &Projection) {
+        let view = camera.to_mat4();
+        self.view_position = camera.position.extend(1.0);
+        self.view_projection = projection.to_mat4() * view;
+        self.inverse_view_projection = self.view_projection.inverse();
+        self.previous_view_projection = self.unjittered_view_projection;
+        self.unjittered_view_projection = projection.to_unjittered_mat4() * view;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_render_pipeline(
+    label: Option<&str>,
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    depth_format: Option<wgpu::TextureFormat>,
+    sample_count: u32,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    shader: wgpu::ShaderModuleDescriptor,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(shader);
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label,
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: Some("vs_main"),
+            compilation_options: Default::default(),
+            buffers: vertex_layouts,
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: Some("fs_main"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            targets: &[Some(color_format.into()), Some(MOTION_FORMAT.into())],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            unclipped_depth: false,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false,
+        },
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
+            format,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multiview: None,
+        cache: None,
+    })
+}
+
+/// Intermediate data drawn instead of or on top of the shaded scene.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DebugView {
+    #[default]
+    None,
+    /// Heatmap of how many lights reach the cluster each pixel falls into.
+    LightClusters,
+    /// Tints each shadow cascade of the first directional light with its own color.
+    ShadowCascades,
+}
+
+/// Startup options for a [`Renderer`].
+#[derive(Debug, Clone)]
+pub struct RendererConfig {
+    /// Models, lights and camera to draw, the first model is also used to draw the light.
+    pub scene: Scene,
+    /// Number of MSAA samples per pixel, 1 disables multisampling. Has to be one of
+    /// [`supported_sample_counts`], the window and headless renderers fall back on their own.
+    pub sample_count: u32,
+    /// Maximum anisotropic filtering samples for material textures, 1 disables it.
+    pub anisotropy: u16,
+    pub debug_view: DebugView,
+    pub tonemapping: Tonemapping,
+    /// Exposure in stops, the scene is scaled by `2^exposure` before tonemapping.
+    pub exposure: f32,
+    /// Glow around bright parts of the scene, `None` disables it.
+    pub bloom: Option<BloomSettings>,
+    /// Post-process anti-aliasing, independent of `sample_count`.
+    pub anti_aliasing: AntiAliasing,
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            scene: Scene::default(),
+            sample_count: 1,
+            anisotropy: 16,
+            debug_view: DebugView::None,
+            tonemapping: Tonemapping::default(),
+            exposure: 0.0,
+            bloom: Some(BloomSettings::default()),
+            anti_aliasing: AntiAliasing::None,
+        }
+    }
+}
+
+/// A loaded model together with the instances it is drawn with.
+struct SceneModel {
+    model: Handle<model::Model>,
+    instance_count: u32,
+    /// Instance transforms combined with the node transform, for every node with meshes.
+    node_instance_buffers: Vec<(usize, wgpu::Buffer)>,
+}
+
+/// Multisampled color attachment that gets resolved into the HDR or motion target,
+/// `None` when MSAA is disabled.
+fn create_msaa_texture(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    size: wgpu::Extent3d,
+    sample_count: u32,
+) -> Option<wgpu::TextureView> {
+    if sample_count <= 1 {
+        return None;
+    }
+
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("MSAA Color Texture"),
+        size,
+        mip_level_count: 1,
+        sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+
+    Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
+}
+
+/// MSAA sample counts the adapter can use for both the HDR target and the depth buffer,
+/// in ascending order and always including 1.
+pub fn supported_sample_counts(adapter: &wgpu::Adapter) -> Vec<u32> {
+    // Without adapter specific format features only the counts WebGPU guarantees are allowed
+    let features = adapter.features();
+    let flags = |format: wgpu::TextureFormat| {
+        if features.contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES) {
+            adapter.get_texture_format_features(format).flags
+        } else {
+            format.guaranteed_format_features(features).flags
+        }
+    };
+    let color = flags(HDR_FORMAT);
+    let depth = flags(Texture::DEPTH_FORMAT);
+
+    [1, 2, 4, 8]
+        .into_iter()
+        .filter(|&count| {
+            count == 1
+                || (color.sample_count_supported(count) && depth.sample_count_supported(count))
+        })
+        .collect()
+}
+
+/// The requested sample count if the adapter supports it, otherwise the highest
+/// supported count below it.
+pub(crate) fn select_sample_count(adapter: &wgpu::Adapter, requested: u32) -> u32 {
+    let sample_count = supported_sample_counts(adapter)
+        .into_iter()
+        .filter(|&count| count <= requested)
+        .max()
+        .unwrap_or(1);
+    if sample_count != requested {
+        log::warn!(
+            "{requested}x MSAA is not supported by {}, falling back to {sample_count}x",
+            adapter.get_info().name
+        );
+    }
+
+    sample_count
+}
+
+/// Requests a device with every compressed texture format family the adapter
+/// supports, textures in other formats are decoded on the CPU. Adapter specific
+/// format features are enabled too, they unlock 2x and 8x MSAA.
+pub(crate) async fn request_device(
+    adapter: &wgpu::Adapter,
+) -> anyhow::Result<(wgpu::Device, wgpu::Queue)> {
+    let features =
+        texture::COMPRESSION_FEATURES | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES;
+    let device = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                required_features: adapter.features() & features,
+                required_limits: wgpu::Limits::default(),
+                memory_hints: Default::default(),
+            },
+            None,
+        )
+        .await?;
+
+    Ok(device)
+}
+
+/// Owns every GPU resource needed to draw the scene, independent of any window.
+///
+/// The host is responsible for providing a texture view to draw into, see
+/// [`Renderer::render`], and for calling [`Renderer::resize`] when it changes size.
+pub struct Renderer {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    render_pipeline: wgpu::RenderPipeline,
+    lights: Lights,
+    light_render_pipeline: wgpu::RenderPipeline,
+    camera: Camera,
+    camera_uniform: CameraUniform,
+    camera_bind_group: wgpu::BindGroup,
+    camera_buffer: wgpu::Buffer,
+    projection: Projection,
+    clear_color: wgpu::Color,
+    debug_view: DebugView,
+    tonemapping: Tonemapping,
+    exposure: f32,
+    tonemapper: Tonemapper,
+    bloom_settings: Option<BloomSettings>,
+    bloom: Bloom,
+    anti_aliasing: AntiAliasing,
+    taa: Taa,
+    /// Whether bloom and tonemapping read the TAA output instead of the HDR target.
+    taa_output_bound: bool,
+    fxaa: Fxaa,
+    environment_lighting: EnvironmentLighting,
+    skybox: Option<Skybox>,
+    color_format: wgpu::TextureFormat,
+    size: wgpu::Extent3d,
+    sample_count: u32,
+    msaa_texture: Option<wgpu::TextureView>,
+    msaa_motion_texture: Option<wgpu::TextureView>,
+    hdr_texture: wgpu::Texture,
+    hdr_view: wgpu::TextureView,
+    motion_texture: wgpu::TextureView,
+    depth_texture: Texture,
+    models: Vec<SceneModel>,
+    assets: AssetServer,
+}
+
+impl Renderer {
+    /// Creates a renderer drawing into targets of `color_format` with the given size.
+    pub fn new(
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        color_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        config: &RendererConfig,
+    ) -> anyhow::Result<Self> {
+        let scene = &config.scene;
+        anyhow::ensure!(!scene.models.is_empty(), "At least one model is required");
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+
+        let mut assets = AssetServer::new(&device, &queue, config.anisotropy);
+
+        let mut lights = Lights::new(&device, &[ModelVertex::desc(), InstanceRaw::desc()]);
+        for light in &scene.lights {
+            lights.add(light.into());
+        }
+
+        let camera = Camera::new(
+            scene.camera.position.into(),
+            scene.camera.yaw.to_radians(),
+            scene.camera.pitch.to_radians(),
+        );
+        let projection = Projection::new(
+            size.width,
+            size.height,
+            scene.camera.fovy.to_radians(),
+            scene.camera.znear,
+            scene.camera.zfar,
+        );
+        let camera_uniform = CameraUniform::new(&camera, &projection);
+
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera Buffer"),
+            contents: bytemuck::bytes_of(&camera_uniform),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let camera_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Camera Bind Group Layout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<CameraUniform>() as u64,
+                        ),
+                    },
+                    count: None,
+                }],
+            });
+
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Camera Bind Group"),
+            layout: &camera_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            }],
+        });
+
+        let sample_count = config.sample_count;
+        let msaa_texture = create_msaa_texture(&device, HDR_FORMAT, size, sample_count);
+        let msaa_motion_texture = create_msaa_texture(&device, MOTION_FORMAT, size, sample_count);
+        let hdr_texture = tonemap::create_hdr_texture(&device, size);
+        let hdr_view = hdr_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let motion_texture = antialiasing::create_motion_texture(&device, size);
+        let bloom = Bloom::new(&device, &hdr_view, size);
+        let tonemapper = Tonemapper::new(&device, color_format, &hdr_view, &bloom);
+        let taa = Taa::new(&device, &hdr_view, &motion_texture, size);
+        let fxaa = Fxaa::new(&device, color_format, size);
+        let depth_texture =
+            Texture::create_depth_texture(&device, size, sample_count, "Depth Texture");
+
+        let [r, g, b, a] = scene.clear_color;
+        let environment = match &scene.environment {
+            Some(path) => assets.load_environment(path)?,
+            // Without an environment the clear color lights the scene from every direction
+            None => assets.create_constant_environment([r, g, b].map(|c| c as f32)),
+        };
+        let environment_lighting = EnvironmentLighting::new(&device, &queue, &environment);
+        let skybox = scene.environment.is_some().then(|| {
+            Skybox::new(
+                &device,
+                &environment,
+                &camera_bind_group_layout,
+                HDR_FORMAT,
+                Texture::DEPTH_FORMAT,
+                sample_count,
+            )
+        });
+
+        let shader = wgpu::ShaderModuleDescriptor {
+            label: Some("Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                shader::with_preludes(
+                    &[shader::MOTION],
+                    include_str!("../assets/shaders/draw.wgsl"),
+                )
+                .into(),
+            ),
+        };
+
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Render Pipeline Layout"),
+                bind_group_layouts: &[
+                    assets.material_layout(),
+                    &camera_bind_group_layout,
+                    lights.bind_group_layout(),
+                    &environment_lighting.bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let render_pipeline = create_render_pipeline(
+            Some("Render Pipeline"),
+            &device,
+            &render_pipeline_layout,
+            HDR_FORMAT,
+            Some(Texture::DEPTH_FORMAT),
+            sample_count,
+            &[ModelVertex::desc(), InstanceRaw::desc()],
+            shader,
+        );
+
+        let light_render_pipeline = {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Light Pipeline Layout"),
+                bind_group_layouts: &[&camera_bind_group_layout, lights.bind_group_layout()],
+                push_constant_ranges: &[],
+            });
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("Light Shader"),
+                source: wgpu::ShaderSource::Wgsl(
+                    shader::with_preludes(
+                        &[shader::MOTION],
+                        include_str!("../assets/shaders/light.wgsl"),
+                    )
+                    .into(),
+                ),
+            };
+            create_render_pipeline(
+                Some("Light Render Pipeline"),
+                &device,
+                &layout,
+                HDR_FORMAT,
+                Some(Texture::DEPTH_FORMAT),
+                sample_count,
+                &[ModelVertex::desc()],
+                shader,
+            )
+        };
+
+        let mut scene_min = Vec3::splat(f32::INFINITY);
+        let mut scene_max = Vec3::splat(f32::NEG_INFINITY);
+        let models = scene
+            .models
+            .iter()
+            .map(|desc| {
+                let model = assets
+                    .load_model(&desc.path)
+                    .with_context(|| format!("Failed to load model {}", desc.path.display()))?;
+
+                let instance_transforms = desc
+                    .instances
+                    .iter()
+                    .map(|instance| Instance::from(instance).to_mat4())
+                    .collect::<Vec<_>>();
+
+                let node_instance_buffers = model
+                    .nodes
+                    .iter()
+                    .enumerate()
+                    // Empty buffers cannot be bound, scenes built in code may have no instances
+                    .filter(|(_, node)| !node.meshes.is_empty() && !instance_transforms.is_empty())
+                    .map(|(index, node)| {
+                        let transforms = instance_transforms
+                            .iter()
+                            .map(|transform| *transform * node.world_transform)
+                            .collect::<Vec<_>>();
+                        for transform in &transforms {
+                            for &mesh_index in &node.meshes {
+                                let mesh = &model.meshes[mesh_index];
+                                let (min, max) = transform_bounds(*transform, mesh.min, mesh.max);
+                                scene_min = scene_min.min(min);
+                                scene_max = scene_max.max(max);
+                            }
+                        }
+                        let instance_data = transforms
+                            .into_iter()
+                            .map(InstanceRaw::new)
+                            .collect::<Vec<_>>();
+                        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                            label: Some("Instance Buffer"),
+                            contents: bytemuck::cast_slice(&instance_data),
+                            usage: wgpu::BufferUsages::VERTEX,
+                        });
+                        (index, buffer)
+                    })
+                    .collect();
+
+                Ok(SceneModel {
+                    model,
+                    instance_count: instance_transforms.len() as u32,
+                    node_instance_buffers,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        lights.set_shadow_bounds(scene_min, scene_max);
+        lights.set_view(&camera, &projection, (width, height), config.debug_view);
+        lights.upload(&device, &queue);
+
+        let mut renderer = Self {
+            device,
+            queue,
+            render_pipeline,
+            lights,
+            light_render_pipeline,
+            camera,
+            camera_uniform,
+            camera_bind_group,
+            camera_buffer,
+            projection,
+            clear_color: wgpu::Color { r, g, b, a },
+            debug_view: config.debug_view,
+            tonemapping: config.tonemapping,
+            exposure: config.exposure,
+            tonemapper,
+            bloom_settings: config.bloom,
+            bloom,
+            anti_aliasing: config.anti_aliasing,
+            taa,
+            taa_output_bound: false,
+            fxaa,
+            environment_lighting,
+            skybox,
+            color_format,
+            size,
+            sample_count,
+            msaa_texture,
+            msaa_motion_texture,
+            hdr_texture,
+            hdr_view,
+            motion_texture,
+            depth_texture,
+            models,
+            assets,
+        };
+        renderer.upload_post_processing();
+
+        Ok(renderer)
+    }
+
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    pub fn color_format(&self) -> wgpu::TextureFormat {
+        self.color_format
+    }
+
+    pub fn size(&self) -> wgpu::Extent3d {
+        self.size
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// Cache of every model and texture loaded for the scene.
+    pub fn assets(&self) -> &AssetServer {
+        &self.assets
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
+
+    /// Adds a light, it is drawn from the next [`Renderer::update`] on.
+    pub fn add_light(&mut self, light: Light) -> LightId {
+        self.lights.add(light)
+    }
+
+    pub fn remove_light(&mut self, id: LightId) -> Option<Light> {
+        self.lights.remove(id)
+    }
+
+    pub fn light(&self, id: LightId) -> Option<&Light> {
+        self.lights.get(id)
+    }
+
+    /// Changes to the light are uploaded by the next [`Renderer::update`].
+    pub fn light_mut(&mut self, id: LightId) -> Option<&mut Light> {
+        self.lights.get_mut(id)
+    }
+
+    pub fn lights(&self) -> impl Iterator<Item = (LightId, &Light)> {
+        self.lights.iter()
+    }
+
+    pub fn debug_view(&self) -> DebugView {
+        self.debug_view
+    }
+
+    /// Takes effect with the next [`Renderer::update`].
+    pub fn set_debug_view(&mut self, debug_view: DebugView) {
+        self.debug_view = debug_view;
+    }
+
+    pub fn tonemapping(&self) -> Tonemapping {
+        self.tonemapping
+    }
+
+    /// Takes effect with the next [`Renderer::update`].
+    pub fn set_tonemapping(&mut self, tonemapping: Tonemapping) {
+        self.tonemapping = tonemapping;
+    }
+
+    /// Exposure in stops, see [`RendererConfig::exposure`].
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    /// Takes effect with the next [`Renderer::update`].
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.exposure = exposure;
+    }
+
+    pub fn bloom(&self) -> Option<BloomSettings> {
+        self.bloom_settings
+    }
+
+    /// Enables bloom with the given settings or disables it with `None`,
+    /// takes effect with the next [`Renderer::update`].
+    pub fn set_bloom(&mut self, bloom: Option<BloomSettings>) {
+        self.bloom_settings = bloom;
+    }
+
+    pub fn anti_aliasing(&self) -> AntiAliasing {
+        self.anti_aliasing
+    }
+
+    /// Takes effect with the next [`Renderer::update`].
+    pub fn set_anti_aliasing(&mut self, anti_aliasing: AntiAliasing) {
+        self.anti_aliasing = anti_aliasing;
+    }
+
+    /// Zero sizes are ignored, no attachment can be empty.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        self.msaa_texture =
+            create_msaa_texture(&self.device, HDR_FORMAT, self.size, self.sample_count);
+        self.msaa_motion_texture =
+            create_msaa_texture(&self.device, MOTION_FORMAT, self.size, self.sample_count);
+        self.hdr_texture = tonemap::create_hdr_texture(&self.device, self.size);
+        self.hdr_view = self
+            .hdr_texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        self.motion_texture = antialiasing::create_motion_texture(&self.device, self.size);
+        self.bloom.resize(&self.device, &self.hdr_view, self.size);
+        self.tonemapper
+            .set_input(&self.device, &self.hdr_view, &self.bloom);
+        self.taa_output_bound = false;
+        self.taa.resize(
+            &self.device,
+            &self.hdr_view,
+            &self.motion_texture,
+            self.size,
+        );
+        self.fxaa.resize(&self.device, self.size);
+        self.depth_texture = Texture::create_depth_texture(
+            &self.device,
+            self.size,
+            self.sample_count,
+            "Depth Texture",
+        );
+        self.projection.resize(width, height);
+    }
+
+    /// Uploads the current camera and any lights changed since the last frame.
+    /// `dt` is the time since the previous update, nothing in the renderer is
+    /// animated by it at the moment.
+    pub fn update(&mut self, _dt: std::time::Duration) {
+        let jitter = match self.anti_aliasing {
+            AntiAliasing::Taa => self.taa.prepare(&self.queue, self.size),
+            AntiAliasing::None | AntiAliasing::Fxaa => {
+                self.taa.invalidate();
+                Vec2::ZERO
+            }
+        };
+        self.projection.set_jitter(jitter);
+        self.bind_post_processing_input();
+        self.camera_uniform.update(&self.camera, &self.projection);
+        self.queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[self.camera_uniform]),
+        );
+
+        self.lights.set_view(
+            &self.camera,
+            &self.projection,
+            (self.size.width, self.size.height),
+            self.debug_view,
+        );
+        self.lights.upload(&self.device, &self.queue);
+        self.upload_post_processing();
+    }
+
+    /// Points bloom and tonemapping at the history TAA resolves into this frame, or
+    /// back at the HDR target once TAA is disabled.
+    fn bind_post_processing_input(&mut self) {
+        let taa = self.anti_aliasing == AntiAliasing::Taa;
+        if !taa && !self.taa_output_bound {
+            return;
+        }
+        let input = if taa {
+            self.taa.output()
+        } else {
+            &self.hdr_view
+        };
+        self.bloom.set_input(&self.device, input);
+        self.tonemapper.set_input(&self.device, input, &self.bloom);
+        self.taa_output_bound = taa;
+    }
+
+    fn upload_post_processing(&mut self) {
+        let bloom_intensity = match &self.bloom_settings {
+            Some(settings) => {
+                self.bloom.upload(&self.queue, settings);
+                self.bloom.composite_scale(settings)
+            }
+            None => 0.0,
+        };
+        self.tonemapper.upload(
+            &self.queue,
+            self.tonemapping,
+            self.exposure,
+            bloom_intensity,
+        );
+    }
+
+    /// Draws one frame into `view` and submits it to the queue.
+    pub fn render(&self, view: &wgpu::TextureView) {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Render Encoder"),
+            });
+        self.encode(&mut encoder, view);
+        self.queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    /// Records all render passes for one frame into `encoder`, targeting `view`.
+    ///
+    /// The scene is drawn into an HDR target first, `view` only receives the result
+    /// of anti-aliasing, bloom and tonemapping.
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        self.lights.encode_shadows(encoder, |render_pass| {
+            for scene_model in &self.models {
+                for (node_index, instance_buffer) in &scene_model.node_instance_buffers {
+                    render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+                    render_pass.draw_shadow_node_instanced(
+                        &scene_model.model,
+                        &scene_model.model.nodes[*node_index],
+                        0..scene_model.instance_count,
+                    );
+                }
+            }
+        });
+        self.lights.cull(encoder);
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Render Pass"),
+                color_attachments: &[
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: self.msaa_texture.as_ref().unwrap_or(&self.hdr_view),
+                        resolve_target: self.msaa_texture.as_ref().map(|_| &self.hdr_view),
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(self.clear_color),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    }),
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: self
+                            .msaa_motion_texture
+                            .as_ref()
+                            .unwrap_or(&self.motion_texture),
+                        resolve_target: self
+                            .msaa_motion_texture
+                            .as_ref()
+                            .map(|_| &self.motion_texture),
+                        ops: wgpu::Operations {
+                            // Pixels nothing is drawn to have not moved
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    }),
+                ],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+
+            render_pass.set_pipeline(&self.render_pipeline);
+            render_pass.set_bind_group(3, &self.environment_lighting.bind_group, &[]);
+            for scene_model in &self.models {
+                for (node_index, instance_buffer) in &scene_model.node_instance_buffers {
+                    render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+                    render_pass.draw_node_instanced(
+                        &scene_model.model,
+                        &scene_model.model.nodes[*node_index],
+                        0..scene_model.instance_count,
+                        &self.camera_bind_group,
+                        self.lights.bind_group(),
+                    );
+                }
+            }
+
+            // Every light gets a gizmo instance, the shader hides the directional ones
+            if self.lights.len() > 0 {
+                render_pass.set_pipeline(&self.light_render_pipeline);
+                render_pass.draw_light_model_instanced(
+                    &self.models[0].model,
+                    0..self.lights.len() as u32,
+                    &self.camera_bind_group,
+                    self.lights.bind_group(),
+                );
+            }
+
+            if let Some(skybox) = &self.skybox {
+                skybox.draw(&mut render_pass, &self.camera_bind_group);
+            }
+        }
+
+        if self.anti_aliasing == AntiAliasing::Taa {
+            self.taa.encode(encoder);
+        }
+        if self.bloom_settings.is_some() {
+            self.bloom.encode(encoder);
+        }
+        if self.anti_aliasing == AntiAliasing::Fxaa {
+            self.tonemapper.encode(encoder, self.fxaa.input());
+            self.fxaa.encode(encoder, view);
+        } else {
+            self.tonemapper.encode(encoder, view);
+        }
+    }
+}
@@ -0,0 +1,514 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use bevy_math::{EulerRot, Quat, Vec3};
+use serde::Deserialize;
+
+const NUM_INSTANCES_PER_ROW: u32 = 10;
+/// Distance between neighboring instances of the grid.
+const GRID_SPACING: f32 = 3.0;
+
+/// Declarative description of everything the renderer draws, loaded from a RON file.
+///
+/// ```ron
+/// Scene(
+///     clear_color: (0.1, 0.2, 0.3, 1.0),
+///     environment: Some("../environments/sky.hdr"),
+///     camera: (position: (0.0, 5.0, 10.0), yaw: -180.0, pitch: -20.0),
+///     models: [
+///         (path: "../models/Dice.glb", instances: [(translation: (0.0, 0.0, 0.0))]),
+///     ],
+///     lights: [
+///         (kind: Point, position: (2.0, 2.0, 2.0), intensity: 10.0, shadow: Some((resolution: 512))),
+///         (kind: Directional, direction: (-1.0, -1.0, -2.0), intensity: 2.0, shadow: Some(())),
+///         (kind: Spot, position: (0.0, 0.0, 5.0), inner_cone_angle: 20.0, outer_cone_angle: 30.0),
+///     ],
+/// )
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scene {
+    #[serde(default = "default_clear_color")]
+    pub clear_color: [f64; 4],
+    /// Equirectangular `.hdr` or `.exr` image drawn as the sky instead of `clear_color`
+    /// and lighting the scene, without one the clear color is used as uniform ambient light.
+    #[serde(default)]
+    pub environment: Option<PathBuf>,
+    #[serde(default)]
+    pub camera: CameraDesc,
+    pub models: Vec<ModelDesc>,
+    #[serde(default)]
+    pub lights: Vec<LightDesc>,
+}
+
+/// Initial camera pose and projection, angles are in degrees.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct CameraDesc {
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    pub fovy: f32,
+    pub znear: f32,
+    pub zfar: f32,
+}
+
+impl Default for CameraDesc {
+    fn default() -> Self {
+        Self {
+            position: [0.0, 5.0, 10.0],
+            yaw: -180.0,
+            pitch: -20.0,
+            fovy: 90.0,
+            znear: 0.1,
+            zfar: 100.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelDesc {
+    /// Relative paths are resolved against the directory of the scene file.
+    pub path: PathBuf,
+    /// Must not be empty, leave it out for a single untransformed instance.
+    #[serde(
+        default = "default_instances",
+        deserialize_with = "deserialize_instances"
+    )]
+    pub instances: Vec<InstanceDesc>,
+}
+
+/// Placement of a single model instance, `rotation` holds XYZ Euler angles in degrees.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct InstanceDesc {
+    pub translation: [f32; 3],
+    pub rotation: [f32; 3],
+    pub scale: [f32; 3],
+}
+
+impl Default for InstanceDesc {
+    fn default() -> Self {
+        Self {
+            translation: [0.0; 3],
+            rotation: [0.0; 3],
+            scale: [1.0; 3],
+        }
+    }
+}
+
+impl InstanceDesc {
+    pub fn rotation(&self) -> Quat {
+        let [x, y, z] = self.rotation.map(f32::to_radians);
+        Quat::from_euler(EulerRot::XYZ, x, y, z)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum LightKind {
+    Point,
+    Directional,
+    Spot,
+}
+
+/// A light in the units of `KHR_lights_punctual`, `intensity` is in candela
+/// for point and spot lights and in lux for directional lights.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LightDesc {
+    #[serde(default = "default_light_kind")]
+    pub kind: LightKind,
+    /// Ignored by directional lights.
+    #[serde(default)]
+    pub position: [f32; 3],
+    /// Direction the light shines in, ignored by point lights.
+    #[serde(default = "default_light_direction")]
+    pub direction: [f32; 3],
+    #[serde(default = "default_light_color")]
+    pub color: [f32; 3],
+    #[serde(default = "default_light_intensity")]
+    pub intensity: f32,
+    /// Distance at which the light has faded out, estimated from the intensity when missing.
+    #[serde(default)]
+    pub range: Option<f32>,
+    /// Spot cone angles from the direction in degrees, the light fades out between them.
+    #[serde(default)]
+    pub inner_cone_angle: f32,
+    #[serde(default = "default_outer_cone_angle")]
+    pub outer_cone_angle: f32,
+    /// Casts shadows, point lights render them into the six faces of a cube.
+    #[serde(default)]
+    pub shadow: Option<ShadowDesc>,
+}
+
+/// Shadow map settings of a light, `bias` and `normal_offset` are world space distances.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct ShadowDesc {
+    pub resolution: u32,
+    pub bias: f32,
+    pub normal_offset: f32,
+    /// Slices of the camera view with their own shadow map, only used by directional lights.
+    pub cascades: u32,
+}
+
+impl Default for ShadowDesc {
+    fn default() -> Self {
+        Self {
+            resolution: 2048,
+            bias: 0.02,
+            normal_offset: 0.05,
+            cascades: 4,
+        }
+    }
+}
+
+fn default_clear_color() -> [f64; 4] {
+    [0.1, 0.2, 0.3, 1.0]
+}
+
+fn default_instances() -> Vec<InstanceDesc> {
+    vec![InstanceDesc::default()]
+}
+
+/// Rejects empty lists while parsing, so the error points at the list in the file.
+fn deserialize_instances<'de, D>(deserializer: D) -> Result<Vec<InstanceDesc>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let instances = Vec::<InstanceDesc>::deserialize(deserializer)?;
+    if instances.is_empty() {
+        return Err(serde::de::Error::invalid_length(
+            0,
+            &"at least one instance",
+        ));
+    }
+    Ok(instances)
+}
+
+fn default_light_kind() -> LightKind {
+    LightKind::Point
+}
+
+fn default_light_direction() -> [f32; 3] {
+    [0.0, 0.0, -1.0]
+}
+
+fn default_outer_cone_angle() -> f32 {
+    45.0
+}
+
+fn default_light_color() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn default_light_intensity() -> f32 {
+    1.0
+}
+
+impl Scene {
+    /// Reads a scene from a RON file, errors point at the offending line and column.
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read scene {}", path.display()))?;
+        let mut scene: Scene =
+            ron::from_str(&source).map_err(|err| anyhow::anyhow!("{}:{err}", path.display()))?;
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        for model in &mut scene.models {
+            model.path = base.join(&model.path);
+        }
+        if let Some(environment) = &mut scene.environment {
+            *environment = base.join(&*environment);
+        }
+
+        scene.validate()?;
+
+        Ok(scene)
+    }
+
+    /// The classic grid of instances for each of the given models, side by side along X.
+    pub fn from_models(models: impl IntoIterator<Item = PathBuf>) -> Self {
+        Self {
+            clear_color: default_clear_color(),
+            environment: None,
+            camera: CameraDesc::default(),
+            models: models
+                .into_iter()
+                .enumerate()
+                .map(|(index, path)| ModelDesc {
+                    path,
+                    instances: grid_instances(index),
+                })
+                .collect(),
+            lights: vec![LightDesc {
+                kind: LightKind::Point,
+                position: [2.0, 2.0, 2.0],
+                direction: default_light_direction(),
+                color: default_light_color(),
+                intensity: 10.0,
+                range: None,
+                inner_cone_angle: 0.0,
+                outer_cone_angle: default_outer_cone_angle(),
+                shadow: None,
+            }],
+        }
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            !self.models.is_empty(),
+            "Scene must contain at least one model"
+        );
+
+        for model in &self.models {
+            anyhow::ensure!(
+                model.path.is_file(),
+                "Model {} does not exist",
+                model.path.display()
+            );
+        }
+
+        if let Some(environment) = &self.environment {
+            anyhow::ensure!(
+                environment.is_file(),
+                "Environment {} does not exist",
+                environment.display()
+            );
+        }
+
+        for light in &self.lights {
+            anyhow::ensure!(
+                light.intensity >= 0.0,
+                "Light intensity must not be negative, got {}",
+                light.intensity
+            );
+            if let Some(range) = light.range {
+                anyhow::ensure!(range > 0.0, "Light range must be positive, got {range}");
+            }
+            if light.kind != LightKind::Point {
+                anyhow::ensure!(
+                    Vec3::from(light.direction).length_squared() > 0.0,
+                    "{:?} light direction must not be zero",
+                    light.kind
+                );
+            }
+            if light.kind == LightKind::Spot {
+                anyhow::ensure!(
+                    0.0 <= light.inner_cone_angle
+                        && light.inner_cone_angle < light.outer_cone_angle
+                        && light.outer_cone_angle <= 90.0,
+                    "Spot light cone angles must satisfy 0 <= inner < outer <= 90 degrees, got {} and {}",
+                    light.inner_cone_angle,
+                    light.outer_cone_angle
+                );
+            }
+            if let Some(shadow) = &light.shadow {
+                anyhow::ensure!(
+                    (1..=8192).contains(&shadow.resolution),
+                    "Shadow resolution must be between 1 and 8192, got {}",
+                    shadow.resolution
+                );
+                anyhow::ensure!(
+                    (1..=8).contains(&shadow.cascades),
+                    "Shadow cascades must be between 1 and 8, got {}",
+                    shadow.cascades
+                );
+                anyhow::ensure!(
+                    shadow.bias >= 0.0 && shadow.normal_offset >= 0.0,
+                    "Shadow bias and normal offset must not be negative, got {} and {}",
+                    shadow.bias,
+                    shadow.normal_offset
+                );
+            }
+        }
+
+        let camera = &self.camera;
+        anyhow::ensure!(
+            camera.fovy > 0.0 && camera.fovy < 180.0,
+            "Camera fovy must be between 0 and 180 degrees, got {}",
+            camera.fovy
+        );
+        anyhow::ensure!(
+            camera.znear > 0.0 && camera.zfar > camera.znear,
+            "Camera planes must satisfy 0 < znear < zfar, got {} and {}",
+            camera.znear,
+            camera.zfar
+        );
+
+        Ok(())
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::from_models([PathBuf::from("assets/models/Dice.glb")])
+    }
+}
+
+/// Grid of instances for the model at `index`, shifted along X by one grid width per
+/// model so that the grids of several models do not overlap.
+fn grid_instances(index: usize) -> Vec<InstanceDesc> {
+    let offset = Vec3::X * index as f32 * GRID_SPACING * NUM_INSTANCES_PER_ROW as f32;
+    (0..NUM_INSTANCES_PER_ROW)
+        .flat_map(|z| {
+            (0..NUM_INSTANCES_PER_ROW).map(move |x| {
+                let x = GRID_SPACING * (x as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
+                let y = GRID_SPACING * (z as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
+
+                let translation = Vec3::new(x, y, 0.0);
+
+                let rotation = if translation.length_squared() == 0.0 {
+                    // this is needed so an object at (0, 0, 0) won't get scaled to zero
+                    // as Quaternions can affect scale if they're not created correctly
+                    Quat::from_axis_angle(Vec3::Z, 0.0)
+                } else {
+                    Quat::from_axis_angle(translation.normalize(), std::f32::consts::FRAC_PI_4)
+                };
+                let (rx, ry, rz) = rotation.to_euler(EulerRot::XYZ);
+
+                InstanceDesc {
+                    translation: (translation + offset).into(),
+                    rotation: [rx, ry, rz].map(f32::to_degrees),
+                    scale: [1.0; 3],
+                }
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DICE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/models/Dice.glb");
+
+    /// Writes `source` to a scene file of its own in the temp directory.
+    fn write_scene(name: &str, source: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join("rs-vulkan-scene-tests");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{name}.ron"));
+        std::fs::write(&path, source).unwrap();
+        path
+    }
+
+    fn load_error(name: &str, source: &str) -> (PathBuf, String) {
+        let path = write_scene(name, source);
+        let err = Scene::load(&path).unwrap_err();
+        (path, format!("{err:#}"))
+    }
+
+    #[test]
+    fn loads_minimal_scene_with_defaults() {
+        let path = write_scene("minimal", &format!("Scene(models: [(path: {DICE:?})])"));
+        let scene = Scene::load(&path).unwrap();
+
+        assert_eq!(scene.clear_color, default_clear_color());
+        assert_eq!(scene.models.len(), 1);
+        assert_eq!(scene.models[0].instances.len(), 1);
+        assert!(scene.lights.is_empty());
+    }
+
+    #[test]
+    fn models_get_separate_grids() {
+        let scene = Scene::from_models([PathBuf::from(DICE), PathBuf::from(DICE)]);
+        let [first, second] = &scene.models[..] else {
+            panic!("expected two models");
+        };
+
+        let max_x = |model: &ModelDesc| {
+            model
+                .instances
+                .iter()
+                .map(|instance| instance.translation[0])
+                .fold(f32::MIN, f32::max)
+        };
+        let min_x = |model: &ModelDesc| {
+            model
+                .instances
+                .iter()
+                .map(|instance| instance.translation[0])
+                .fold(f32::MAX, f32::min)
+        };
+        assert!(min_x(second) > max_x(first));
+    }
+
+    #[test]
+    fn syntax_error_points_at_line_and_column() {
+        let (path, err) = load_error(
+            "syntax",
+            &format!(
+                "Scene(\n    models: [(path: {DICE:?})],\n    clear_color: (0.1, 0.2,, 0.3),\n)"
+            ),
+        );
+
+        assert!(
+            err.starts_with(&format!("{}:3:", path.display())),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    fn unknown_field_points_at_line_and_column() {
+        let (path, err) = load_error(
+            "unknown_field",
+            &format!("Scene(\n    models: [(path: {DICE:?}, scale: 2.0)],\n)"),
+        );
+
+        assert!(
+            err.starts_with(&format!("{}:2:", path.display())),
+            "unexpected error: {err}"
+        );
+        assert!(err.contains("scale"), "unexpected error: {err}");
+    }
+
+    #[test]
+    fn empty_instances_point_at_line_and_column() {
+        let (path, err) = load_error(
+            "empty_instances",
+            &format!("Scene(\n    models: [\n        (path: {DICE:?}, instances: []),\n    ],\n)"),
+        );
+
+        assert!(
+            err.starts_with(&format!("{}:3:", path.display())),
+            "unexpected error: {err}"
+        );
+        assert!(
+            err.contains("at least one instance"),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    fn missing_model_is_rejected() {
+        let (_, err) = load_error("missing_model", "Scene(models: [(path: \"missing.glb\")])");
+
+        assert!(
+            err.contains("missing.glb does not exist"),
+            "unexpected error: {err}"
+        );
+    }
+
+    #[test]
+    fn invalid_spot_cone_is_rejected() {
+        let (_, err) = load_error(
+            "spot_cone",
+            &format!(
+                "Scene(\n    models: [(path: {DICE:?})],\n    lights: [(kind: Spot, inner_cone_angle: 40.0, outer_cone_angle: 30.0)],\n)"
+            ),
+        );
+
+        assert!(err.contains("cone angles"), "unexpected error: {err}");
+    }
+
+    #[test]
+    fn relative_paths_resolve_against_scene_directory() {
+        // Validation only checks that the model exists, so the scene file itself will do
+        let path = write_scene("relative", "Scene(models: [(path: \"relative.ron\")])");
+        let scene = Scene::load(&path).unwrap();
+
+        assert_eq!(scene.models[0].path, path);
+    }
+}
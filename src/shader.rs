@@ -0,0 +1,15 @@
+//! WGSL shared between several shaders, prepended to their source before it is compiled.
+
+/// `vs_main` drawing one triangle over the whole target, with the `FullscreenOutput`
+/// it passes on.
+pub(crate) const FULLSCREEN: &str = include_str!("../assets/shaders/fullscreen.wgsl");
+/// `FragmentOutput` of the passes into the HDR target and the `motion_vector` filling it.
+pub(crate) const MOTION: &str = include_str!("../assets/shaders/motion.wgsl");
+
+/// `source` with the `preludes` it uses in front of it.
+pub(crate) fn with_preludes(preludes: &[&str], source: &str) -> String {
+    let mut wgsl = preludes.join("\n");
+    wgsl.push('\n');
+    wgsl.push_str(source);
+    wgsl
+}
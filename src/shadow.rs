@@ -0,0 +1,738 @@
+use bevy_math::{Mat4, Vec3};
+
+use crate::{
+    camera::{Camera, Projection},
+    light::{Light, LightId},
+    scene::{LightKind, ShadowDesc},
+    texture::Texture,
+};
+
+/// How a light renders and samples its shadow map.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShadowSettings {
+    /// Width and height of the shadow map in texels, of each cube face for point lights.
+    pub resolution: u32,
+    /// World space distance the receiver is moved towards the light, hides shadow acne.
+    pub bias: f32,
+    /// World space distance the receiver is moved along its normal at grazing angles.
+    pub normal_offset: f32,
+    /// Slices of the view frustum a directional light renders a shadow map for, spot lights ignore it.
+    pub cascades: u32,
+}
+
+impl Default for ShadowSettings {
+    fn default() -> Self {
+        Self {
+            resolution: 2048,
+            bias: 0.02,
+            normal_offset: 0.05,
+            cascades: 4,
+        }
+    }
+}
+
+impl From<&ShadowDesc> for ShadowSettings {
+    fn from(desc: &ShadowDesc) -> Self {
+        Self {
+            resolution: desc.resolution,
+            bias: desc.bias,
+            normal_offset: desc.normal_offset,
+            cascades: desc.cascades,
+        }
+    }
+}
+
+/// Shadow map lookup data, indexed by the light's `shadow` field in the shader.
+///
+/// Directional lights have one entry per cascade and point lights one per cube face,
+/// starting at that index.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowRaw {
+    view_proj: Mat4,
+    /// Fraction of the layer the light renders into, lights with a lower resolution use a tile.
+    uv_scale: f32,
+    layer: u32,
+    bias: f32,
+    normal_offset: f32,
+    /// View depth where the cascade ends.
+    cascade_far: f32,
+    /// Number of cascades of the light, the same in each of its entries.
+    cascade_count: u32,
+    /// Corner of the tile in the layer.
+    uv_offset: [f32; 2],
+    /// Point lights store the distance to the light divided by `linear_range`
+    /// instead of the depth, zero for the other lights.
+    light_position: [f32; 3],
+    linear_range: f32,
+}
+
+/// What a depth pass needs to know about its view, one per [`ShadowPass`].
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowViewRaw {
+    view_proj: Mat4,
+    light_position: [f32; 3],
+    linear_range: f32,
+}
+
+impl ShadowRaw {
+    /// A single map without cascades, the layer and projection still have to be filled in.
+    fn new(settings: &ShadowSettings) -> Self {
+        Self {
+            view_proj: Mat4::IDENTITY,
+            uv_scale: 0.0,
+            layer: 0,
+            bias: settings.bias,
+            normal_offset: settings.normal_offset,
+            cascade_far: f32::MAX,
+            cascade_count: 1,
+            uv_offset: [0.0; 2],
+            light_position: [0.0; 3],
+            linear_range: 0.0,
+        }
+    }
+}
+
+/// The camera view frustum, in the terms needed to split it into cascades.
+#[derive(Debug, Default)]
+struct Frustum {
+    inverse_view: Mat4,
+    tan_half_fovy: f32,
+    aspect: f32,
+    znear: f32,
+    zfar: f32,
+}
+
+/// One depth pass into a tile of a layer of the shadow map array.
+struct ShadowPass {
+    /// Index of the shadow entry and its view projection.
+    shadow: usize,
+    layer: u32,
+    /// Top left texel of the tile.
+    origin: [u32; 2],
+    resolution: u32,
+    /// Writes the linear distance to the light instead of the depth.
+    linear: bool,
+    /// First pass into its layer, which clears it.
+    clear: bool,
+}
+
+/// Directions of the cube faces a point light renders, in the order the shader picks them.
+const CUBE_FACES: [Vec3; 6] = [
+    Vec3::X,
+    Vec3::NEG_X,
+    Vec3::Y,
+    Vec3::NEG_Y,
+    Vec3::Z,
+    Vec3::NEG_Z,
+];
+
+/// Shadow map slots allocated before the buffers are first grown.
+const INITIAL_CAPACITY: usize = 4;
+/// Blend between logarithmic (1) and uniform (0) cascade splits.
+const CASCADE_SPLIT_LAMBDA: f32 = 0.75;
+/// Smallest tiles are this many halvings of the layer size.
+const MAX_TILE_LEVEL: u32 = 4;
+/// Area of a layer in units of the smallest tile.
+const LAYER_AREA: u64 = 1 << (2 * MAX_TILE_LEVEL);
+
+/// Depth maps of every shadow casting light, stored in layers of one texture array.
+///
+/// The layers are as large as the highest light resolution. Lights asking for
+/// less share layers in tiles of a half, a quarter and so on of their size, and
+/// only render into and sample from a corner of their tile. Lights that do not
+/// fit into the layers the device allows cast no shadow.
+pub(crate) struct ShadowMaps {
+    pipeline: wgpu::RenderPipeline,
+    distance_pipeline: wgpu::RenderPipeline,
+    /// One view projection per pass, bound with a dynamic offset.
+    view_buffer: wgpu::Buffer,
+    view_bind_group_layout: wgpu::BindGroupLayout,
+    view_bind_group: wgpu::BindGroup,
+    view_stride: u64,
+    buffer: wgpu::Buffer,
+    capacity: usize,
+    texture: Texture,
+    layer_views: Vec<wgpu::TextureView>,
+    size: u32,
+    layers: u32,
+    passes: Vec<ShadowPass>,
+    /// Lights left without shadows in the last frame, to warn only when it changes.
+    dropped_lights: usize,
+    scene_center: Vec3,
+    scene_radius: f32,
+    frustum: Frustum,
+}
+
+impl ShadowMaps {
+    pub fn new(device: &wgpu::Device, vertex_layouts: &[wgpu::VertexBufferLayout]) -> Self {
+        let view_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Shadow View Bind Group Layout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(size_of::<ShadowViewRaw>() as u64),
+                    },
+                    count: None,
+                }],
+            });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[&view_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Shadow Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../assets/shaders/shadow.wgsl").into()),
+        });
+        let create_pipeline = |label, fragment: Option<wgpu::FragmentState>| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    compilation_options: Default::default(),
+                    buffers: vertex_layouts,
+                },
+                fragment,
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    // Single sided geometry like planes still has to cast shadows
+                    cull_mode: None,
+                    unclipped_depth: false,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    conservative: false,
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        // Depth only, there is no color to write
+        let pipeline = create_pipeline("Shadow Pipeline", None);
+        let distance_pipeline = create_pipeline(
+            "Point Shadow Pipeline",
+            Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_distance"),
+                compilation_options: Default::default(),
+                targets: &[],
+            }),
+        );
+
+        let view_stride = (size_of::<ShadowViewRaw>() as u64)
+            .next_multiple_of(device.limits().min_uniform_buffer_offset_alignment as u64);
+        let (buffer, view_buffer, view_bind_group) = Self::create_buffers(
+            device,
+            &view_bind_group_layout,
+            view_stride,
+            INITIAL_CAPACITY,
+        );
+        let (texture, layer_views) = Self::create_texture(device, 1, 2);
+
+        Self {
+            pipeline,
+            distance_pipeline,
+            view_buffer,
+            view_bind_group_layout,
+            view_bind_group,
+            view_stride,
+            buffer,
+            capacity: INITIAL_CAPACITY,
+            texture,
+            layer_views,
+            size: 1,
+            layers: 2,
+            passes: Vec::new(),
+            dropped_lights: 0,
+            scene_center: Vec3::ZERO,
+            scene_radius: 1.0,
+            frustum: Frustum::default(),
+        }
+    }
+
+    fn create_buffers(
+        device: &wgpu::Device,
+        view_bind_group_layout: &wgpu::BindGroupLayout,
+        view_stride: u64,
+        capacity: usize,
+    ) -> (wgpu::Buffer, wgpu::Buffer, wgpu::BindGroup) {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadows Buffer"),
+            size: (capacity * size_of::<ShadowRaw>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let view_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow View Buffer"),
+            size: capacity as u64 * view_stride,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let view_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Shadow View Bind Group"),
+            layout: view_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &view_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(size_of::<ShadowViewRaw>() as u64),
+                }),
+            }],
+        });
+        (buffer, view_buffer, view_bind_group)
+    }
+
+    fn create_texture(
+        device: &wgpu::Device,
+        size: u32,
+        layers: u32,
+    ) -> (Texture, Vec<wgpu::TextureView>) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Shadow Maps"),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: layers,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Texture::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let layer_views = (0..layers)
+            .map(|layer| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Shadow Map Layer"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Map Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        (
+            Texture {
+                texture,
+                view,
+                sampler,
+                stored_format: Texture::DEPTH_FORMAT,
+            },
+            layer_views,
+        )
+    }
+
+    /// Storage buffer of the per light lookup data.
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// Depth texture array with a comparison sampler.
+    pub fn texture(&self) -> &Texture {
+        &self.texture
+    }
+
+    /// Sets the box around every shadow caster, cascades reach towards the light to include it.
+    pub fn set_scene_bounds(&mut self, min: Vec3, max: Vec3) {
+        if min.cmple(max).all() {
+            self.scene_center = (min + max) / 2.0;
+            self.scene_radius = (max - min).length().max(1e-3) / 2.0;
+        }
+    }
+
+    /// Sets the camera whose view frustum the cascades of directional lights are fit to.
+    pub fn set_view(&mut self, camera: &Camera, projection: &Projection) {
+        self.frustum = Frustum {
+            inverse_view: camera.to_mat4().inverse(),
+            tan_half_fovy: (projection.fovy() / 2.0).tan(),
+            aspect: projection.aspect(),
+            znear: projection.znear(),
+            zfar: projection.zfar(),
+        };
+    }
+
+    /// Assigns shadow maps to the lights casting shadows and uploads their projections.
+    ///
+    /// Returns the shadow index of every light, -1 for lights without one, and
+    /// whether the buffer or texture were recreated and need to be bound again.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        lights: &[(LightId, Light)],
+    ) -> (Vec<i32>, bool) {
+        let limits = device.limits();
+        let casters: Vec<(usize, u32, Vec<ShadowRaw>)> = lights
+            .iter()
+            .enumerate()
+            .filter_map(|(index, (_, light))| {
+                let settings = light.shadow?;
+                let resolution = settings
+                    .resolution
+                    .clamp(1, limits.max_texture_dimension_2d);
+                let shadows = match light.kind {
+                    LightKind::Directional => self.cascades(light, &settings, resolution),
+                    LightKind::Spot => vec![ShadowRaw {
+                        view_proj: spot_view_proj(light),
+                        ..ShadowRaw::new(&settings)
+                    }],
+                    LightKind::Point => point_view_projs(light, resolution)
+                        .map(|view_proj| ShadowRaw {
+                            view_proj,
+                            light_position: light.position.into(),
+                            linear_range: light.range(),
+                            ..ShadowRaw::new(&settings)
+                        })
+                        .to_vec(),
+                };
+                Some((index, resolution, shadows))
+            })
+            .collect();
+        let size = casters
+            .iter()
+            .map(|&(_, resolution, _)| resolution)
+            .max()
+            .unwrap_or(1);
+
+        // Lights keep their shadows in order while their tiles fit, one layer
+        // less than the limit leaves room for the GL workaround below
+        let max_area = (limits.max_texture_array_layers - 1) as u64 * LAYER_AREA;
+        let mut area = 0;
+        let mut indices = vec![-1; lights.len()];
+        let mut shadows = Vec::new();
+        let mut tiles = Vec::new();
+        let mut dropped_lights = 0;
+        for (light, resolution, light_shadows) in casters {
+            let level = tile_level(size, resolution);
+            let light_area = light_shadows.len() as u64 * (LAYER_AREA >> (2 * level));
+            if area + light_area > max_area {
+                dropped_lights += 1;
+                continue;
+            }
+            area += light_area;
+            indices[light] = shadows.len() as i32;
+            tiles.extend(
+                (shadows.len()..)
+                    .take(light_shadows.len())
+                    .map(|index| (level, index, resolution)),
+            );
+            shadows.extend(light_shadows);
+        }
+        if dropped_lights != self.dropped_lights {
+            if dropped_lights > 0 {
+                log::warn!(
+                    "{dropped_lights} lights cast no shadow, their shadow maps exceed the {} texture array layers of the device",
+                    limits.max_texture_array_layers
+                );
+            }
+            self.dropped_lights = dropped_lights;
+        }
+
+        // Largest tiles first, each then starts at a multiple of its own size
+        tiles.sort_by_key(|&(level, _, _)| level);
+        self.passes.clear();
+        let mut offset = 0;
+        for (level, index, resolution) in tiles {
+            let (layer, origin) = place_tile(size, level, offset);
+            let shadow = &mut shadows[index];
+            shadow.layer = layer;
+            shadow.uv_scale = resolution as f32 / size as f32;
+            shadow.uv_offset = origin.map(|texel| texel as f32 / size as f32);
+            self.passes.push(ShadowPass {
+                shadow: index,
+                layer,
+                origin,
+                resolution,
+                linear: shadow.linear_range > 0.0,
+                clear: offset % LAYER_AREA == 0,
+            });
+            offset += LAYER_AREA >> (2 * level);
+        }
+
+        let mut recreated = false;
+        if shadows.len() > self.capacity {
+            self.capacity = shadows.len().next_power_of_two();
+            (self.buffer, self.view_buffer, self.view_bind_group) = Self::create_buffers(
+                device,
+                &self.view_bind_group_layout,
+                self.view_stride,
+                self.capacity,
+            );
+            recreated = true;
+        }
+
+        // GL picks the texture target from the layer count, one layer would make it a
+        // 2D texture and a multiple of six on a square texture a cube map
+        let mut layers = (offset.div_ceil(LAYER_AREA) as u32).max(2);
+        if layers.is_multiple_of(6) {
+            layers += 1;
+        }
+        if size != self.size || layers > self.layers {
+            (self.texture, self.layer_views) = Self::create_texture(device, size, layers);
+            self.size = size;
+            self.layers = layers;
+            recreated = true;
+        }
+
+        if !shadows.is_empty() {
+            queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&shadows));
+        }
+        for (index, shadow) in shadows.iter().enumerate() {
+            let view = ShadowViewRaw {
+                view_proj: shadow.view_proj,
+                light_position: shadow.light_position,
+                linear_range: shadow.linear_range,
+            };
+            queue.write_buffer(
+                &self.view_buffer,
+                index as u64 * self.view_stride,
+                bytemuck::bytes_of(&view),
+            );
+        }
+
+        (indices, recreated)
+    }
+
+    /// Splits the view frustum into `settings.cascades` slices and fits an orthographic
+    /// projection around each, covering everything in the scene that can cast into it.
+    fn cascades(
+        &self,
+        light: &Light,
+        settings: &ShadowSettings,
+        resolution: u32,
+    ) -> Vec<ShadowRaw> {
+        let frustum = &self.frustum;
+        let count = settings.cascades.max(1);
+        // Mix of logarithmic and uniform splits, the logarithmic ones alone leave
+        // the far cascades huge while uniform ones waste resolution up close
+        let split = |i: u32| {
+            let t = i as f32 / count as f32;
+            let logarithmic = frustum.znear * (frustum.zfar / frustum.znear).powf(t);
+            let uniform = frustum.znear + (frustum.zfar - frustum.znear) * t;
+            CASCADE_SPLIT_LAMBDA * logarithmic + (1.0 - CASCADE_SPLIT_LAMBDA) * uniform
+        };
+
+        let direction = light.direction.normalize_or(Vec3::NEG_Z);
+        let light_view = Mat4::look_to_rh(Vec3::ZERO, direction, up_vector(direction));
+        let scene_center = light_view.transform_point3(self.scene_center);
+        let mut first_radius = None;
+
+        (0..count)
+            .map(|i| {
+                let (near, far) = (split(i), split(i + 1));
+                let corners = [near, far].into_iter().flat_map(|depth| {
+                    let x = frustum.tan_half_fovy * frustum.aspect * depth;
+                    let y = frustum.tan_half_fovy * depth;
+                    [(-x, -y), (x, -y), (-x, y), (x, y)].map(|(x, y)| {
+                        frustum
+                            .inverse_view
+                            .transform_point3(Vec3::new(x, y, -depth))
+                    })
+                });
+                let corners = corners.collect::<Vec<_>>();
+                let center = corners.iter().sum::<Vec3>() / corners.len() as f32;
+                // A sphere keeps the size independent of the camera rotation, rounding it
+                // keeps the size from flickering with floating point noise
+                let radius = corners
+                    .iter()
+                    .map(|corner| corner.distance(center))
+                    .fold(0.0, f32::max);
+                let radius = (radius * 16.0).ceil() / 16.0;
+
+                // Moving the projection in whole texels keeps shadow edges from crawling
+                let texel = 2.0 * radius / resolution as f32;
+                let center = light_view.transform_point3(center);
+                let center = ((center / texel).floor() * texel).with_z(center.z);
+
+                // Casters between the light and the cascade are anywhere in the scene
+                let z_max = (center.z + radius).max(scene_center.z + self.scene_radius);
+                let z_min = (center.z - radius).min(scene_center.z - self.scene_radius);
+                let projection = Mat4::orthographic_rh(
+                    center.x - radius,
+                    center.x + radius,
+                    center.y - radius,
+                    center.y + radius,
+                    -z_max,
+                    -z_min,
+                );
+
+                // Far cascades have larger texels and need proportionally larger offsets
+                let scale = radius / *first_radius.get_or_insert(radius);
+                ShadowRaw {
+                    view_proj: projection * light_view,
+                    bias: settings.bias * scale,
+                    normal_offset: settings.normal_offset * scale,
+                    cascade_far: far,
+                    cascade_count: count,
+                    ..ShadowRaw::new(settings)
+                }
+            })
+            .collect()
+    }
+
+    /// Records one depth pass per shadow map, `draw` issues the draw calls of every model
+    /// with the vertex buffer in slot 0 and the instance buffer in slot 1.
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        mut draw: impl FnMut(&mut wgpu::RenderPass),
+    ) {
+        for pass in &self.passes {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.layer_views[pass.layer as usize],
+                    // Clearing the whole layer makes the unused parts read as unshadowed
+                    depth_ops: Some(wgpu::Operations {
+                        load: if pass.clear {
+                            wgpu::LoadOp::Clear(1.0)
+                        } else {
+                            wgpu::LoadOp::Load
+                        },
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            let [x, y] = pass.origin.map(|texel| texel as f32);
+            let resolution = pass.resolution as f32;
+            render_pass.set_viewport(x, y, resolution, resolution, 0.0, 1.0);
+            render_pass.set_pipeline(if pass.linear {
+                &self.distance_pipeline
+            } else {
+                &self.pipeline
+            });
+            render_pass.set_bind_group(
+                0,
+                &self.view_bind_group,
+                &[(pass.shadow as u64 * self.view_stride) as u32],
+            );
+            draw(&mut render_pass);
+        }
+    }
+}
+
+/// Level of the smallest tile of a `size` layer that still holds `resolution` texels,
+/// each level halves the tile size.
+fn tile_level(size: u32, resolution: u32) -> u32 {
+    (0..=MAX_TILE_LEVEL)
+        .rev()
+        .find(|&level| size >> level >= resolution)
+        .unwrap_or(0)
+}
+
+/// Layer and top left texel of a tile of `level` starting `offset` smallest tiles
+/// into the array, which must be a multiple of the tile area.
+///
+/// Tiles are laid out in Z-order, which keeps the four tiles of each level
+/// inside the tile of the level above they split.
+fn place_tile(size: u32, level: u32, offset: u64) -> (u32, [u32; 2]) {
+    let index = (offset % LAYER_AREA / (LAYER_AREA >> (2 * level))) as u32;
+    let bits = |shift: u32| {
+        (0..level).fold(0, |value, bit| {
+            value | ((index >> (2 * bit + shift)) & 1) << bit
+        })
+    };
+    let origin = [bits(0), bits(1)].map(|tile| tile * (size >> level));
+    ((offset / LAYER_AREA) as u32, origin)
+}
+
+/// Any up vector works for the light view as long as it is not parallel to the light direction.
+fn up_vector(direction: Vec3) -> Vec3 {
+    if direction.cross(Vec3::Z).length_squared() < 1e-6 {
+        Vec3::Y
+    } else {
+        Vec3::Z
+    }
+}
+
+/// Perspective projection from the spot light position covering its outer cone.
+fn spot_view_proj(light: &Light) -> Mat4 {
+    let direction = light.direction.normalize_or(Vec3::NEG_Z);
+    let range = light.range();
+    let fovy = (2.0 * light.outer_cone_angle).clamp(0.01, std::f32::consts::PI - 0.01);
+    let view = Mat4::look_to_rh(light.position, direction, up_vector(direction));
+    let projection = Mat4::perspective_rh(fovy, 1.0, (range * 1e-3).min(0.05), range);
+    projection * view
+}
+
+/// Projections from the point light position onto each of the [`CUBE_FACES`].
+fn point_view_projs(light: &Light, resolution: u32) -> [Mat4; 6] {
+    let range = light.range();
+    // Slightly wider than 90 degrees, so filtering near the face edges stays inside the face
+    let fovy = 2.0 * (1.0 + 4.0 / resolution as f32).atan();
+    let projection = Mat4::perspective_rh(fovy, 1.0, (range * 1e-3).min(0.05), range);
+    CUBE_FACES.map(|direction| {
+        projection * Mat4::look_to_rh(light.position, direction, up_vector(direction))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_do_not_overlap() {
+        let size = 1024;
+        let mut levels: Vec<u32> = [0, 2, 1, 4, 4, 3, 1, 2, 2, 0, 4, 3, 1]
+            .into_iter()
+            .cycle()
+            .take(40)
+            .collect();
+        levels.sort();
+
+        let mut offset = 0;
+        let mut tiles: Vec<(u32, [u32; 2], u32)> = Vec::new();
+        for level in levels {
+            let (layer, [x, y]) = place_tile(size, level, offset);
+            let extent = size >> level;
+            assert!(x + extent <= size && y + extent <= size);
+            for &(other_layer, [other_x, other_y], other_extent) in &tiles {
+                let apart = layer != other_layer
+                    || x >= other_x + other_extent
+                    || other_x >= x + extent
+                    || y >= other_y + other_extent
+                    || other_y >= y + extent;
+                assert!(apart, "{level} at {offset} overlaps");
+            }
+            tiles.push((layer, [x, y], extent));
+            offset += LAYER_AREA >> (2 * level);
+        }
+    }
+}
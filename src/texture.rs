@@ -1,11 +1,122 @@
-use anyhow::*;
+mod basis;
+mod compressed;
+mod decode;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use anyhow::Result;
 use image::GenericImageView;
 
+/// What the texels of a texture hold, which decides whether they are sRGB encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextureRole {
+    /// Colors such as base color and emissive, stored as sRGB.
+    Color,
+    /// Linear values such as normals, metallic-roughness and occlusion.
+    Data,
+}
+
+impl TextureRole {
+    /// Every image is uploaded as 8 bit RGBA in this format.
+    pub fn format(self) -> wgpu::TextureFormat {
+        match self {
+            TextureRole::Color => wgpu::TextureFormat::Rgba8UnormSrgb,
+            TextureRole::Data => wgpu::TextureFormat::Rgba8Unorm,
+        }
+    }
+}
+
+/// Compressed format families that are sampled directly when the device supports
+/// them, textures in other formats are decoded to RGBA8 on the CPU.
+pub const COMPRESSION_FEATURES: wgpu::Features = wgpu::Features::TEXTURE_COMPRESSION_BC
+    .union(wgpu::Features::TEXTURE_COMPRESSION_ETC2)
+    .union(wgpu::Features::TEXTURE_COMPRESSION_ASTC);
+
+/// Pixel data that cannot be uploaded as a texture.
+#[derive(Debug, thiserror::Error)]
+pub enum TextureError {
+    #[error("Unsupported pixel format {0:?}, only 8 and 16 bit images are supported")]
+    UnsupportedFormat(gltf::image::Format),
+    #[error("Expected {expected} bytes of pixels for a {width}x{height} image, got {actual}")]
+    SizeMismatch {
+        width: u32,
+        height: u32,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("Invalid KTX2 file: {0}")]
+    InvalidKtx2(ktx2::ParseError),
+    #[error("Only 2D KTX2 textures are supported, not arrays, cube maps or 3D textures")]
+    UnsupportedKtx2Layout,
+    #[error("KTX2 format {0:?} is not supported")]
+    UnsupportedKtx2Format(ktx2::Format),
+    #[error("KTX2 supercompression {0:?} is not supported")]
+    UnsupportedSupercompression(ktx2::SupercompressionScheme),
+    #[error("KTX2 mip level {level} is smaller than its size requires")]
+    Ktx2LevelSize { level: u32 },
+    #[error("KTX2 file has no format and is not Basis Universal")]
+    MissingKtx2Format,
+    #[error("Invalid Basis Universal data: {0}")]
+    InvalidBasis(&'static str),
+    #[error("{0:?} is not supported by the device and cannot be decoded on the CPU")]
+    NoCpuDecoder(wgpu::TextureFormat),
+}
+
+/// Converts a decoded glTF image to RGBA8, the format every texture is uploaded in.
+///
+/// Grayscale is replicated to RGB, missing alpha becomes opaque and 16 bit
+/// channels keep their most significant byte.
+pub fn gltf_to_rgba8(
+    image: &gltf::image::Data,
+) -> std::result::Result<Cow<'_, [u8]>, TextureError> {
+    use gltf::image::Format;
+
+    let pixels = &image.pixels;
+    let sixteen_bit = || {
+        pixels
+            .chunks_exact(2)
+            .map(|channel| (u16::from_ne_bytes([channel[0], channel[1]]) >> 8) as u8)
+            .collect::<Vec<_>>()
+    };
+    let expand = |channels: &[u8], components: usize| {
+        channels
+            .chunks_exact(components)
+            .flat_map(|texel| match *texel {
+                [l] => [l, l, l, 255],
+                [l, a] => [l, l, l, a],
+                [r, g, b] => [r, g, b, 255],
+                [r, g, b, a] => [r, g, b, a],
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let rgba = match image.format {
+        Format::R8G8B8A8 => Cow::Borrowed(pixels.as_slice()),
+        Format::R8 => Cow::Owned(expand(pixels, 1)),
+        Format::R8G8 => Cow::Owned(expand(pixels, 2)),
+        Format::R8G8B8 => Cow::Owned(expand(pixels, 3)),
+        Format::R16 => Cow::Owned(expand(&sixteen_bit(), 1)),
+        Format::R16G16 => Cow::Owned(expand(&sixteen_bit(), 2)),
+        Format::R16G16B16 => Cow::Owned(expand(&sixteen_bit(), 3)),
+        Format::R16G16B16A16 => Cow::Owned(sixteen_bit()),
+        format @ (Format::R32G32B32FLOAT | Format::R32G32B32A32FLOAT) => {
+            return Err(TextureError::UnsupportedFormat(format));
+        }
+    };
+
+    Ok(rgba)
+}
+
 pub struct Texture {
     #[allow(unused)]
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
+    /// Format the texels were stored in, which tells which channels hold data
+    /// when the texture was expanded to RGBA on upload.
+    pub stored_format: wgpu::TextureFormat,
 }
 
 impl Texture {
@@ -13,6 +124,7 @@ impl Texture {
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         path: P,
+        role: TextureRole,
         label: &str,
     ) -> Result<Self> {
         let img = image::open(path)?;
@@ -24,24 +136,132 @@ impl Texture {
             depth_or_array_layers: 1,
         };
 
-        Self::from_image(device, queue, size, &img.to_rgb8(), Some(label))
+        Self::from_image(device, queue, size, &img.to_rgba8(), role, Some(label))
     }
 
+    /// A 1x1 texture of a single RGBA color, used in place of missing material maps.
+    pub fn from_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        role: TextureRole,
+        label: &str,
+    ) -> Result<Self> {
+        let size = wgpu::Extent3d {
+            width: 1,
+            height: 1,
+            depth_or_array_layers: 1,
+        };
+
+        Self::from_image(device, queue, size, &color, role, Some(label))
+    }
+
+    /// Uploads RGBA8 pixels, see [`gltf_to_rgba8`] for converting other formats.
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         size: wgpu::Extent3d,
         bytes: &[u8],
+        role: TextureRole,
+        label: Option<&str>,
+    ) -> Result<Self> {
+        Self::create(device, queue, size, 1, bytes, role.format(), label)
+    }
+
+    /// Like [`Texture::from_image`], but with a full mip chain downsampled on the GPU.
+    pub fn from_image_mipmapped(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mipmaps: &mut MipmapGenerator,
+        size: wgpu::Extent3d,
+        bytes: &[u8],
+        role: TextureRole,
         label: Option<&str>,
     ) -> Result<Self> {
+        Self::from_pixels_mipmapped(device, queue, mipmaps, size, bytes, role.format(), label)
+    }
+
+    /// Like [`Texture::from_image_mipmapped`] for pixels in any uncompressed `format`.
+    fn from_pixels_mipmapped(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mipmaps: &mut MipmapGenerator,
+        size: wgpu::Extent3d,
+        bytes: &[u8],
+        format: wgpu::TextureFormat,
+        label: Option<&str>,
+    ) -> Result<Self> {
+        let texture = Self::create(
+            device,
+            queue,
+            size,
+            size.max_mips(wgpu::TextureDimension::D2),
+            bytes,
+            format,
+            label,
+        )?;
+        mipmaps.generate(device, queue, &texture.texture);
+
+        Ok(texture)
+    }
+
+    /// Loads a KTX2 container, see [`crate::assets::AssetServer::load_texture`].
+    ///
+    /// Block compressed formats are uploaded as is with their stored mip
+    /// levels when the device supports them. Otherwise the BC, ETC2/EAC and
+    /// LDR ASTC formats are decoded on the CPU, to RGBA8 or to RGBA16F for the
+    /// signed and HDR ones, uncompressed 8 bit formats are always expanded to
+    /// RGBA8. Basis Universal textures are uploaded as ETC2 when they are
+    /// opaque ETC1S and the device supports it, and decoded to RGBA8 otherwise.
+    /// The role decides between the sRGB and the linear variant of the format,
+    /// whatever the file declares.
+    pub fn from_ktx2(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mipmaps: &mut MipmapGenerator,
+        bytes: &[u8],
+        role: TextureRole,
+        label: Option<&str>,
+    ) -> Result<Self> {
+        compressed::load(device, queue, mipmaps, bytes, role, label)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        size: wgpu::Extent3d,
+        mip_level_count: u32,
+        bytes: &[u8],
+        format: wgpu::TextureFormat,
+        label: Option<&str>,
+    ) -> Result<Self> {
+        let texel_size = format.block_copy_size(None).unwrap_or_default();
+        let expected = texel_size as usize * size.width as usize * size.height as usize;
+        if bytes.len() != expected {
+            return Err(TextureError::SizeMismatch {
+                width: size.width,
+                height: size.height,
+                expected,
+                actual: bytes.len(),
+            }
+            .into());
+        }
+
+        let mut usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST;
+        if mip_level_count > 1 {
+            // Lower mip levels are rendered from the level above
+            usage |= wgpu::TextureUsages::RENDER_ATTACHMENT;
+        }
+
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            format,
+            usage,
             view_formats: &[],
         });
 
@@ -52,49 +272,53 @@ impl Texture {
                 mip_level: 0,
                 origin: wgpu::Origin3d::ZERO,
             },
-            &bytes,
+            bytes,
             wgpu::TexelCopyBufferLayout {
                 offset: 0,
-                bytes_per_row: Some(4 * size.width),
+                bytes_per_row: Some(texel_size * size.width),
                 rows_per_image: Some(size.height),
             },
             size,
         );
 
+        Ok(Self::from_texture(device, texture))
+    }
+
+    fn from_texture(device: &wgpu::Device, texture: wgpu::Texture) -> Self {
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            label: Some("Texture Sampler"),
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Nearest,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            lod_min_clamp: 0.,
-            lod_max_clamp: 0.,
-            compare: None,
-            anisotropy_clamp: 1,
-            border_color: None,
-        });
+        let sampler = SamplerDesc::default().create(device, 1);
 
-        Ok(Self {
+        Self {
+            stored_format: texture.format(),
             texture,
             view,
             sampler,
-        })
+        }
     }
 
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
-    pub fn create_depth_texture(device: &wgpu::Device, size: wgpu::Extent3d, label: &str) -> Self {
+    pub fn create_depth_texture(
+        device: &wgpu::Device,
+        size: wgpu::Extent3d,
+        sample_count: u32,
+        label: &str,
+    ) -> Self {
+        // Multisampled textures cannot be used with the comparison sampler below
+        let usage = if sample_count > 1 {
+            wgpu::TextureUsages::RENDER_ATTACHMENT
+        } else {
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+        };
+
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some(label),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            usage,
             view_formats: &[],
         });
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
@@ -119,6 +343,209 @@ impl Texture {
             texture,
             view,
             sampler,
+            stored_format: Self::DEPTH_FORMAT,
         }
     }
 }
+
+/// Wrapping and filtering of a material texture, translated from the glTF sampler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SamplerDesc {
+    pub address_mode_u: wgpu::AddressMode,
+    pub address_mode_v: wgpu::AddressMode,
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    pub mipmap_filter: wgpu::FilterMode,
+}
+
+/// Repeating with trilinear filtering, the glTF defaults.
+impl Default for SamplerDesc {
+    fn default() -> Self {
+        Self {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+        }
+    }
+}
+
+impl SamplerDesc {
+    /// Creates the sampler, `anisotropy` is only applied when every filter is
+    /// linear as wgpu does not allow it otherwise.
+    pub fn create(&self, device: &wgpu::Device, anisotropy: u16) -> wgpu::Sampler {
+        let trilinear = [self.mag_filter, self.min_filter, self.mipmap_filter]
+            .iter()
+            .all(|&filter| filter == wgpu::FilterMode::Linear);
+
+        device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Texture Sampler"),
+            address_mode_u: self.address_mode_u,
+            address_mode_v: self.address_mode_v,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: self.mag_filter,
+            min_filter: self.min_filter,
+            mipmap_filter: self.mipmap_filter,
+            lod_min_clamp: 0.,
+            lod_max_clamp: 32.,
+            compare: None,
+            anisotropy_clamp: if trilinear {
+                anisotropy.clamp(1, 16)
+            } else {
+                1
+            },
+            border_color: None,
+        })
+    }
+}
+
+/// Fills the mip chain of a texture by repeatedly rendering each level into
+/// the next smaller one with a linear filter.
+pub struct MipmapGenerator {
+    shader: wgpu::ShaderModule,
+    sampler: wgpu::Sampler,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipelines: HashMap<wgpu::TextureFormat, wgpu::RenderPipeline>,
+}
+
+impl MipmapGenerator {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Blit Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../assets/shaders/blit.wgsl").into()),
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Mipmap Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Mipmap Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Mipmap Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        Self {
+            shader,
+            sampler,
+            bind_group_layout,
+            pipeline_layout,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Overwrites every mip level below the first one, `texture` has to be
+    /// usable as a render attachment.
+    pub fn generate(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture: &wgpu::Texture,
+    ) {
+        let format = texture.format();
+        let pipeline = self.pipelines.entry(format).or_insert_with(|| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Mipmap Pipeline"),
+                layout: Some(&self.pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &self.shader,
+                    entry_point: Some("vs_main"),
+                    compilation_options: Default::default(),
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &self.shader,
+                    entry_point: Some("fs_main"),
+                    compilation_options: Default::default(),
+                    targets: &[Some(format.into())],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        });
+
+        let views = (0..texture.mip_level_count())
+            .map(|mip| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Mip View"),
+                    base_mip_level: mip,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Mipmap Encoder"),
+        });
+        for pair in views.windows(2) {
+            let [source, target] = pair else {
+                unreachable!()
+            };
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Mipmap Bind Group"),
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(source),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                ],
+            });
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Mipmap Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+}
@@ -0,0 +1,136 @@
+//! Transcoding of Basis Universal textures, the ETC1S and UASTC formats KTX2
+//! files store without a Vulkan format.
+//!
+//! Opaque ETC1S is made of ETC1 blocks, which are uploaded as ETC2 where the
+//! device supports it. ETC1S with alpha and UASTC are decoded to RGBA8.
+
+mod etc1s;
+mod uastc;
+
+use std::borrow::Cow;
+
+use ktx2::{ColorModel, DfdBlockBasic, SupercompressionScheme};
+
+use super::compressed::Encoding;
+use super::{TextureError, decode};
+
+const ASTC_4X4: wgpu::TextureFormat = wgpu::TextureFormat::Astc {
+    block: wgpu::AstcBlock::B4x4,
+    channel: wgpu::AstcChannel::Unorm,
+};
+
+/// Transcodes the `levels` of a Basis Universal file, returning how the result
+/// is stored and its levels. Decoded textures only keep the first level, the
+/// others are generated again.
+pub(super) fn transcode<Data: AsRef<[u8]>>(
+    reader: &ktx2::Reader<Data>,
+    levels: &[Cow<[u8]>],
+) -> Result<(Encoding, Vec<Vec<u8>>), TextureError> {
+    let header = reader.header();
+    let dfd = reader
+        .dfd_blocks()
+        .next()
+        .and_then(|block| DfdBlockBasic::parse(block.data).ok())
+        .ok_or(TextureError::MissingKtx2Format)?;
+    let (width, height) = (header.pixel_width, header.pixel_height.max(1));
+
+    match dfd.header.color_model {
+        Some(ColorModel::ETC1S) => {
+            if header.supercompression_scheme != Some(SupercompressionScheme::BasisLZ) {
+                return Err(TextureError::InvalidBasis("ETC1S without BasisLZ"));
+            }
+            let levels: Vec<&[u8]> = levels.iter().map(AsRef::as_ref).collect();
+            let mut decoded = etc1s::decode(
+                reader.supercompression_global_data(),
+                &levels,
+                width,
+                height,
+            )?;
+
+            if decoded.iter().all(|level| level.alpha.is_none()) {
+                let blocks = decoded.into_iter().map(|level| level.rgb).collect();
+                return Ok((Encoding::Blocks(wgpu::TextureFormat::Etc2Rgb8Unorm), blocks));
+            }
+            let level = decoded.swap_remove(0);
+            let decode = |blocks: &[u8]| {
+                decode::decode(wgpu::TextureFormat::Etc2Rgb8Unorm, width, height, blocks)
+                    .map(|(_, pixels)| pixels)
+                    .ok_or(TextureError::Ktx2LevelSize { level: 0 })
+            };
+            let mut pixels = decode(&level.rgb)?;
+            if let Some(alpha) = &level.alpha {
+                for (pixel, alpha) in pixels
+                    .chunks_exact_mut(4)
+                    .zip(decode(alpha)?.chunks_exact(4))
+                {
+                    pixel[3] = alpha[1];
+                }
+            }
+            Ok((Encoding::Channels(4), vec![pixels]))
+        }
+        Some(ColorModel::UASTC) => {
+            let texels =
+                decode::decode_blocks(ASTC_4X4, width, height, &levels[0], |block, texels| {
+                    texels.copy_from_slice(&uastc::decode_block(block))
+                })
+                .ok_or(TextureError::Ktx2LevelSize { level: 0 })?;
+            Ok((Encoding::Channels(4), vec![texels.concat()]))
+        }
+        _ => Err(TextureError::MissingKtx2Format),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A KTX2 file of one 4x4 UASTC level with a solid block of `color`.
+    fn uastc_file(color: [u8; 4]) -> Vec<u8> {
+        let block = color
+            .iter()
+            .enumerate()
+            .fold(0x17u128, |bits, (i, &c)| bits | (c as u128) << (5 + 8 * i));
+
+        // Basic descriptor block with one sample of the RGBA channel id
+        let mut dfd = Vec::new();
+        dfd.extend(44u32.to_le_bytes());
+        dfd.extend(0u32.to_le_bytes());
+        dfd.extend(2u16.to_le_bytes());
+        dfd.extend(40u16.to_le_bytes());
+        dfd.extend([166, 1, 1, 0, 3, 3, 0, 0, 16, 0, 0, 0, 0, 0, 0, 0]);
+        dfd.extend(0u16.to_le_bytes());
+        dfd.extend([127, 3, 0, 0, 0, 0]);
+        dfd.extend(0u32.to_le_bytes());
+        dfd.extend(u32::MAX.to_le_bytes());
+
+        let dfd_offset = 12 + 9 * 4 + 4 * 4 + 2 * 8 + 3 * 8;
+        let level_offset = dfd_offset + dfd.len();
+        let mut file = b"\xabKTX 20\xbb\r\n\x1a\n".to_vec();
+        for value in [0, 1, 4, 4, 0, 0, 1, 1, 0] {
+            file.extend(u32::to_le_bytes(value));
+        }
+        for value in [dfd_offset, dfd.len(), 0, 0] {
+            file.extend((value as u32).to_le_bytes());
+        }
+        for value in [0, 0, level_offset, 16, 16] {
+            file.extend((value as u64).to_le_bytes());
+        }
+        file.extend(dfd);
+        file.extend(block.to_le_bytes());
+        file
+    }
+
+    #[test]
+    fn uastc_is_decoded_to_rgba8() {
+        let file = uastc_file([10, 200, 30, 40]);
+        let reader = ktx2::Reader::new(&file[..]).unwrap();
+        let levels: Vec<_> = reader
+            .levels()
+            .map(|level| Cow::Borrowed(level.data))
+            .collect();
+
+        let (encoding, levels) = transcode(&reader, &levels).unwrap();
+        assert!(matches!(encoding, Encoding::Channels(4)));
+        assert_eq!(levels, [[10, 200, 30, 40].repeat(16)]);
+    }
+}
@@ -0,0 +1,632 @@
+//! ETC1S in BasisLZ supercompression: ETC1 blocks whose halves share one
+//! color and intensity table, referenced from palettes of endpoints and
+//! selectors that the global data of the file stores with Huffman coding.
+//!
+//! Only the palettes the current encoder writes are supported, files that use
+//! the global selector codebook of early versions are rejected.
+
+use crate::texture::TextureError;
+
+/// Symbol of the endpoint predictions that repeats the previous one.
+const ENDPOINT_PRED_REPEAT_LAST: u32 = 256;
+/// Symbol of the selector run lengths whose length is stored separately.
+const SELECTOR_RUN_ESCAPE: u32 = 63;
+const MIN_SELECTOR_RUN: u32 = 3;
+
+/// The ETC1 blocks of the color slice of a level and of its alpha slice,
+/// whose green channel holds the alpha.
+pub(super) struct Level {
+    pub rgb: Vec<u8>,
+    pub alpha: Option<Vec<u8>>,
+}
+
+/// Decodes every level to ETC1 blocks. `levels` holds the data of each level
+/// and `global_data` the palettes and tables they share.
+pub(super) fn decode(
+    global_data: &[u8],
+    levels: &[&[u8]],
+    width: u32,
+    height: u32,
+) -> Result<Vec<Level>, TextureError> {
+    let field = |offset: usize, size: usize| -> Result<usize, TextureError> {
+        let bytes = global_data
+            .get(offset..offset + size)
+            .ok_or(TextureError::InvalidBasis("global data is too short"))?;
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0, |value, &byte| value << 8 | byte as usize))
+    };
+    let endpoint_count = field(0, 2)?;
+    let selector_count = field(2, 2)?;
+    let lengths = [field(4, 4)?, field(8, 4)?, field(12, 4)?];
+
+    // An image description per level follows the header, then the palettes and tables
+    let images_start = 20;
+    let mut section_start = images_start + 20 * levels.len();
+    let sections = lengths.map(|length| {
+        let section = global_data.get(section_start..section_start + length);
+        section_start += length;
+        section
+    });
+    let [Some(endpoints), Some(selectors), Some(tables)] = sections else {
+        return Err(TextureError::InvalidBasis("global data is too short"));
+    };
+
+    let endpoints = read_endpoints(endpoints, endpoint_count)?;
+    let selectors = read_selectors(selectors, selector_count)?;
+    let tables = Tables::read(tables)?;
+    let codebook = Codebook {
+        endpoints,
+        selectors,
+        tables,
+    };
+
+    levels
+        .iter()
+        .enumerate()
+        .map(|(level, data)| {
+            let image = images_start + 20 * level;
+            if field(image, 4)? & 2 != 0 {
+                return Err(TextureError::InvalidBasis("video frames are not supported"));
+            }
+            let slice = |offset: usize, length: usize| {
+                data.get(offset..offset + length)
+                    .ok_or(TextureError::InvalidBasis("slice is outside of its level"))
+            };
+            let blocks_x = (width >> level).max(1).div_ceil(4);
+            let blocks_y = (height >> level).max(1).div_ceil(4);
+
+            let rgb = slice(field(image + 4, 4)?, field(image + 8, 4)?)?;
+            let alpha_length = field(image + 16, 4)?;
+            Ok(Level {
+                rgb: codebook.decode_slice(rgb, blocks_x, blocks_y)?,
+                alpha: match alpha_length {
+                    0 => None,
+                    length => {
+                        let alpha = slice(field(image + 12, 4)?, length)?;
+                        Some(codebook.decode_slice(alpha, blocks_x, blocks_y)?)
+                    }
+                },
+            })
+        })
+        .collect()
+}
+
+/// Reads bit fields from the lowest bit of each byte up, past the end it reads zeros.
+struct BitReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    fn bit(&mut self) -> u32 {
+        let bit = self
+            .data
+            .get(self.offset / 8)
+            .map_or(0, |byte| byte >> (self.offset % 8) & 1);
+        self.offset += 1;
+        bit as u32
+    }
+
+    fn bits(&mut self, count: u32) -> u32 {
+        (0..count).fold(0, |value, i| value | self.bit() << i)
+    }
+
+    /// A variable length number in chunks of `chunk_bits`, each followed by a
+    /// bit that tells whether another chunk comes.
+    fn vlc(&mut self, chunk_bits: u32) -> Result<u32, TextureError> {
+        let mut value = 0;
+        for shift in (0..32).step_by(chunk_bits as usize) {
+            let chunk = self.bits(chunk_bits + 1);
+            value |= (chunk & ((1 << chunk_bits) - 1)) << shift;
+            if chunk >> chunk_bits == 0 {
+                return Ok(value);
+            }
+        }
+        Err(TextureError::InvalidBasis(
+            "variable length number is too long",
+        ))
+    }
+
+    fn symbol(&mut self, huffman: &Huffman) -> Result<u32, TextureError> {
+        huffman.decode(self)
+    }
+}
+
+/// A canonical Huffman code like Deflate's, whose codes are read a bit at a
+/// time from the most significant bit.
+#[derive(Default)]
+struct Huffman {
+    /// Number of codes of each length.
+    counts: [u16; 17],
+    /// Symbols ordered by code.
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(sizes: &[u8]) -> Result<Self, TextureError> {
+        let mut counts = [0u16; 17];
+        for &size in sizes {
+            counts[size as usize] += 1;
+        }
+        counts[0] = 0;
+
+        // More codes of a length than it has room for cannot be decoded
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = 2 * left - count as i32;
+            if left < 0 {
+                return Err(TextureError::InvalidBasis(
+                    "Huffman table is over-subscribed",
+                ));
+            }
+        }
+
+        let mut symbols: Vec<(u8, u16)> = sizes
+            .iter()
+            .enumerate()
+            .filter(|&(_, &size)| size > 0)
+            .map(|(symbol, &size)| (size, symbol as u16))
+            .collect();
+        symbols.sort();
+        Ok(Self {
+            counts,
+            symbols: symbols.into_iter().map(|(_, symbol)| symbol).collect(),
+        })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u32, TextureError> {
+        // First code of the current length and the index of its symbol
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= reader.bit() as i32;
+            let count = count as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize] as u32);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(TextureError::InvalidBasis("invalid Huffman code"))
+    }
+}
+
+/// Reads a Huffman table whose code sizes are themselves Huffman coded.
+fn read_huffman(reader: &mut BitReader) -> Result<Huffman, TextureError> {
+    const CODE_SIZE_ORDER: [usize; 21] = [
+        17, 18, 19, 20, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15, 16,
+    ];
+
+    let symbol_count = reader.bits(14) as usize;
+    if symbol_count == 0 {
+        return Ok(Huffman::default());
+    }
+    let code_size_count = reader.bits(5) as usize;
+    if !(1..=CODE_SIZE_ORDER.len()).contains(&code_size_count) {
+        return Err(TextureError::InvalidBasis("invalid Huffman table"));
+    }
+    let mut code_size_sizes = [0u8; 21];
+    for &symbol in &CODE_SIZE_ORDER[..code_size_count] {
+        code_size_sizes[symbol] = reader.bits(3) as u8;
+    }
+    let code_sizes = Huffman::new(&code_size_sizes)?;
+
+    let mut sizes = Vec::with_capacity(symbol_count);
+    while sizes.len() < symbol_count {
+        let (size, run) = match reader.symbol(&code_sizes)? {
+            size @ 0..=16 => (size as u8, 1),
+            17 => (0, reader.bits(3) + 3),
+            18 => (0, reader.bits(7) + 11),
+            symbol => {
+                let previous = sizes.last().copied().unwrap_or(0);
+                if previous == 0 {
+                    return Err(TextureError::InvalidBasis("invalid Huffman table"));
+                }
+                let run = if symbol == 19 {
+                    reader.bits(2) + 3
+                } else {
+                    reader.bits(7) + 7
+                };
+                (previous, run)
+            }
+        };
+        if sizes.len() + run as usize > symbol_count {
+            return Err(TextureError::InvalidBasis("invalid Huffman table"));
+        }
+        sizes.extend(std::iter::repeat_n(size, run as usize));
+    }
+
+    Huffman::new(&sizes)
+}
+
+/// The color shared by both halves of a block, as 5 bit channels and the
+/// index of the intensity table.
+#[derive(Clone, Copy)]
+struct Endpoint {
+    color: [u32; 3],
+    table: u32,
+}
+
+fn read_endpoints(data: &[u8], count: usize) -> Result<Vec<Endpoint>, TextureError> {
+    let mut reader = BitReader::new(data);
+    let color_models = [
+        read_huffman(&mut reader)?,
+        read_huffman(&mut reader)?,
+        read_huffman(&mut reader)?,
+    ];
+    let table_model = read_huffman(&mut reader)?;
+    let grayscale = reader.bit() == 1;
+
+    // Each endpoint is a delta from the previous one, the color model
+    // depends on the previous value of the channel
+    let mut previous = Endpoint {
+        color: [16; 3],
+        table: 0,
+    };
+    let mut endpoints = Vec::with_capacity(count);
+    for _ in 0..count {
+        let table = (reader.symbol(&table_model)? + previous.table) & 7;
+        let mut color = previous.color;
+        let channels = if grayscale { 1 } else { 3 };
+        for value in &mut color[..channels] {
+            let model = match *value {
+                0..=9 => &color_models[0],
+                10..=21 => &color_models[1],
+                _ => &color_models[2],
+            };
+            *value = (reader.symbol(model)? + *value) & 31;
+        }
+        if grayscale {
+            color = [color[0]; 3];
+        }
+        previous = Endpoint { color, table };
+        endpoints.push(previous);
+    }
+    Ok(endpoints)
+}
+
+/// Reads the selectors of each block as the low word of an ETC1 block.
+fn read_selectors(data: &[u8], count: usize) -> Result<Vec<u32>, TextureError> {
+    let mut reader = BitReader::new(data);
+    if reader.bit() == 1 {
+        return Err(TextureError::InvalidBasis(
+            "global selector codebooks are not supported",
+        ));
+    }
+    if reader.bit() == 1 {
+        return Err(TextureError::InvalidBasis(
+            "hybrid selector codebooks are not supported",
+        ));
+    }
+    let raw = reader.bit() == 1;
+    let model = if raw {
+        Huffman::default()
+    } else {
+        read_huffman(&mut reader)?
+    };
+
+    // A byte per row with two bits per texel, all but the first are
+    // stored as the difference to the previous selector
+    let mut previous = [0u32; 4];
+    let mut selectors = Vec::with_capacity(count);
+    for i in 0..count {
+        for row in &mut previous {
+            *row = if raw || i == 0 {
+                reader.bits(8)
+            } else {
+                reader.symbol(&model)? ^ *row
+            };
+        }
+
+        // From the order of their values to the ETC1 codes, each stored in two bit planes
+        let mut lo = 0;
+        for (y, row) in previous.iter().enumerate() {
+            for x in 0..4 {
+                let code = [3, 2, 0, 1][(row >> (2 * x) & 3) as usize];
+                let texel = x * 4 + y;
+                lo |= (code >> 1) << (16 + texel) | (code & 1) << texel;
+            }
+        }
+        selectors.push(lo);
+    }
+    Ok(selectors)
+}
+
+/// Huffman tables of the slices.
+struct Tables {
+    endpoint_pred: Huffman,
+    endpoint_delta: Huffman,
+    selector: Huffman,
+    selector_run: Huffman,
+    selector_history_size: usize,
+}
+
+impl Tables {
+    fn read(data: &[u8]) -> Result<Self, TextureError> {
+        let mut reader = BitReader::new(data);
+        Ok(Self {
+            endpoint_pred: read_huffman(&mut reader)?,
+            endpoint_delta: read_huffman(&mut reader)?,
+            selector: read_huffman(&mut reader)?,
+            selector_run: read_huffman(&mut reader)?,
+            selector_history_size: reader.bits(13) as usize,
+        })
+    }
+}
+
+/// Recently used selectors, those used again move closer to the front.
+struct SelectorHistory {
+    selectors: Vec<usize>,
+    next: usize,
+}
+
+impl SelectorHistory {
+    fn new(size: usize) -> Self {
+        Self {
+            selectors: vec![0; size],
+            next: size / 2,
+        }
+    }
+
+    fn get(&mut self, index: usize) -> Option<usize> {
+        let selector = *self.selectors.get(index)?;
+        if index > 0 {
+            self.selectors.swap(index / 2, index);
+        }
+        Some(selector)
+    }
+
+    fn add(&mut self, selector: usize) {
+        self.selectors[self.next] = selector;
+        self.next += 1;
+        if self.next == self.selectors.len() {
+            self.next = self.selectors.len() / 2;
+        }
+    }
+}
+
+struct Codebook {
+    endpoints: Vec<Endpoint>,
+    selectors: Vec<u32>,
+    tables: Tables,
+}
+
+/// What the previous row decided for the block in each column.
+#[derive(Clone, Copy, Default)]
+struct Prediction {
+    endpoint: usize,
+    /// Predictions of the 2x2 blocks, the bottom two are read from here.
+    bits: u32,
+}
+
+impl Codebook {
+    /// Decodes a slice of `blocks_x` by `blocks_y` blocks to ETC1 blocks.
+    fn decode_slice(
+        &self,
+        data: &[u8],
+        blocks_x: u32,
+        blocks_y: u32,
+    ) -> Result<Vec<u8>, TextureError> {
+        let invalid = || TextureError::InvalidBasis("invalid slice");
+        let tables = &self.tables;
+        let mut reader = BitReader::new(data);
+        let selector_count = self.selectors.len();
+        let mut history = SelectorHistory::new(tables.selector_history_size);
+        let selector_run_symbol = (selector_count + tables.selector_history_size) as u32;
+
+        // Two rows of predictions, the previous one and the current one
+        let mut predictions = [
+            vec![Prediction::default(); blocks_x as usize],
+            vec![Prediction::default(); blocks_x as usize],
+        ];
+        let (mut pred_bits, mut previous_pred, mut pred_repeat) = (0, 0, 0);
+        let mut previous_endpoint = 0;
+        let mut selector_run = 0;
+
+        let mut blocks = Vec::with_capacity(8 * blocks_x as usize * blocks_y as usize);
+        for y in 0..blocks_y as usize {
+            let (current, above) = (y & 1, (y & 1) ^ 1);
+            for x in 0..blocks_x as usize {
+                // The endpoint predictions of a 2x2 group of blocks share a symbol
+                if x & 1 == 0 {
+                    if y & 1 == 0 {
+                        if pred_repeat > 0 {
+                            pred_repeat -= 1;
+                            pred_bits = previous_pred;
+                        } else {
+                            pred_bits = reader.symbol(&tables.endpoint_pred)?;
+                            if pred_bits == ENDPOINT_PRED_REPEAT_LAST {
+                                pred_repeat = reader.vlc(4)? + 3 - 1;
+                                pred_bits = previous_pred;
+                            } else {
+                                previous_pred = pred_bits;
+                            }
+                        }
+                        predictions[above][x].bits = pred_bits >> 4;
+                    } else {
+                        pred_bits = predictions[current][x].bits;
+                    }
+                }
+
+                let endpoint = match pred_bits & 3 {
+                    // Left, above and above left
+                    0 if x > 0 => previous_endpoint,
+                    1 if y > 0 => predictions[above][x].endpoint,
+                    2 if x > 0 && y > 0 => predictions[above][x - 1].endpoint,
+                    3 => {
+                        let endpoint =
+                            reader.symbol(&tables.endpoint_delta)? as usize + previous_endpoint;
+                        endpoint % self.endpoints.len().max(1)
+                    }
+                    _ => return Err(invalid()),
+                };
+                pred_bits >>= 2;
+                predictions[current][x].endpoint = endpoint;
+                previous_endpoint = endpoint;
+
+                let symbol = if selector_run > 0 {
+                    selector_run -= 1;
+                    selector_count as u32
+                } else {
+                    let symbol = reader.symbol(&tables.selector)?;
+                    if symbol == selector_run_symbol {
+                        // A run of the most recent selector
+                        let run = match reader.symbol(&tables.selector_run)? {
+                            SELECTOR_RUN_ESCAPE => reader.vlc(7)? + MIN_SELECTOR_RUN,
+                            run => run + MIN_SELECTOR_RUN,
+                        };
+                        selector_run = run - 1;
+                        selector_count as u32
+                    } else {
+                        symbol
+                    }
+                };
+                let selector = match (symbol as usize).checked_sub(selector_count) {
+                    Some(index) => history.get(index).ok_or_else(invalid)?,
+                    None => {
+                        if tables.selector_history_size > 0 {
+                            history.add(symbol as usize);
+                        }
+                        symbol as usize
+                    }
+                };
+
+                let endpoint = self.endpoints.get(endpoint).ok_or_else(invalid)?;
+                let lo = *self.selectors.get(selector).ok_or_else(invalid)?;
+                let [r, g, b] = endpoint.color;
+                let table = endpoint.table;
+                // Differential mode with a delta of zero and no flip
+                let hi = r << 27 | g << 19 | b << 11 | table << 5 | table << 2 | 2;
+                blocks.extend(hi.to_be_bytes());
+                blocks.extend(lo.to_be_bytes());
+            }
+        }
+        Ok(blocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::decode::{ETC_MODIFIERS, etc2};
+
+    /// Writes bit fields from the lowest bit of each byte up.
+    #[derive(Default)]
+    struct BitWriter {
+        data: Vec<u8>,
+        offset: usize,
+    }
+
+    impl BitWriter {
+        fn put(&mut self, value: u32, count: u32) {
+            for i in 0..count {
+                if self.offset.is_multiple_of(8) {
+                    self.data.push(0);
+                }
+                *self.data.last_mut().unwrap() |= ((value >> i & 1) as u8) << (self.offset % 8);
+                self.offset += 1;
+            }
+        }
+
+        /// A Huffman code is written from its most significant bit.
+        fn code(&mut self, code: u32, size: u32) {
+            for i in (0..size).rev() {
+                self.put(code >> i, 1);
+            }
+        }
+
+        /// A table that gives the `symbol_count` symbols codes of `size` bits,
+        /// with the code of each symbol being its value.
+        fn fixed_huffman(&mut self, symbol_count: u32, size: u32) {
+            self.put(symbol_count, 14);
+            // Every code size gets a 5 bit code equal to the size
+            self.put(21, 5);
+            for _ in 0..21 {
+                self.put(5, 3);
+            }
+            for _ in 0..symbol_count {
+                self.code(size, 5);
+            }
+        }
+    }
+
+    #[test]
+    fn slice_decodes_to_its_palette_entries() {
+        let mut endpoints = BitWriter::default();
+        for _ in 0..4 {
+            endpoints.fixed_huffman(32, 5);
+        }
+        endpoints.put(0, 1);
+        // Intensity 3 and a red that moves from 16 to 20 and then 24
+        for delta in [[3, 4, 0, 0], [0, 4, 0, 0]] {
+            let [table, r, g, b] = delta;
+            endpoints.code(table, 5);
+            endpoints.code(r, 5);
+            endpoints.code(g, 5);
+            endpoints.code(b, 5);
+        }
+
+        let mut selectors = BitWriter::default();
+        selectors.put(0, 1);
+        selectors.put(0, 1);
+        selectors.put(1, 1);
+        // All the lowest values and all the highest ones
+        for byte in [0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff] {
+            selectors.put(byte, 8);
+        }
+
+        let mut tables = BitWriter::default();
+        tables.fixed_huffman(257, 9);
+        tables.fixed_huffman(2, 1);
+        tables.fixed_huffman(4, 2);
+        tables.fixed_huffman(64, 6);
+        tables.put(0, 13);
+
+        // Two blocks: the delta to endpoint 1 and then the one on its left,
+        // with both selectors
+        let mut slice = BitWriter::default();
+        slice.code(3, 9);
+        slice.code(1, 1);
+        slice.code(0, 2);
+        slice.code(1, 2);
+
+        let mut global_data = Vec::new();
+        for (value, size) in [
+            (2, 2),
+            (2, 2),
+            (endpoints.data.len(), 4),
+            (selectors.data.len(), 4),
+            (tables.data.len(), 4),
+            (0, 4),
+            (0, 4),
+            (0, 4),
+            (slice.data.len(), 4),
+            (0, 4),
+            (0, 4),
+        ] {
+            global_data.extend(&(value as u32).to_le_bytes()[..size]);
+        }
+        global_data.extend(&endpoints.data);
+        global_data.extend(&selectors.data);
+        global_data.extend(&tables.data);
+
+        let levels = decode(&global_data, &[&slice.data], 8, 4).unwrap();
+        assert_eq!(levels.len(), 1);
+        assert!(levels[0].alpha.is_none());
+
+        let [_, large] = ETC_MODIFIERS[3];
+        let base = [24 << 3 | 24 >> 2, 16 << 3 | 16 >> 2, 16 << 3 | 16 >> 2];
+        for (block, modifier) in levels[0].rgb.chunks(8).zip([-large, large]) {
+            let expected = base.map(|c| (c + modifier).clamp(0, 255) as u8);
+            for texel in etc2(block, false) {
+                assert_eq!(texel, [expected[0], expected[1], expected[2], 255]);
+            }
+        }
+    }
+}
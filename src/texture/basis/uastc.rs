@@ -0,0 +1,358 @@
+//! UASTC, 4x4 blocks in a subset of ASTC that is stored so it also transcodes
+//! quickly to BC7 and ETC. Blocks are decoded to RGBA8 with the endpoint and
+//! weight decoding of ASTC.
+
+use crate::texture::decode::Bits;
+use crate::texture::decode::astc::{
+    ERROR_COLOR, ISE_RANGES, endpoints, interpolate, select_partition, unquantize_color,
+    unquantize_weight,
+};
+
+/// Second weight plane of a mode.
+#[derive(Clone, Copy)]
+enum DualPlane {
+    None,
+    /// The channel is stored in the block.
+    Stored,
+    /// Always the alpha of luminance alpha endpoints.
+    Alpha,
+}
+
+#[derive(Clone, Copy)]
+struct Mode {
+    /// Huffman code of the mode, read from the lowest bit.
+    code: u32,
+    code_bits: u32,
+    endpoint_mode: u32,
+    subsets: u32,
+    /// Bits of the partition pattern index.
+    pattern_bits: u32,
+    dual_plane: DualPlane,
+    weight_bits: u32,
+    /// Index into [`ISE_RANGES`] of the endpoints.
+    endpoint_range: usize,
+    /// Bits of the BC1 and ETC hints that follow the code, only other
+    /// transcoders use them.
+    hint_bits: u32,
+}
+
+const fn mode(
+    (code, code_bits): (u32, u32),
+    endpoint_mode: u32,
+    (subsets, pattern_bits): (u32, u32),
+    dual_plane: DualPlane,
+    weight_bits: u32,
+    endpoint_range: usize,
+    hint_bits: u32,
+) -> Mode {
+    Mode {
+        code,
+        code_bits,
+        endpoint_mode,
+        subsets,
+        pattern_bits,
+        dual_plane,
+        weight_bits,
+        endpoint_range,
+        hint_bits,
+    }
+}
+
+/// The modes in the order of their index, mode 8 is a solid color and handled apart.
+#[rustfmt::skip]
+const MODES: [Mode; 19] = [
+    mode((0x01, 4), 8, (1, 0), DualPlane::None, 4, 19, 15),
+    mode((0x35, 6), 8, (1, 0), DualPlane::None, 2, 20, 15),
+    mode((0x1d, 5), 8, (2, 5), DualPlane::None, 3, 8, 15),
+    mode((0x03, 5), 8, (3, 4), DualPlane::None, 2, 7, 15),
+    mode((0x13, 5), 8, (2, 5), DualPlane::None, 2, 12, 15),
+    mode((0x0b, 5), 8, (1, 0), DualPlane::None, 3, 20, 15),
+    mode((0x1b, 5), 8, (1, 0), DualPlane::Stored, 2, 18, 15),
+    mode((0x07, 5), 8, (2, 5), DualPlane::None, 2, 12, 15),
+    mode((0x17, 5), 0, (1, 0), DualPlane::None, 0, 0, 0),
+    mode((0x0f, 5), 12, (2, 5), DualPlane::None, 2, 8, 23),
+    mode((0x02, 3), 12, (1, 0), DualPlane::None, 4, 13, 17),
+    mode((0x00, 2), 12, (1, 0), DualPlane::Stored, 2, 13, 17),
+    mode((0x06, 3), 12, (1, 0), DualPlane::None, 3, 19, 17),
+    mode((0x1f, 5), 12, (1, 0), DualPlane::Stored, 1, 20, 23),
+    mode((0x0d, 5), 12, (1, 0), DualPlane::None, 2, 20, 23),
+    mode((0x05, 7), 4, (1, 0), DualPlane::None, 4, 20, 23),
+    mode((0x15, 6), 4, (2, 5), DualPlane::None, 2, 20, 23),
+    mode((0x25, 6), 4, (1, 0), DualPlane::Alpha, 2, 20, 23),
+    mode((0x09, 4), 8, (1, 0), DualPlane::None, 5, 11, 15),
+];
+
+const SOLID_MODE: usize = 8;
+
+/// The 2 subset patterns: BC7 partition, ASTC partition seed and whether
+/// the ASTC subsets are swapped relative to BC7.
+#[rustfmt::skip]
+const PARTITIONS_2: [(u32, u32, bool); 30] = [
+    (0, 28, false), (1, 20, false), (2, 16, true), (3, 29, false), (4, 91, true),
+    (5, 9, false), (6, 107, true), (7, 72, true), (8, 149, false), (9, 204, true),
+    (10, 50, false), (11, 114, true), (12, 496, true), (13, 17, true), (14, 78, false),
+    (15, 39, true), (17, 252, true), (18, 828, true), (19, 43, false), (20, 156, false),
+    (21, 116, false), (22, 210, true), (23, 476, true), (24, 273, false), (25, 684, true),
+    (26, 359, false), (29, 246, true), (32, 195, true), (33, 694, true), (52, 524, true),
+];
+
+/// The 3 subset patterns: BC7 partition and ASTC partition seed.
+#[rustfmt::skip]
+const PARTITIONS_3: [(u32, u32); 11] = [
+    (4, 260), (8, 74), (9, 32), (10, 156), (11, 183), (12, 15),
+    (13, 745), (20, 0), (35, 335), (36, 902), (57, 254),
+];
+
+/// Patterns of mode 7, 3 BC7 subsets of which two are one ASTC subset: BC7
+/// partition and ASTC partition seed.
+#[rustfmt::skip]
+const PARTITIONS_3_AS_2: [(u32, u32); 19] = [
+    (10, 36), (11, 48), (0, 61), (2, 137), (8, 161), (13, 183), (1, 226), (33, 281),
+    (40, 302), (20, 307), (21, 479), (58, 495), (3, 593), (32, 594), (59, 605), (34, 799),
+    (20, 812), (14, 988), (31, 993),
+];
+
+/// The mode of a block from the code in its lowest bits.
+fn block_mode(bits: u128) -> Option<usize> {
+    let matches = |(code, code_bits): (u32, u32)| bits as u32 & ((1 << code_bits) - 1) == code;
+    MODES
+        .iter()
+        .position(|mode| matches((mode.code, mode.code_bits)))
+}
+
+/// Decodes a UASTC block to its texels in row order. Invalid blocks decode to
+/// the ASTC error color.
+pub(super) fn decode_block(block: &[u8]) -> [[u8; 4]; 16] {
+    let bits = u128::from_le_bytes(block[..16].try_into().unwrap());
+    decode(bits).unwrap_or([ERROR_COLOR; 16])
+}
+
+fn decode(bits: u128) -> Option<[[u8; 4]; 16]> {
+    let index = block_mode(bits)?;
+    let mode = MODES[index];
+    let mut bits = Bits(bits >> mode.code_bits);
+
+    if index == SOLID_MODE {
+        let color = [0; 4].map(|_| bits.take(8));
+        return Some([color; 16]);
+    }
+
+    bits.0 >>= mode.hint_bits;
+    let pattern = bits.take(mode.pattern_bits) as usize;
+    let seed = match (index, mode.subsets) {
+        (_, 1) => 0,
+        (7, _) => PARTITIONS_3_AS_2.get(pattern)?.1,
+        (_, 2) => PARTITIONS_2.get(pattern)?.1,
+        _ => PARTITIONS_3.get(pattern)?.1,
+    };
+    let subsets = if index == 7 { 2 } else { mode.subsets };
+    let dual_plane = match mode.dual_plane {
+        DualPlane::None => None,
+        DualPlane::Stored => Some(bits.take(2) as u32),
+        DualPlane::Alpha => Some(3),
+    };
+
+    let values_per_subset = 2 * (mode.endpoint_mode / 4 + 1);
+    let mut colors = read_endpoints(&mut bits, mode.endpoint_range, subsets * values_per_subset);
+
+    // The first texel of each subset stores its weights with one bit less
+    let planes = 1 + dual_plane.is_some() as usize;
+    let subset_of = |texel: usize| match subsets {
+        1 => 0,
+        _ => select_partition(seed, texel as u32 % 4, texel as u32 / 4, subsets, true) as usize,
+    };
+    let mut anchors = [false; 16];
+    let mut seen = [false; 4];
+    for (texel, anchor) in anchors.iter_mut().enumerate() {
+        let subset = subset_of(texel);
+        *anchor = !std::mem::replace(&mut seen[subset], true);
+    }
+    let mut weights = [0u32; 32];
+    for texel in 0..16 {
+        for plane in 0..planes {
+            let count = mode.weight_bits - anchors[texel] as u32;
+            weights[texel * planes + plane] = bits.take(count) as u32;
+        }
+    }
+    let weights = &mut weights[..16 * planes];
+
+    let (value_bits, _) = ISE_RANGES[mode.endpoint_range];
+    let unquantize = |value: u32| {
+        unquantize_color(
+            mode.endpoint_range,
+            (value >> value_bits, value & ((1 << value_bits) - 1)),
+        )
+    };
+
+    // Direct RGB endpoints whose second color sums lower are decoded with blue
+    // contraction in ASTC, which UASTC does not use, swapping the endpoints keeps
+    // the colors
+    if matches!(mode.endpoint_mode, 8 | 12) {
+        let max_weight = (1 << mode.weight_bits) - 1;
+        for subset in 0..subsets as usize {
+            let values =
+                &mut colors[subset * values_per_subset as usize..][..values_per_subset as usize];
+            let sum = |end: usize| {
+                (0..3)
+                    .map(|c| unquantize(values[2 * c + end]) as u32)
+                    .sum::<u32>()
+            };
+            if sum(1) < sum(0) {
+                for pair in values.chunks_exact_mut(2) {
+                    pair.swap(0, 1);
+                }
+                for texel in (0..16).filter(|&texel| subset_of(texel) == subset) {
+                    for weight in &mut weights[texel * planes..][..planes] {
+                        *weight = max_weight - *weight;
+                    }
+                }
+            }
+        }
+    }
+
+    let values: Vec<i32> = colors
+        .iter()
+        .map(|&value| unquantize(value) as i32)
+        .collect();
+    let pairs = values
+        .chunks_exact(values_per_subset as usize)
+        .map(|values| endpoints(mode.endpoint_mode, values))
+        .collect::<Option<Vec<_>>>()?;
+    // The weights are plain binary values in the ranges of 1 to 5 bits
+    let weight_range = [0, 0, 2, 5, 8, 11][mode.weight_bits as usize];
+
+    let mut texels = [[0; 4]; 16];
+    for (texel, color) in texels.iter_mut().enumerate() {
+        let [e0, e1] = pairs[subset_of(texel)];
+        for (channel, value) in color.iter_mut().enumerate() {
+            let plane = (dual_plane == Some(channel as u32)) as usize;
+            let weight = unquantize_weight(weight_range, (0, weights[texel * planes + plane]));
+            *value = interpolate(e0[channel], e1[channel], weight, false);
+        }
+    }
+    Some(texels)
+}
+
+/// Reads `count` endpoint values in `range` as `tq << bits | low`. Unlike ASTC
+/// the trits and quints of all values come first, packed as base 3 or 5 numbers.
+fn read_endpoints(bits: &mut Bits, range: usize, count: u32) -> Vec<u32> {
+    let (value_bits, multiplier) = ISE_RANGES[range];
+    let (group, packed_bits): (usize, &[u32]) = match multiplier {
+        3 => (5, &[0, 2, 4, 5, 7, 8]),
+        5 => (3, &[0, 3, 5, 7]),
+        _ => (1, &[0, 0]),
+    };
+
+    let mut values = vec![0; count as usize];
+    for chunk in values.chunks_mut(group) {
+        let mut packed = bits.take(packed_bits[chunk.len()]) as u32;
+        for value in chunk {
+            *value = packed % multiplier;
+            packed /= multiplier;
+        }
+    }
+    for value in &mut values {
+        *value = *value << value_bits | bits.take(value_bits) as u32;
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::decode::{BC7_PARTITIONS_2, BC7_PARTITIONS_3};
+
+    /// Code of the reserved mode, blocks that use it are invalid.
+    const RESERVED_CODE: (u32, u32) = (0x45, 7);
+
+    #[test]
+    fn mode_codes_are_a_complete_prefix_code() {
+        for bits in 0..128u32 {
+            let matching = MODES
+                .iter()
+                .map(|mode| (mode.code, mode.code_bits))
+                .chain([RESERVED_CODE])
+                .filter(|&(code, code_bits)| bits & ((1 << code_bits) - 1) == code)
+                .count();
+            assert_eq!(matching, 1, "{bits:#09b}");
+        }
+    }
+
+    /// The BC7 subset and ASTC partition of every texel of a pattern.
+    fn subsets(bc7: impl Fn(usize) -> usize, seed: u32, count: u32) -> Vec<(usize, usize)> {
+        (0..16)
+            .map(|texel| {
+                let astc = select_partition(seed, texel as u32 % 4, texel as u32 / 4, count, true);
+                (bc7(texel), astc as usize)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn partition_seeds_match_the_bc7_patterns() {
+        for (bc7, seed, invert) in PARTITIONS_2 {
+            let bc7_subset = |texel| (BC7_PARTITIONS_2[bc7 as usize] >> texel) as usize & 1;
+            for (bc7_subset, astc) in subsets(bc7_subset, seed, 2) {
+                assert_eq!(bc7_subset ^ invert as usize, astc, "BC7 pattern {bc7}");
+            }
+        }
+
+        // Each BC7 subset maps to one ASTC subset, one to one for 3 subsets
+        let bc7_subset =
+            |bc7: u32| move |texel| (BC7_PARTITIONS_3[bc7 as usize] >> (2 * texel)) as usize & 3;
+        for (patterns, count) in [(&PARTITIONS_3[..], 3), (&PARTITIONS_3_AS_2[..], 2)] {
+            for &(bc7, seed) in patterns {
+                let mut mapping = [None; 3];
+                for (bc7_subset, astc) in subsets(bc7_subset(bc7), seed, count) {
+                    assert_eq!(
+                        *mapping[bc7_subset].get_or_insert(astc),
+                        astc,
+                        "BC7 pattern {bc7}"
+                    );
+                }
+                let mut used: Vec<_> = mapping.iter().flatten().collect();
+                used.sort();
+                used.dedup();
+                assert_eq!(used.len(), count as usize, "BC7 pattern {bc7}");
+            }
+        }
+    }
+
+    #[test]
+    fn solid_block_decodes_to_its_color() {
+        let color = [12u128, 34, 56, 78];
+        let bits = color
+            .iter()
+            .enumerate()
+            .fold(0x17, |bits, (i, &c)| bits | c << (5 + 8 * i));
+        assert_eq!(decode_block(&bits.to_le_bytes()), [[12, 34, 56, 78]; 16]);
+    }
+
+    #[test]
+    fn direct_block_decodes_to_its_endpoints() {
+        // Mode 18: one subset of RGB endpoints and weights with 5 bits each
+        let mut writer = 0u128;
+        let mut offset = 0;
+        let mut put = |value: u128, count: u32| {
+            writer |= value << offset;
+            offset += count;
+        };
+        put(0x09, 4);
+        put(0, 15);
+        // Red from 0 to 255, green and blue 0
+        for value in [0, 31, 0, 0, 0, 0] {
+            put(value, 5);
+        }
+        // Weights rising along the rows, the first one has a bit less
+        let weights = [0, 10, 20, 31];
+        for texel in 0..16 {
+            put(weights[texel % 4], if texel == 0 { 4 } else { 5 });
+        }
+        let texels = decode_block(&writer.to_le_bytes());
+        for (texel, color) in texels.iter().enumerate() {
+            let weight = unquantize_weight(11, (0, weights[texel % 4] as u32));
+            let red = interpolate(0, 255, weight, false);
+            assert_eq!(*color, [red, 0, 0, 255], "texel {texel}");
+        }
+    }
+}
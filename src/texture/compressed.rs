@@ -0,0 +1,229 @@
+use std::borrow::Cow;
+use std::io::Read;
+
+use anyhow::Result;
+use ktx2::{Format, SupercompressionScheme};
+use wgpu::util::DeviceExt;
+
+use super::{MipmapGenerator, Texture, TextureError, TextureRole, basis, decode};
+
+/// How the texels of a KTX2 format are stored.
+pub(super) enum Encoding {
+    /// Uncompressed 8 bit channels, expanded to RGBA8 on upload.
+    Channels(usize),
+    /// Blocks that are uploaded as is when the device supports the format.
+    Blocks(wgpu::TextureFormat),
+}
+
+fn encoding(format: Format) -> Option<Encoding> {
+    use wgpu::{AstcBlock as B, AstcChannel, TextureFormat as F};
+
+    let astc = |block| {
+        Encoding::Blocks(F::Astc {
+            block,
+            channel: AstcChannel::Unorm,
+        })
+    };
+
+    let encoding = match format {
+        Format::R8_UNORM | Format::R8_SRGB => Encoding::Channels(1),
+        Format::R8G8_UNORM | Format::R8G8_SRGB => Encoding::Channels(2),
+        Format::R8G8B8_UNORM | Format::R8G8B8_SRGB => Encoding::Channels(3),
+        Format::R8G8B8A8_UNORM | Format::R8G8B8A8_SRGB => Encoding::Channels(4),
+        // wgpu has no BC1 without alpha, the opaque variant decodes identically for opaque blocks
+        Format::BC1_RGB_UNORM_BLOCK
+        | Format::BC1_RGB_SRGB_BLOCK
+        | Format::BC1_RGBA_UNORM_BLOCK
+        | Format::BC1_RGBA_SRGB_BLOCK => Encoding::Blocks(F::Bc1RgbaUnorm),
+        Format::BC2_UNORM_BLOCK | Format::BC2_SRGB_BLOCK => Encoding::Blocks(F::Bc2RgbaUnorm),
+        Format::BC3_UNORM_BLOCK | Format::BC3_SRGB_BLOCK => Encoding::Blocks(F::Bc3RgbaUnorm),
+        Format::BC4_UNORM_BLOCK => Encoding::Blocks(F::Bc4RUnorm),
+        Format::BC4_SNORM_BLOCK => Encoding::Blocks(F::Bc4RSnorm),
+        Format::BC5_UNORM_BLOCK => Encoding::Blocks(F::Bc5RgUnorm),
+        Format::BC5_SNORM_BLOCK => Encoding::Blocks(F::Bc5RgSnorm),
+        Format::BC6H_UFLOAT_BLOCK => Encoding::Blocks(F::Bc6hRgbUfloat),
+        Format::BC6H_SFLOAT_BLOCK => Encoding::Blocks(F::Bc6hRgbFloat),
+        Format::BC7_UNORM_BLOCK | Format::BC7_SRGB_BLOCK => Encoding::Blocks(F::Bc7RgbaUnorm),
+        Format::ETC2_R8G8B8_UNORM_BLOCK | Format::ETC2_R8G8B8_SRGB_BLOCK => {
+            Encoding::Blocks(F::Etc2Rgb8Unorm)
+        }
+        Format::ETC2_R8G8B8A1_UNORM_BLOCK | Format::ETC2_R8G8B8A1_SRGB_BLOCK => {
+            Encoding::Blocks(F::Etc2Rgb8A1Unorm)
+        }
+        Format::ETC2_R8G8B8A8_UNORM_BLOCK | Format::ETC2_R8G8B8A8_SRGB_BLOCK => {
+            Encoding::Blocks(F::Etc2Rgba8Unorm)
+        }
+        Format::EAC_R11_UNORM_BLOCK => Encoding::Blocks(F::EacR11Unorm),
+        Format::EAC_R11_SNORM_BLOCK => Encoding::Blocks(F::EacR11Snorm),
+        Format::EAC_R11G11_UNORM_BLOCK => Encoding::Blocks(F::EacRg11Unorm),
+        Format::EAC_R11G11_SNORM_BLOCK => Encoding::Blocks(F::EacRg11Snorm),
+        Format::ASTC_4x4_UNORM_BLOCK | Format::ASTC_4x4_SRGB_BLOCK => astc(B::B4x4),
+        Format::ASTC_5x4_UNORM_BLOCK | Format::ASTC_5x4_SRGB_BLOCK => astc(B::B5x4),
+        Format::ASTC_5x5_UNORM_BLOCK | Format::ASTC_5x5_SRGB_BLOCK => astc(B::B5x5),
+        Format::ASTC_6x5_UNORM_BLOCK | Format::ASTC_6x5_SRGB_BLOCK => astc(B::B6x5),
+        Format::ASTC_6x6_UNORM_BLOCK | Format::ASTC_6x6_SRGB_BLOCK => astc(B::B6x6),
+        Format::ASTC_8x5_UNORM_BLOCK | Format::ASTC_8x5_SRGB_BLOCK => astc(B::B8x5),
+        Format::ASTC_8x6_UNORM_BLOCK | Format::ASTC_8x6_SRGB_BLOCK => astc(B::B8x6),
+        Format::ASTC_8x8_UNORM_BLOCK | Format::ASTC_8x8_SRGB_BLOCK => astc(B::B8x8),
+        Format::ASTC_10x5_UNORM_BLOCK | Format::ASTC_10x5_SRGB_BLOCK => astc(B::B10x5),
+        Format::ASTC_10x6_UNORM_BLOCK | Format::ASTC_10x6_SRGB_BLOCK => astc(B::B10x6),
+        Format::ASTC_10x8_UNORM_BLOCK | Format::ASTC_10x8_SRGB_BLOCK => astc(B::B10x8),
+        Format::ASTC_10x10_UNORM_BLOCK | Format::ASTC_10x10_SRGB_BLOCK => astc(B::B10x10),
+        Format::ASTC_12x10_UNORM_BLOCK | Format::ASTC_12x10_SRGB_BLOCK => astc(B::B12x10),
+        Format::ASTC_12x12_UNORM_BLOCK | Format::ASTC_12x12_SRGB_BLOCK => astc(B::B12x12),
+        _ => return None,
+    };
+
+    Some(encoding)
+}
+
+/// Bytes of one mip level of a block compressed texture.
+fn level_byte_size(format: wgpu::TextureFormat, width: u32, height: u32) -> usize {
+    let (block_width, block_height) = format.block_dimensions();
+    let block_size = format.block_copy_size(None).unwrap_or_default() as usize;
+    width.div_ceil(block_width) as usize * height.div_ceil(block_height) as usize * block_size
+}
+
+fn decompress(data: &[u8], size: u64) -> Result<Vec<u8>> {
+    let mut decoder = ruzstd::decoding::StreamingDecoder::new(data)?;
+    let mut decompressed = Vec::with_capacity(size as usize);
+    decoder.read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+pub(super) fn load(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    mipmaps: &mut MipmapGenerator,
+    bytes: &[u8],
+    role: TextureRole,
+    label: Option<&str>,
+) -> Result<Texture> {
+    let reader = ktx2::Reader::new(bytes).map_err(TextureError::InvalidKtx2)?;
+    let header = reader.header();
+    if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count != 1 {
+        return Err(TextureError::UnsupportedKtx2Layout.into());
+    }
+
+    match header.supercompression_scheme {
+        None | Some(SupercompressionScheme::Zstandard) => {}
+        // Only ETC1S, which has no Vulkan format, uses BasisLZ
+        Some(SupercompressionScheme::BasisLZ) if header.format.is_none() => {}
+        Some(scheme) => return Err(TextureError::UnsupportedSupercompression(scheme).into()),
+    }
+
+    let mut levels = reader
+        .levels()
+        .map(|level| match header.supercompression_scheme {
+            Some(SupercompressionScheme::Zstandard) => {
+                decompress(level.data, level.uncompressed_byte_length).map(Cow::Owned)
+            }
+            _ => Ok(Cow::Borrowed(level.data)),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let size = wgpu::Extent3d {
+        width: header.pixel_width,
+        height: header.pixel_height.max(1),
+        depth_or_array_layers: 1,
+    };
+    let encoding = match header.format {
+        Some(format) => encoding(format).ok_or(TextureError::UnsupportedKtx2Format(format))?,
+        None => {
+            let (encoding, transcoded) = basis::transcode(&reader, &levels)?;
+            levels = transcoded.into_iter().map(Cow::Owned).collect();
+            encoding
+        }
+    };
+    let (width, height) = (size.width as usize, size.height as usize);
+
+    let format = match encoding {
+        Encoding::Channels(channels) => {
+            let pixels = levels[0]
+                .get(..channels * width * height)
+                .ok_or(TextureError::Ktx2LevelSize { level: 0 })?;
+            let rgba = pixels
+                .chunks_exact(channels)
+                .flat_map(|texel| match *texel {
+                    [r] => [r, 0, 0, 255],
+                    [r, g] => [r, g, 0, 255],
+                    [r, g, b] => [r, g, b, 255],
+                    [r, g, b, a] => [r, g, b, a],
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<_>>();
+
+            let mut texture =
+                Texture::from_image_mipmapped(device, queue, mipmaps, size, &rgba, role, label)?;
+            texture.stored_format = match channels {
+                1 => wgpu::TextureFormat::R8Unorm,
+                2 => wgpu::TextureFormat::Rg8Unorm,
+                _ => texture.stored_format,
+            };
+            return Ok(texture);
+        }
+        Encoding::Blocks(format) => match role {
+            TextureRole::Color => format.add_srgb_suffix(),
+            TextureRole::Data => format.remove_srgb_suffix(),
+        },
+    };
+
+    // The stored levels stop at the first one that is missing or has the wrong size
+    let mut data = Vec::new();
+    let mut mip_level_count = 0;
+    for (level, bytes) in levels.iter().enumerate() {
+        let mip_size = size.mip_level_size(level as u32, wgpu::TextureDimension::D2);
+        let Some(bytes) = bytes.get(..level_byte_size(format, mip_size.width, mip_size.height))
+        else {
+            break;
+        };
+        data.extend_from_slice(bytes);
+        mip_level_count += 1;
+    }
+    if mip_level_count == 0 {
+        return Err(TextureError::Ktx2LevelSize { level: 0 }.into());
+    }
+
+    let (block_width, block_height) = format.block_dimensions();
+    let block_aligned =
+        size.width.is_multiple_of(block_width) && size.height.is_multiple_of(block_height);
+    if device.features().contains(format.required_features()) && block_aligned {
+        let texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label,
+                size,
+                mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            &data,
+        );
+
+        return Ok(Texture::from_texture(device, texture));
+    }
+
+    // Without support the first level is decoded and the mip chain generated again
+    let (decoded_format, pixels) = decode::decode(format, size.width, size.height, &levels[0])
+        .ok_or(TextureError::NoCpuDecoder(format))?;
+    log::debug!(
+        "Decoded {} as {format:?} is not supported by the device",
+        label.unwrap_or("texture")
+    );
+
+    let mut texture = Texture::from_pixels_mipmapped(
+        device,
+        queue,
+        mipmaps,
+        size,
+        &pixels,
+        decoded_format,
+        label,
+    )?;
+    texture.stored_format = format;
+    Ok(texture)
+}
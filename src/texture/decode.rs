@@ -0,0 +1,1035 @@
+//! CPU decoders for block compressed formats, used when the device cannot
+//! sample them directly.
+//!
+//! Every decoder produces the channel layout the GPU would return, single and
+//! dual channel formats fill the missing channels with 0 and alpha with 1.
+//! Unsigned formats decode to RGBA8, signed and HDR ones to RGBA16F so that
+//! negative and large values survive.
+
+pub(super) mod astc;
+mod bc6h;
+
+use half::f16;
+
+/// Decodes the blocks of a `width` by `height` image, `None` when there is no
+/// decoder for the format or `data` is too short. Returns the format of the
+/// decoded pixels, RGBA8 in the color space of `format` or RGBA16F.
+pub(super) fn decode(
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    data: &[u8],
+) -> Option<(wgpu::TextureFormat, Vec<u8>)> {
+    use wgpu::TextureFormat as F;
+
+    let unorm = |decode_block: fn(&[u8]) -> [[u8; 4]; 16]| {
+        let texels = decode_blocks(format, width, height, data, |block, texels| {
+            texels.copy_from_slice(&decode_block(block))
+        })?;
+        let format = if format.is_srgb() {
+            F::Rgba8UnormSrgb
+        } else {
+            F::Rgba8Unorm
+        };
+        Some((format, texels.concat()))
+    };
+    let float = |decode_block: fn(&[u8]) -> [[f16; 4]; 16]| {
+        let texels = decode_blocks(format, width, height, data, |block, texels| {
+            texels.copy_from_slice(&decode_block(block))
+        })?;
+        let bytes = texels
+            .iter()
+            .flatten()
+            .flat_map(|channel| channel.to_le_bytes())
+            .collect();
+        Some((F::Rgba16Float, bytes))
+    };
+
+    match format.remove_srgb_suffix() {
+        F::Bc1RgbaUnorm => unorm(|block| bc1(block, true)),
+        F::Bc2RgbaUnorm => unorm(bc2),
+        F::Bc3RgbaUnorm => unorm(bc3),
+        F::Bc4RUnorm => unorm(bc4),
+        F::Bc4RSnorm => float(bc4_snorm),
+        F::Bc5RgUnorm => unorm(bc5),
+        F::Bc5RgSnorm => float(bc5_snorm),
+        F::Bc6hRgbUfloat => float(|block| bc6h::decode_block(block, false)),
+        F::Bc6hRgbFloat => float(|block| bc6h::decode_block(block, true)),
+        F::Bc7RgbaUnorm => unorm(bc7),
+        F::Etc2Rgb8Unorm => unorm(|block| etc2(block, false)),
+        F::Etc2Rgb8A1Unorm => unorm(|block| etc2(block, true)),
+        F::Etc2Rgba8Unorm => unorm(etc2_rgba8),
+        F::EacR11Unorm => unorm(eac_r11),
+        F::EacR11Snorm => float(eac_r11_snorm),
+        F::EacRg11Unorm => unorm(eac_rg11),
+        F::EacRg11Snorm => float(eac_rg11_snorm),
+        F::Astc {
+            block: _,
+            channel: wgpu::AstcChannel::Unorm,
+        } => {
+            let (block_width, block_height) = format.block_dimensions();
+            let texels = decode_blocks(format, width, height, data, |block, texels| {
+                astc::decode_block(block, block_width, block_height, format.is_srgb(), texels)
+            })?;
+            // Only the top 8 bits of the decoded sRGB values are meaningful
+            let format = if format.is_srgb() {
+                F::Rgba8UnormSrgb
+            } else {
+                F::Rgba8Unorm
+            };
+            Some((format, texels.concat()))
+        }
+        _ => None,
+    }
+}
+
+/// Calls `decode_block` for every block of the image, which fills the texels
+/// of one block row by row, and puts the texels in place.
+pub(super) fn decode_blocks<T: Copy + Default>(
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    data: &[u8],
+    decode_block: impl Fn(&[u8], &mut [T]),
+) -> Option<Vec<T>> {
+    let (block_width, block_height) = format.block_dimensions();
+    let block_size = format.block_copy_size(None)? as usize;
+
+    let blocks_x = width.div_ceil(block_width) as usize;
+    let blocks_y = height.div_ceil(block_height) as usize;
+    if data.len() < blocks_x * blocks_y * block_size {
+        return None;
+    }
+
+    let (width, height) = (width as usize, height as usize);
+    let (block_width, block_height) = (block_width as usize, block_height as usize);
+    let mut texels = vec![T::default(); width * height];
+    let mut block_texels = vec![T::default(); block_width * block_height];
+    for (i, block) in data
+        .chunks_exact(block_size)
+        .take(blocks_x * blocks_y)
+        .enumerate()
+    {
+        decode_block(block, &mut block_texels);
+        let (block_x, block_y) = (block_width * (i % blocks_x), block_height * (i / blocks_x));
+        for (texel, value) in block_texels.iter().enumerate() {
+            let x = block_x + texel % block_width;
+            let y = block_y + texel / block_width;
+            // Blocks on the right and bottom edge may extend past the image
+            if x < width && y < height {
+                texels[y * width + x] = *value;
+            }
+        }
+    }
+
+    Some(texels)
+}
+
+fn rgb565(color: u16) -> [u8; 3] {
+    let r = (color >> 11) as u8 & 31;
+    let g = (color >> 5) as u8 & 63;
+    let b = color as u8 & 31;
+    [
+        (r << 3) | (r >> 2),
+        (g << 2) | (g >> 4),
+        (b << 3) | (b >> 2),
+    ]
+}
+
+/// The color part shared by BC1, BC2 and BC3. Only BC1 switches to three
+/// colors and transparent black when the first endpoint is not larger.
+fn bc1(block: &[u8], punch_through: bool) -> [[u8; 4]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+
+    let [e0, e1] = [rgb565(c0), rgb565(c1)];
+    let mix = |w0: u32, w1: u32| -> [u8; 4] {
+        let channel = |i: usize| ((w0 * e0[i] as u32 + w1 * e1[i] as u32) / (w0 + w1)) as u8;
+        [channel(0), channel(1), channel(2), 255]
+    };
+    let palette = if c0 > c1 || !punch_through {
+        [mix(1, 0), mix(0, 1), mix(2, 1), mix(1, 2)]
+    } else {
+        [mix(1, 0), mix(0, 1), mix(1, 1), [0; 4]]
+    };
+
+    std::array::from_fn(|i| palette[(indices >> (2 * i)) as usize & 3])
+}
+
+fn bc2(block: &[u8]) -> [[u8; 4]; 16] {
+    let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+    let mut texels = bc1(&block[8..], false);
+    for (i, texel) in texels.iter_mut().enumerate() {
+        texel[3] = ((alpha >> (4 * i)) as u8 & 15) * 17;
+    }
+    texels
+}
+
+fn bc3(block: &[u8]) -> [[u8; 4]; 16] {
+    let alpha = bc4_channel(&block[..8]);
+    let mut texels = bc1(&block[8..], false);
+    for (texel, alpha) in texels.iter_mut().zip(alpha) {
+        texel[3] = alpha;
+    }
+    texels
+}
+
+fn bc4(block: &[u8]) -> [[u8; 4]; 16] {
+    bc4_channel(block).map(|r| [r, 0, 0, 255])
+}
+
+fn bc5(block: &[u8]) -> [[u8; 4]; 16] {
+    let r = bc4_channel(&block[..8]);
+    let g = bc4_channel(&block[8..]);
+    std::array::from_fn(|i| [r[i], g[i], 0, 255])
+}
+
+fn bc4_snorm(block: &[u8]) -> [[f16; 4]; 16] {
+    bc4_snorm_channel(block).map(|r| [r, f16::ZERO, f16::ZERO, f16::ONE])
+}
+
+fn bc5_snorm(block: &[u8]) -> [[f16; 4]; 16] {
+    let r = bc4_snorm_channel(&block[..8]);
+    let g = bc4_snorm_channel(&block[8..]);
+    std::array::from_fn(|i| [r[i], g[i], f16::ZERO, f16::ONE])
+}
+
+/// Two 8 bit endpoints with 3 bit indices, the alpha block of BC3 and the channels of BC4/BC5.
+fn bc4_channel(block: &[u8]) -> [u8; 16] {
+    let [a0, a1] = [block[0] as u32, block[1] as u32];
+    let mut bits = [0; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+
+    let palette: [u8; 8] = if a0 > a1 {
+        std::array::from_fn(|i| match i {
+            0 => a0 as u8,
+            1 => a1 as u8,
+            i => (((8 - i as u32) * a0 + (i as u32 - 1) * a1) / 7) as u8,
+        })
+    } else {
+        std::array::from_fn(|i| match i {
+            0 => a0 as u8,
+            1 => a1 as u8,
+            6 => 0,
+            7 => 255,
+            i => (((6 - i as u32) * a0 + (i as u32 - 1) * a1) / 5) as u8,
+        })
+    };
+
+    std::array::from_fn(|i| palette[(indices >> (3 * i)) as usize & 7])
+}
+
+/// The signed variant of [`bc4_channel`], the endpoints are two's complement
+/// with -128 clamped to -127.
+fn bc4_snorm_channel(block: &[u8]) -> [f16; 16] {
+    let [a0, a1] = [block[0], block[1]].map(|endpoint| (endpoint as i8).max(-127) as f32 / 127.0);
+    let mut bits = [0; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+
+    let palette: [f32; 8] = if block[0] as i8 > block[1] as i8 {
+        std::array::from_fn(|i| match i {
+            0 => a0,
+            1 => a1,
+            i => ((8 - i) as f32 * a0 + (i - 1) as f32 * a1) / 7.0,
+        })
+    } else {
+        std::array::from_fn(|i| match i {
+            0 => a0,
+            1 => a1,
+            6 => -1.0,
+            7 => 1.0,
+            i => ((6 - i) as f32 * a0 + (i - 1) as f32 * a1) / 5.0,
+        })
+    };
+
+    std::array::from_fn(|i| f16::from_f32(palette[(indices >> (3 * i)) as usize & 7]))
+}
+
+/// Reads little endian bit fields from the start of a block.
+pub(super) struct Bits(pub(super) u128);
+
+impl Bits {
+    pub(super) fn take(&mut self, count: u32) -> u8 {
+        let value = (self.0 & ((1 << count) - 1)) as u8;
+        self.0 >>= count;
+        value
+    }
+}
+
+/// Subset of every texel for the 2 subset BC7 partitions, one bit per texel.
+pub(super) const BC7_PARTITIONS_2: [u16; 64] = [
+    0xcccc, 0x8888, 0xeeee, 0xecc8, 0xc880, 0xfeec, 0xfec8, 0xec80, 0xc800, 0xffec, 0xfe80, 0xe800,
+    0xffe8, 0xff00, 0xfff0, 0xf000, 0xf710, 0x008e, 0x7100, 0x08ce, 0x008c, 0x7310, 0x3100, 0x8cce,
+    0x088c, 0x3110, 0x6666, 0x366c, 0x17e8, 0x0ff0, 0x718e, 0x399c, 0xaaaa, 0xf0f0, 0x5a5a, 0x33cc,
+    0x3c3c, 0x55aa, 0x9696, 0xa55a, 0x73ce, 0x13c8, 0x324c, 0x3bdc, 0x6996, 0xc33c, 0x9966, 0x0660,
+    0x0272, 0x04e4, 0x4e40, 0x2720, 0xc936, 0x936c, 0x39c6, 0x639c, 0x9336, 0x9cc6, 0x817e, 0xe718,
+    0xccf0, 0x0fcc, 0x7744, 0xee22,
+];
+
+/// Subset of every texel for the 3 subset BC7 partitions, two bits per texel.
+pub(super) const BC7_PARTITIONS_3: [u32; 64] = [
+    0xaa685050, 0x6a5a5040, 0x5a5a4200, 0x5450a0a8, 0xa5a50000, 0xa0a05050, 0x5555a0a0, 0x5a5a5050,
+    0xaa550000, 0xaa555500, 0xaaaa5500, 0x90909090, 0x94949494, 0xa4a4a4a4, 0xa9a59450, 0x2a0a4250,
+    0xa5945040, 0x0a425054, 0xa5a5a500, 0x55a0a0a0, 0xa8a85454, 0x6a6a4040, 0xa4a45000, 0x1a1a0500,
+    0x0050a4a4, 0xaaa59090, 0x14696914, 0x69691400, 0xa08585a0, 0xaa821414, 0x50a4a450, 0x6a5a0200,
+    0xa9a58000, 0x5090a0a8, 0xa8a09050, 0x24242424, 0x00aa5500, 0x24924924, 0x24499224, 0x50a50a50,
+    0x500aa550, 0xaaaa4444, 0x66660000, 0xa5a0a5a0, 0x50a050a0, 0x69286928, 0x44aaaa44, 0x66666600,
+    0xaa444444, 0x54a854a8, 0x95809580, 0x96969600, 0xa85454a8, 0x80959580, 0xaa141414, 0x96960000,
+    0xaaaa1414, 0xa05050a0, 0xa0a5a5a0, 0x96000000, 0x40804080, 0xa9a8a9a8, 0xaaaaaa44, 0x2a4a5254,
+];
+
+/// Texel whose index of the second subset drops its top bit.
+const BC7_ANCHORS_2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 2, 8, 2, 2, 8, 8, 15, 2, 8,
+    2, 2, 8, 8, 2, 2, 15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6, 6, 2, 6, 8, 15, 15, 2,
+    2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+/// Anchor texels of the second and third subset of the 3 subset partitions.
+#[rustfmt::skip]
+const BC7_ANCHORS_3: [[u8; 2]; 64] = [
+    [3, 15], [3, 8], [15, 8], [15, 3], [8, 15], [3, 15], [15, 3], [15, 8],
+    [8, 15], [8, 15], [6, 15], [6, 15], [6, 15], [5, 15], [3, 15], [3, 8],
+    [3, 15], [3, 8], [8, 15], [15, 3], [3, 15], [3, 8], [6, 15], [10, 8],
+    [5, 3], [8, 15], [8, 6], [6, 10], [8, 15], [5, 15], [15, 10], [15, 8],
+    [8, 15], [15, 3], [3, 15], [5, 10], [6, 10], [10, 8], [8, 9], [15, 10],
+    [15, 6], [3, 15], [15, 8], [5, 15], [15, 3], [15, 6], [15, 6], [15, 8],
+    [3, 15], [15, 3], [5, 15], [5, 15], [5, 15], [8, 15], [5, 15], [10, 15],
+    [5, 15], [10, 15], [8, 15], [13, 15], [15, 3], [12, 15], [3, 15], [3, 8],
+];
+
+const BC7_WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const BC7_WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const BC7_WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// Field widths of a BC7 mode.
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    /// One p-bit per endpoint, or shared by both endpoints of a subset.
+    endpoint_p_bits: bool,
+    shared_p_bits: bool,
+    index_bits: u32,
+    secondary_index_bits: u32,
+}
+
+const fn bc7_mode(fields: [u32; 10]) -> Bc7Mode {
+    let [subsets, pb, rb, isb, cb, ab, epb, spb, ib, ib2] = fields;
+    Bc7Mode {
+        subsets: subsets as usize,
+        partition_bits: pb,
+        rotation_bits: rb,
+        index_selection_bits: isb,
+        color_bits: cb,
+        alpha_bits: ab,
+        endpoint_p_bits: epb == 1,
+        shared_p_bits: spb == 1,
+        index_bits: ib,
+        secondary_index_bits: ib2,
+    }
+}
+
+const BC7_MODES: [Bc7Mode; 8] = [
+    bc7_mode([3, 4, 0, 0, 4, 0, 1, 0, 3, 0]),
+    bc7_mode([2, 6, 0, 0, 6, 0, 0, 1, 3, 0]),
+    bc7_mode([3, 6, 0, 0, 5, 0, 0, 0, 2, 0]),
+    bc7_mode([2, 6, 0, 0, 7, 0, 1, 0, 2, 0]),
+    bc7_mode([1, 0, 2, 1, 5, 6, 0, 0, 2, 3]),
+    bc7_mode([1, 0, 2, 0, 7, 8, 0, 0, 2, 2]),
+    bc7_mode([1, 0, 0, 0, 7, 7, 1, 0, 4, 0]),
+    bc7_mode([2, 6, 0, 0, 5, 5, 1, 0, 2, 0]),
+];
+
+fn bc7(block: &[u8]) -> [[u8; 4]; 16] {
+    let mut bits = Bits(u128::from_le_bytes(block[..16].try_into().unwrap()));
+    // The mode is the number of zero bits before the first set one
+    let Some(mode_index) = (0..8).find(|_| bits.take(1) == 1) else {
+        return [[0; 4]; 16];
+    };
+    let mode = &BC7_MODES[mode_index];
+
+    let partition = bits.take(mode.partition_bits) as usize;
+    let rotation = bits.take(mode.rotation_bits);
+    let index_selection = bits.take(mode.index_selection_bits);
+
+    let endpoint_count = 2 * mode.subsets;
+    let mut endpoints = [[0u8; 4]; 6];
+    for channel in 0..3 {
+        for endpoint in &mut endpoints[..endpoint_count] {
+            endpoint[channel] = bits.take(mode.color_bits);
+        }
+    }
+    for endpoint in &mut endpoints[..endpoint_count] {
+        endpoint[3] = bits.take(mode.alpha_bits);
+    }
+
+    let mut p_bits = [0u8; 6];
+    if mode.endpoint_p_bits {
+        for p_bit in &mut p_bits[..endpoint_count] {
+            *p_bit = bits.take(1);
+        }
+    } else if mode.shared_p_bits {
+        for subset in 0..mode.subsets {
+            let p_bit = bits.take(1);
+            p_bits[2 * subset..2 * subset + 2].fill(p_bit);
+        }
+    }
+
+    // Endpoints are expanded to 8 bits by replicating their top bits
+    let has_p_bit = mode.endpoint_p_bits || mode.shared_p_bits;
+    for (endpoint, p_bit) in endpoints[..endpoint_count].iter_mut().zip(p_bits) {
+        for (channel, value) in endpoint.iter_mut().enumerate() {
+            let mut width = if channel < 3 {
+                mode.color_bits
+            } else {
+                mode.alpha_bits
+            };
+            if width == 0 {
+                *value = 255;
+                continue;
+            }
+            if has_p_bit {
+                *value = (*value << 1) | p_bit;
+                width += 1;
+            }
+            let shifted = (*value as u32) << (8 - width);
+            *value = (shifted | shifted >> width) as u8;
+        }
+    }
+
+    let subset_of = |texel: usize| match mode.subsets {
+        2 => (BC7_PARTITIONS_2[partition] >> texel) as usize & 1,
+        3 => (BC7_PARTITIONS_3[partition] >> (2 * texel)) as usize & 3,
+        _ => 0,
+    };
+    let is_anchor = |texel: usize| match mode.subsets {
+        _ if texel == 0 => true,
+        2 => texel == BC7_ANCHORS_2[partition] as usize,
+        3 => BC7_ANCHORS_3[partition].contains(&(texel as u8)),
+        _ => false,
+    };
+
+    let mut indices = [0u8; 16];
+    for (texel, index) in indices.iter_mut().enumerate() {
+        *index = bits.take(mode.index_bits - is_anchor(texel) as u32);
+    }
+    let mut secondary_indices = [0u8; 16];
+    if mode.secondary_index_bits > 0 {
+        for (texel, index) in secondary_indices.iter_mut().enumerate() {
+            *index = bits.take(mode.secondary_index_bits - (texel == 0) as u32);
+        }
+    }
+
+    let weight = |bits: u32, index: u8| match bits {
+        2 => BC7_WEIGHTS_2[index as usize],
+        3 => BC7_WEIGHTS_3[index as usize],
+        _ => BC7_WEIGHTS_4[index as usize],
+    };
+    let interpolate = |e0: u8, e1: u8, weight: u32| {
+        (((64 - weight) * e0 as u32 + weight * e1 as u32 + 32) >> 6) as u8
+    };
+
+    std::array::from_fn(|texel| {
+        let subset = subset_of(texel);
+        let [e0, e1] = [endpoints[2 * subset], endpoints[2 * subset + 1]];
+        let (color_weight, alpha_weight) = if mode.secondary_index_bits == 0 {
+            let weight = weight(mode.index_bits, indices[texel]);
+            (weight, weight)
+        } else {
+            let primary = weight(mode.index_bits, indices[texel]);
+            let secondary = weight(mode.secondary_index_bits, secondary_indices[texel]);
+            if index_selection == 0 {
+                (primary, secondary)
+            } else {
+                (secondary, primary)
+            }
+        };
+
+        let mut rgba: [u8; 4] = std::array::from_fn(|channel| {
+            let weight = if channel < 3 {
+                color_weight
+            } else {
+                alpha_weight
+            };
+            interpolate(e0[channel], e1[channel], weight)
+        });
+        if rotation > 0 {
+            rgba.swap(rotation as usize - 1, 3);
+        }
+        rgba
+    })
+}
+
+pub(super) const ETC_MODIFIERS: [[i32; 2]; 8] = [
+    [2, 8],
+    [5, 17],
+    [9, 29],
+    [13, 42],
+    [18, 60],
+    [24, 80],
+    [33, 106],
+    [47, 183],
+];
+
+const ETC_DISTANCES: [i32; 8] = [3, 6, 11, 16, 23, 32, 41, 64];
+
+/// Texels of ETC blocks are stored column by column.
+fn etc_texel(i: usize) -> usize {
+    (i % 4) * 4 + i / 4
+}
+
+fn clamp_rgb(rgb: [i32; 3]) -> [u8; 4] {
+    let [r, g, b] = rgb.map(|channel| channel.clamp(0, 255) as u8);
+    [r, g, b, 255]
+}
+
+/// ETC2 RGB, which includes ETC1. With `punch_through` the differential bit
+/// instead marks blocks that may contain transparent texels.
+pub(super) fn etc2(block: &[u8], punch_through: bool) -> [[u8; 4]; 16] {
+    let hi = u32::from_be_bytes(block[..4].try_into().unwrap());
+    let lo = u32::from_be_bytes(block[4..8].try_into().unwrap());
+    let differential = punch_through || hi & 2 != 0;
+    let opaque = !punch_through || hi & 2 != 0;
+    let flip = hi & 1 != 0;
+
+    let four_bit = |shift: u32| (hi >> shift & 15) as i32 * 17;
+    let five_bit = |value: i32| (value << 3) | (value >> 2);
+    let selector = |texel: usize| ((lo >> (texel + 16) & 1) << 1 | (lo >> texel & 1)) as usize;
+    let transparent = |texel: usize| !opaque && selector(texel) == 2;
+
+    let base_colors = if differential {
+        let signed = |shift: u32| ((hi >> shift & 7) as i32 ^ 4) - 4;
+        let [r, g, b] = [27, 19, 11].map(|shift| (hi >> shift & 31) as i32);
+        let [dr, dg, db] = [24, 16, 8].map(signed);
+
+        if !(0..32).contains(&(r + dr)) {
+            return etc2_t_mode(hi, lo, opaque);
+        }
+        if !(0..32).contains(&(g + dg)) {
+            return etc2_h_mode(hi, lo, opaque);
+        }
+        if !(0..32).contains(&(b + db)) {
+            return etc2_planar(block);
+        }
+        [
+            [five_bit(r), five_bit(g), five_bit(b)],
+            [five_bit(r + dr), five_bit(g + dg), five_bit(b + db)],
+        ]
+    } else {
+        [
+            [four_bit(28), four_bit(20), four_bit(12)],
+            [four_bit(24), four_bit(16), four_bit(8)],
+        ]
+    };
+    let tables = [hi >> 5 & 7, hi >> 2 & 7];
+
+    let mut texels = [[0; 4]; 16];
+    for i in 0..16 {
+        let (x, y) = (i / 4, i % 4);
+        let half = if flip { y / 2 } else { x / 2 };
+        let [small, large] = ETC_MODIFIERS[tables[half] as usize];
+        let modifier = match selector(i) {
+            // Punch-through blocks without the opaque bit have no small positive modifier
+            0 if !opaque => 0,
+            0 => small,
+            1 => large,
+            2 => -small,
+            _ => -large,
+        };
+        texels[etc_texel(i)] = if transparent(i) {
+            [0; 4]
+        } else {
+            clamp_rgb(base_colors[half].map(|channel| channel + modifier))
+        };
+    }
+    texels
+}
+
+fn etc2_paint(lo: u32, opaque: bool, paint: [[i32; 3]; 4]) -> [[u8; 4]; 16] {
+    let mut texels = [[0; 4]; 16];
+    for i in 0..16 {
+        let selector = ((lo >> (i + 16) & 1) << 1 | (lo >> i & 1)) as usize;
+        texels[etc_texel(i)] = if !opaque && selector == 2 {
+            [0; 4]
+        } else {
+            clamp_rgb(paint[selector])
+        };
+    }
+    texels
+}
+
+fn etc2_t_mode(hi: u32, lo: u32, opaque: bool) -> [[u8; 4]; 16] {
+    let four_bit = |value: u32| (value & 15) as i32 * 17;
+    let c0 = [
+        four_bit((hi >> 27 & 3) << 2 | (hi >> 24 & 3)),
+        four_bit(hi >> 20),
+        four_bit(hi >> 16),
+    ];
+    let c1 = [four_bit(hi >> 12), four_bit(hi >> 8), four_bit(hi >> 4)];
+    let distance = ETC_DISTANCES[((hi >> 2 & 3) << 1 | (hi & 1)) as usize];
+
+    etc2_paint(
+        lo,
+        opaque,
+        [c0, c1.map(|c| c + distance), c1, c1.map(|c| c - distance)],
+    )
+}
+
+fn etc2_h_mode(hi: u32, lo: u32, opaque: bool) -> [[u8; 4]; 16] {
+    let four_bit = |value: u32| (value & 15) as i32 * 17;
+    let c0 = [
+        four_bit(hi >> 27),
+        four_bit((hi >> 24 & 7) << 1 | (hi >> 20 & 1)),
+        four_bit((hi >> 19 & 1) << 3 | (hi >> 15 & 7)),
+    ];
+    let c1 = [
+        four_bit(hi >> 11),
+        four_bit((hi >> 8 & 7) << 1 | (hi >> 7 & 1)),
+        four_bit(hi >> 3),
+    ];
+    // The order of the base colors stores the lowest bit of the distance index
+    let packed = |[r, g, b]: [i32; 3]| (r << 16) | (g << 8) | b;
+    let low_bit = (packed(c0) >= packed(c1)) as u32;
+    let distance = ETC_DISTANCES[((hi >> 2 & 1) << 2 | (hi & 1) << 1 | low_bit) as usize];
+
+    etc2_paint(
+        lo,
+        opaque,
+        [
+            c0.map(|c| c + distance),
+            c0.map(|c| c - distance),
+            c1.map(|c| c + distance),
+            c1.map(|c| c - distance),
+        ],
+    )
+}
+
+fn etc2_planar(block: &[u8]) -> [[u8; 4]; 16] {
+    let bits = u64::from_be_bytes(block[..8].try_into().unwrap());
+    let field = |shift: u32, count: u32| (bits >> shift & ((1 << count) - 1)) as i32;
+    let six_bit = |value: i32| (value << 2) | (value >> 4);
+    let seven_bit = |value: i32| (value << 1) | (value >> 6);
+
+    let origin = [
+        six_bit(field(57, 6)),
+        seven_bit(field(56, 1) << 6 | field(49, 6)),
+        six_bit(field(48, 1) << 5 | field(43, 2) << 3 | field(39, 3)),
+    ];
+    let horizontal = [
+        six_bit(field(34, 5) << 1 | field(32, 1)),
+        seven_bit(field(25, 7)),
+        six_bit(field(19, 6)),
+    ];
+    let vertical = [
+        six_bit(field(13, 6)),
+        seven_bit(field(6, 7)),
+        six_bit(field(0, 6)),
+    ];
+
+    std::array::from_fn(|texel| {
+        let (x, y) = ((texel % 4) as i32, (texel / 4) as i32);
+        clamp_rgb(std::array::from_fn(|c| {
+            (x * (horizontal[c] - origin[c]) + y * (vertical[c] - origin[c]) + 4 * origin[c] + 2)
+                >> 2
+        }))
+    })
+}
+
+const EAC_MODIFIERS: [[i32; 8]; 16] = [
+    [-3, -6, -9, -15, 2, 5, 8, 14],
+    [-3, -7, -10, -13, 2, 6, 9, 12],
+    [-2, -5, -8, -13, 1, 4, 7, 12],
+    [-2, -4, -6, -13, 1, 3, 5, 12],
+    [-3, -6, -8, -12, 2, 5, 7, 11],
+    [-3, -7, -9, -11, 2, 6, 8, 10],
+    [-4, -7, -8, -11, 3, 6, 7, 10],
+    [-3, -5, -8, -11, 2, 4, 7, 10],
+    [-2, -6, -8, -10, 1, 5, 7, 9],
+    [-2, -5, -8, -10, 1, 4, 7, 9],
+    [-2, -4, -8, -10, 1, 3, 7, 9],
+    [-2, -5, -7, -10, 1, 4, 6, 9],
+    [-3, -4, -7, -10, 2, 3, 6, 9],
+    [-1, -2, -3, -10, 0, 1, 2, 9],
+    [-4, -6, -8, -9, 3, 5, 7, 8],
+    [-3, -5, -7, -9, 2, 4, 6, 8],
+];
+
+/// An EAC block in row order. With `eleven_bit` the values are decoded at the
+/// precision of the R11 formats before being reduced to 8 bits.
+fn eac_channel(block: &[u8], eleven_bit: bool) -> [u8; 16] {
+    let bits = u64::from_be_bytes(block[..8].try_into().unwrap());
+    let base = (bits >> 56) as i32;
+    let multiplier = (bits >> 52 & 15) as i32;
+    let table = &EAC_MODIFIERS[(bits >> 48 & 15) as usize];
+
+    let mut values = [0; 16];
+    for i in 0..16 {
+        let modifier = table[(bits >> (45 - 3 * i) & 7) as usize];
+        values[etc_texel(i)] = if eleven_bit {
+            let scaled = if multiplier == 0 {
+                modifier
+            } else {
+                8 * multiplier * modifier
+            };
+            ((8 * base + 4 + scaled).clamp(0, 2047) >> 3) as u8
+        } else {
+            (base + multiplier * modifier).clamp(0, 255) as u8
+        };
+    }
+    values
+}
+
+/// The signed variant of [`eac_channel`] at 11 bit precision, the base is two's
+/// complement and the values lie in -1023..=1023.
+fn eac_snorm_channel(block: &[u8]) -> [f16; 16] {
+    let bits = u64::from_be_bytes(block[..8].try_into().unwrap());
+    let base = ((bits >> 56) as u8 as i8).max(-127) as i32;
+    let multiplier = (bits >> 52 & 15) as i32;
+    let table = &EAC_MODIFIERS[(bits >> 48 & 15) as usize];
+
+    let mut values = [f16::ZERO; 16];
+    for i in 0..16 {
+        let modifier = table[(bits >> (45 - 3 * i) & 7) as usize];
+        let scaled = if multiplier == 0 {
+            modifier
+        } else {
+            8 * multiplier * modifier
+        };
+        let value = (8 * base + scaled).clamp(-1023, 1023);
+        values[etc_texel(i)] = f16::from_f32(value as f32 / 1023.0);
+    }
+    values
+}
+
+fn etc2_rgba8(block: &[u8]) -> [[u8; 4]; 16] {
+    let alpha = eac_channel(&block[..8], false);
+    let mut texels = etc2(&block[8..], false);
+    for (texel, alpha) in texels.iter_mut().zip(alpha) {
+        texel[3] = alpha;
+    }
+    texels
+}
+
+fn eac_r11(block: &[u8]) -> [[u8; 4]; 16] {
+    eac_channel(block, true).map(|r| [r, 0, 0, 255])
+}
+
+fn eac_rg11(block: &[u8]) -> [[u8; 4]; 16] {
+    let r = eac_channel(&block[..8], true);
+    let g = eac_channel(&block[8..], true);
+    std::array::from_fn(|i| [r[i], g[i], 0, 255])
+}
+
+fn eac_r11_snorm(block: &[u8]) -> [[f16; 4]; 16] {
+    eac_snorm_channel(block).map(|r| [r, f16::ZERO, f16::ZERO, f16::ONE])
+}
+
+fn eac_rg11_snorm(block: &[u8]) -> [[f16; 4]; 16] {
+    let r = eac_snorm_channel(&block[..8]);
+    let g = eac_snorm_channel(&block[8..]);
+    std::array::from_fn(|i| [r[i], g[i], f16::ZERO, f16::ONE])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wgpu::util::DeviceExt;
+
+    /// Decodes `data` by sampling it on the fallback adapter, which has to exist
+    /// and support the format.
+    fn gpu_decode(
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        data: &[u8],
+    ) -> Vec<[f32; 4]> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            compatible_surface: None,
+            power_preference: wgpu::PowerPreference::default(),
+            force_fallback_adapter: true,
+        }))
+        .expect("no fallback adapter");
+        assert!(
+            adapter.features().contains(format.required_features()),
+            "the fallback adapter cannot sample {format:?}"
+        );
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                required_features: format.required_features(),
+                ..Default::default()
+            },
+            None,
+        ))
+        .unwrap();
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture_with_data(
+            &queue,
+            &wgpu::TextureDescriptor {
+                label: None,
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            data,
+        );
+        let target = device.create_texture(&wgpu::TextureDescriptor {
+            label: None,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: None,
+            source: wgpu::ShaderSource::Wgsl(
+                "@group(0) @binding(0) var source: texture_2d<f32>;
+                @vertex fn vs_main(@builtin(vertex_index) i: u32) -> @builtin(position) vec4<f32> {
+                    let uv = vec2<f32>(f32((i << 1u) & 2u), f32(i & 2u));
+                    return vec4<f32>(uv * 4.0 - 1.0, 0.0, 1.0);
+                }
+                @fragment fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+                    return textureLoad(source, vec2<i32>(position.xy), 0);
+                }"
+                .into(),
+            ),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: None,
+            layout: None,
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::TextureFormat::Rgba16Float.into())],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(
+                    &texture.create_view(&Default::default()),
+                ),
+            }],
+        });
+
+        let bytes_per_row = (8 * width).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (bytes_per_row * height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&Default::default());
+        {
+            let view = target.create_view(&Default::default());
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations::default(),
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+        encoder.copy_texture_to_buffer(
+            target.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            size,
+        );
+        queue.submit([encoder.finish()]);
+        buffer.slice(..).map_async(wgpu::MapMode::Read, |_| {});
+        device.poll(wgpu::Maintain::Wait);
+
+        let mapped = buffer.slice(..).get_mapped_range();
+        mapped
+            .chunks(bytes_per_row as usize)
+            .flat_map(|row| row[..8 * width as usize].chunks_exact(8))
+            .map(|texel| {
+                std::array::from_fn(|c| {
+                    f16::from_le_bytes([texel[2 * c], texel[2 * c + 1]]).to_f32()
+                })
+            })
+            .collect()
+    }
+
+    /// Deterministic block contents, xorshift.
+    fn random_bytes(count: usize, mut state: u64) -> Vec<u8> {
+        (0..count)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 24) as u8
+            })
+            .collect()
+    }
+
+    /// Decodes random blocks on the CPU and on the fallback adapter and checks
+    /// that no texel differs by more than `tolerance`.
+    fn assert_matches_gpu(format: wgpu::TextureFormat, tolerance: f32) {
+        let (block_width, block_height) = format.block_dimensions();
+        let (width, height) = (16 * block_width, 16 * block_height);
+        let block_size = format.block_copy_size(None).unwrap() as usize;
+        let data = random_bytes(256 * block_size, 0x9e37_79b9_7f4a_7c15);
+
+        let expected = gpu_decode(format, width, height, &data);
+        let (decoded_format, bytes) = decode(format, width, height, &data).unwrap();
+        let actual: Vec<[f32; 4]> = match decoded_format {
+            wgpu::TextureFormat::Rgba16Float => bytes
+                .chunks_exact(8)
+                .map(|texel| {
+                    std::array::from_fn(|c| {
+                        f16::from_le_bytes([texel[2 * c], texel[2 * c + 1]]).to_f32()
+                    })
+                })
+                .collect(),
+            _ => bytes
+                .chunks_exact(4)
+                .map(|texel| std::array::from_fn(|c| texel[c] as f32 / 255.0))
+                .collect(),
+        };
+
+        let mismatches: Vec<_> = (0..actual.len())
+            .filter(|&i| {
+                actual[i].iter().zip(expected[i]).any(|(a, e)| {
+                    let scale = if decoded_format == wgpu::TextureFormat::Rgba16Float {
+                        e.abs().max(1.0)
+                    } else {
+                        1.0
+                    };
+                    (a - e).abs() > tolerance * scale
+                })
+            })
+            .collect();
+        if let Some(&first) = mismatches.first() {
+            let (x, y) = (first as u32 % width, first as u32 / width);
+            let block = (y / block_height * 16 + x / block_width) as usize;
+            eprintln!(
+                "{format:?}: {} texels differ, first at {x},{y}: {:?} instead of {:?}, block {:02x?}",
+                mismatches.len(),
+                actual[first],
+                expected[first],
+                &data[block * block_size..(block + 1) * block_size]
+            );
+        }
+        assert!(mismatches.is_empty(), "{format:?}");
+    }
+
+    // The software rasterizer interpolates the BC3 alpha and signed BC4 palettes
+    // with truncating 8 bit weights, a little off the exact values decoded here
+    #[test]
+    #[ignore = "needs a fallback adapter that samples the compressed formats"]
+    fn bc_decoders_match_the_gpu() {
+        use wgpu::TextureFormat as F;
+        for format in [
+            F::Bc1RgbaUnorm,
+            F::Bc2RgbaUnorm,
+            F::Bc3RgbaUnorm,
+            F::Bc4RUnorm,
+            F::Bc5RgUnorm,
+            F::Bc7RgbaUnorm,
+        ] {
+            assert_matches_gpu(format, 2.5 / 255.0);
+        }
+        for format in [F::Bc4RSnorm, F::Bc5RgSnorm] {
+            assert_matches_gpu(format, 2.0 / 127.0);
+        }
+        for format in [F::Bc6hRgbUfloat, F::Bc6hRgbFloat] {
+            assert_matches_gpu(format, 1.0 / 512.0);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a fallback adapter that samples the compressed formats"]
+    fn etc2_decoders_match_the_gpu() {
+        use wgpu::TextureFormat as F;
+        for format in [
+            F::Etc2Rgb8Unorm,
+            F::Etc2Rgb8A1Unorm,
+            F::Etc2Rgba8Unorm,
+            F::EacR11Unorm,
+            F::EacRg11Unorm,
+        ] {
+            assert_matches_gpu(format, 2.5 / 255.0);
+        }
+        for format in [F::EacR11Snorm, F::EacRg11Snorm] {
+            assert_matches_gpu(format, 1.0 / 1023.0);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a fallback adapter that samples the compressed formats"]
+    fn astc_decoder_matches_the_gpu() {
+        use wgpu::AstcBlock as B;
+        for block in [
+            B::B4x4,
+            B::B5x4,
+            B::B5x5,
+            B::B6x5,
+            B::B6x6,
+            B::B8x5,
+            B::B8x6,
+            B::B8x8,
+            B::B10x5,
+            B::B10x6,
+            B::B10x8,
+            B::B10x10,
+            B::B12x10,
+            B::B12x12,
+        ] {
+            let format = wgpu::TextureFormat::Astc {
+                block,
+                channel: wgpu::AstcChannel::Unorm,
+            };
+            assert_matches_gpu(format, 2.5 / 255.0);
+        }
+    }
+}
@@ -0,0 +1,590 @@
+//! ASTC in the LDR profile, for every 2D block size.
+//!
+//! Invalid blocks, and the texels of partitions that use one of the HDR
+//! endpoint modes the LDR profile lacks, decode to the error color like
+//! hardware decoders do.
+
+/// Magenta, what the LDR profile returns for blocks it cannot decode.
+pub(in crate::texture) const ERROR_COLOR: [u8; 4] = [255, 0, 255, 255];
+
+/// Bits and trit or quint multiplier of the integer sequence ranges, in the
+/// order of their number of levels 2, 3, 4, 5, 6, 8, 10, ... 256.
+pub(in crate::texture) const ISE_RANGES: [(u32, u32); 21] = [
+    (1, 1),
+    (0, 3),
+    (2, 1),
+    (0, 5),
+    (1, 3),
+    (3, 1),
+    (1, 5),
+    (2, 3),
+    (4, 1),
+    (2, 5),
+    (3, 3),
+    (5, 1),
+    (3, 5),
+    (4, 3),
+    (6, 1),
+    (4, 5),
+    (5, 3),
+    (7, 1),
+    (5, 5),
+    (6, 3),
+    (8, 1),
+];
+
+/// Decodes one block into `texels`, which holds `block_width` by
+/// `block_height` texels row by row. `srgb` selects the sRGB interpolation,
+/// the result is then the sRGB encoded value.
+pub(super) fn decode_block(
+    block: &[u8],
+    block_width: u32,
+    block_height: u32,
+    srgb: bool,
+    texels: &mut [[u8; 4]],
+) {
+    let bits = u128::from_le_bytes(block[..16].try_into().unwrap());
+    if decode(bits, block_width, block_height, srgb, texels).is_none() {
+        texels.fill(ERROR_COLOR);
+    }
+}
+
+/// `count` bits starting at `offset`, at most 32.
+fn field(bits: u128, offset: u32, count: u32) -> u32 {
+    if count == 0 || offset >= 128 {
+        return 0;
+    }
+    (bits >> offset) as u32 & (u32::MAX >> (32 - count))
+}
+
+/// Size of the grid of weights and how they are quantized, from the block mode.
+struct WeightGrid {
+    width: u32,
+    height: u32,
+    dual_plane: bool,
+    /// Index into [`ISE_RANGES`].
+    range: usize,
+}
+
+fn weight_grid(mode: u32) -> Option<WeightGrid> {
+    let a = mode >> 5 & 3;
+    let mut high_precision = mode >> 9 & 1;
+    let mut dual_plane = mode >> 10 & 1;
+
+    let (range, width, height);
+    if mode & 3 != 0 {
+        range = (mode >> 4 & 1) | (mode & 3) << 1;
+        let b = mode >> 7 & 3;
+        (width, height) = match mode >> 2 & 3 {
+            0 => (b + 4, a + 2),
+            1 => (b + 8, a + 2),
+            2 => (a + 2, b + 8),
+            _ if mode & 0x100 != 0 => ((b & 1) + 2, a + 2),
+            _ => (a + 2, (b & 1) + 6),
+        };
+    } else {
+        if mode >> 2 & 3 == 0 {
+            return None;
+        }
+        range = (mode >> 4 & 1) | (mode >> 2 & 3) << 1;
+        let b = mode >> 9 & 3;
+        (width, height) = match mode >> 7 & 3 {
+            0 => (12, a + 2),
+            1 => (a + 2, 12),
+            2 => {
+                // Both bits are taken by the size
+                high_precision = 0;
+                dual_plane = 0;
+                (a + 6, b + 6)
+            }
+            _ => match a {
+                0 => (6, 10),
+                1 => (10, 6),
+                _ => return None,
+            },
+        };
+    }
+
+    Some(WeightGrid {
+        width,
+        height,
+        dual_plane: dual_plane == 1,
+        range: (range - 2 + 6 * high_precision) as usize,
+    })
+}
+
+/// Bits taken by `count` values of an integer sequence in `range`.
+pub(in crate::texture) fn sequence_bits(range: usize, count: u32) -> u32 {
+    let (bits, multiplier) = ISE_RANGES[range];
+    bits * count
+        + match multiplier {
+            3 => (8 * count).div_ceil(5),
+            5 => (7 * count).div_ceil(3),
+            _ => 0,
+        }
+}
+
+/// Reads `count` values of an integer sequence in `range` that starts at
+/// `offset`. Each value is split into its trit or quint and its low bits.
+fn read_sequence(bits: u128, offset: u32, range: usize, count: u32) -> Vec<(u32, u32)> {
+    let (bit_count, multiplier) = ISE_RANGES[range];
+    // Bits past the end of a truncated sequence read as zero
+    let end = offset + sequence_bits(range, count);
+    let mut cursor = offset;
+    let mut read = |count: u32| {
+        let available = end.saturating_sub(cursor).min(count);
+        cursor += count;
+        field(bits, cursor - count, available)
+    };
+
+    let mut values = Vec::with_capacity(count as usize + 4);
+    while (values.len() as u32) < count {
+        match multiplier {
+            3 => {
+                // Five values share 8 bits that are spread between their low bits
+                let mut low = [0; 5];
+                let mut packed = 0;
+                for (i, (shift, width)) in [(0, 2), (2, 2), (4, 1), (5, 2), (7, 1)]
+                    .into_iter()
+                    .enumerate()
+                {
+                    low[i] = read(bit_count);
+                    packed |= read(width) << shift;
+                }
+                values.extend(trits(packed).into_iter().zip(low));
+            }
+            5 => {
+                // Three values share 7 bits
+                let mut low = [0; 3];
+                let mut packed = 0;
+                for (i, (shift, width)) in [(0, 3), (3, 2), (5, 2)].into_iter().enumerate() {
+                    low[i] = read(bit_count);
+                    packed |= read(width) << shift;
+                }
+                values.extend(quints(packed).into_iter().zip(low));
+            }
+            _ => values.push((0, read(bit_count))),
+        }
+    }
+    values.truncate(count as usize);
+    values
+}
+
+/// Unpacks five trits from 8 bits.
+fn trits(t: u32) -> [u32; 5] {
+    let bit = |value: u32, index: u32| value >> index & 1;
+    let (c, t4, t3) = if t >> 2 & 7 == 7 {
+        ((t >> 5 & 7) << 2 | (t & 3), 2, 2)
+    } else if t >> 5 & 3 == 3 {
+        (t & 31, 2, bit(t, 7))
+    } else {
+        (t & 31, bit(t, 7), t >> 5 & 3)
+    };
+    let (t2, t1, t0) = if c & 3 == 3 {
+        (2, bit(c, 4), bit(c, 3) << 1 | (bit(c, 2) & !bit(c, 3) & 1))
+    } else if c >> 2 & 3 == 3 {
+        (2, 2, c & 3)
+    } else {
+        (
+            bit(c, 4),
+            c >> 2 & 3,
+            bit(c, 1) << 1 | (bit(c, 0) & !bit(c, 1) & 1),
+        )
+    };
+    [t0, t1, t2, t3, t4]
+}
+
+/// Unpacks three quints from 7 bits.
+fn quints(q: u32) -> [u32; 3] {
+    let bit = |value: u32, index: u32| value >> index & 1;
+    if q >> 1 & 3 == 3 && q >> 5 & 3 == 0 {
+        let q2 = bit(q, 0) << 2 | (bit(q, 4) & !bit(q, 0) & 1) << 1 | (bit(q, 3) & !bit(q, 0) & 1);
+        return [4, 4, q2];
+    }
+    let (q2, c) = if q >> 1 & 3 == 3 {
+        (4, (q >> 3 & 3) << 3 | (!(q >> 5) & 3) << 1 | bit(q, 0))
+    } else {
+        (q >> 5 & 3, q & 31)
+    };
+    let (q1, q0) = if c & 7 == 5 {
+        (4, c >> 3 & 3)
+    } else {
+        (c >> 3 & 3, c & 7)
+    };
+    [q0, q1, q2]
+}
+
+/// Expands a color endpoint value to 8 bits.
+pub(in crate::texture) fn unquantize_color(range: usize, (d, m): (u32, u32)) -> u8 {
+    let (bits, multiplier) = ISE_RANGES[range];
+    if multiplier == 1 {
+        // Replicate the bits until all 8 are filled
+        let mut value = m << (8 - bits);
+        let mut filled = bits;
+        while filled < 8 {
+            value |= value >> filled;
+            filled *= 2;
+        }
+        return value as u8;
+    }
+
+    let a = if m & 1 == 1 { 0x1ff } else { 0 };
+    let (b, c) = match (multiplier, bits) {
+        (3, 1) => (0, 204),
+        (3, 2) => ((m >> 1 & 1) * 0x116, 93),
+        (3, 3) => {
+            let cb = m >> 1 & 3;
+            (cb << 7 | cb << 2 | cb, 44)
+        }
+        (3, 4) => {
+            let dcb = m >> 1 & 7;
+            (dcb << 6 | dcb, 22)
+        }
+        (3, 5) => {
+            let edcb = m >> 1 & 15;
+            (edcb << 5 | edcb >> 2, 11)
+        }
+        (3, _) => {
+            let fedcb = m >> 1 & 31;
+            (fedcb << 4 | fedcb >> 4, 5)
+        }
+        (_, 1) => (0, 113),
+        (_, 2) => ((m >> 1 & 1) * 0x10c, 54),
+        (_, 3) => {
+            let cb = m >> 1 & 3;
+            (cb << 7 | cb << 1 | cb >> 1, 26)
+        }
+        (_, 4) => {
+            let dcb = m >> 1 & 7;
+            (dcb << 6 | dcb >> 1, 13)
+        }
+        (_, _) => {
+            let edcb = m >> 1 & 15;
+            (edcb << 5 | edcb >> 3, 6)
+        }
+    };
+    let t = (d * c + b) ^ a;
+    ((a & 0x80) | (t >> 2)) as u8
+}
+
+/// Expands a weight to 0..=64.
+pub(in crate::texture) fn unquantize_weight(range: usize, (d, m): (u32, u32)) -> u32 {
+    let (bits, multiplier) = ISE_RANGES[range];
+    let weight = match (multiplier, bits) {
+        (1, 1) => m * 63,
+        (1, 2) => m * 21,
+        (1, 3) => m << 3 | m,
+        (1, 4) => m << 2 | m >> 2,
+        (1, _) => m << 1 | m >> 4,
+        (3, 0) => [0, 32, 63][d as usize],
+        (5, 0) => [0, 16, 32, 47, 63][d as usize],
+        (multiplier, bits) => {
+            let a = if m & 1 == 1 { 0x7f } else { 0 };
+            let (b, c) = match (multiplier, bits) {
+                (3, 1) => (0, 50),
+                (3, 2) => ((m >> 1 & 1) * 0x45, 23),
+                (3, _) => {
+                    let cb = m >> 1 & 3;
+                    (cb << 5 | cb, 11)
+                }
+                (_, 1) => (0, 28),
+                (_, _) => ((m >> 1 & 1) * 0x42, 13),
+            };
+            let t = (d * c + b) ^ a;
+            (a & 0x20) | (t >> 2)
+        }
+    };
+    if weight > 32 { weight + 1 } else { weight }
+}
+
+/// The partition of a texel, from the hash of the partition seed in the block.
+pub(in crate::texture) fn select_partition(
+    seed: u32,
+    x: u32,
+    y: u32,
+    partition_count: u32,
+    small_block: bool,
+) -> u32 {
+    let (x, y) = if small_block {
+        (x << 1, y << 1)
+    } else {
+        (x, y)
+    };
+    let seed = seed + (partition_count - 1) * 1024;
+
+    let mut rnum = seed;
+    rnum ^= rnum >> 15;
+    rnum = rnum.wrapping_sub(rnum << 17);
+    rnum = rnum.wrapping_add(rnum << 7);
+    rnum = rnum.wrapping_add(rnum << 4);
+    rnum ^= rnum >> 5;
+    rnum = rnum.wrapping_add(rnum << 16);
+    rnum ^= rnum >> 7;
+    rnum ^= rnum >> 3;
+    rnum ^= rnum << 6;
+    rnum ^= rnum >> 17;
+
+    let nibble = |shift: u32| {
+        let value = rnum.rotate_right(shift) & 15;
+        value * value
+    };
+    let seeds = [0, 4, 8, 12, 16, 20, 24, 28, 18, 22].map(nibble);
+
+    let (sh1, sh2) = if seed & 1 == 1 {
+        (
+            if seed & 2 != 0 { 4 } else { 5 },
+            if partition_count == 3 { 6 } else { 5 },
+        )
+    } else {
+        (
+            if partition_count == 3 { 6 } else { 5 },
+            if seed & 2 != 0 { 4 } else { 5 },
+        )
+    };
+    let shifted = |i: usize| seeds[i] >> if i.is_multiple_of(2) { sh1 } else { sh2 };
+
+    let a = (shifted(0) * x + shifted(1) * y + (rnum >> 14)) & 0x3f;
+    let b = (shifted(2) * x + shifted(3) * y + (rnum >> 10)) & 0x3f;
+    let mut c = (shifted(4) * x + shifted(5) * y + (rnum >> 6)) & 0x3f;
+    let mut d = (shifted(6) * x + shifted(7) * y + (rnum >> 2)) & 0x3f;
+    if partition_count < 4 {
+        d = 0;
+    }
+    if partition_count < 3 {
+        c = 0;
+    }
+
+    if a >= b && a >= c && a >= d {
+        0
+    } else if b >= c && b >= d {
+        1
+    } else if c >= d {
+        2
+    } else {
+        3
+    }
+}
+
+/// The two endpoints of an LDR color endpoint mode, `None` for HDR modes.
+pub(in crate::texture) fn endpoints(mode: u32, v: &[i32]) -> Option<[[u8; 4]; 2]> {
+    // Moves the top bit of the offset `a` into the base `b` and sign extends the rest
+    let bit_transfer_signed = |a: i32, b: i32| {
+        let b = (b >> 1) | (a & 0x80);
+        let a = (a >> 1) & 0x3f;
+        (if a & 0x20 != 0 { a - 0x40 } else { a }, b)
+    };
+    let blue_contract = |[r, g, b, a]: [i32; 4]| [(r + b) >> 1, (g + b) >> 1, b, a];
+
+    let pair = match mode {
+        0 => [[v[0], v[0], v[0], 255], [v[1], v[1], v[1], 255]],
+        1 => {
+            let l0 = (v[0] >> 2) | (v[1] & 0xc0);
+            let l1 = (l0 + (v[1] & 0x3f)).min(255);
+            [[l0, l0, l0, 255], [l1, l1, l1, 255]]
+        }
+        4 => [[v[0], v[0], v[0], v[2]], [v[1], v[1], v[1], v[3]]],
+        5 => {
+            let (l1, l0) = bit_transfer_signed(v[1], v[0]);
+            let (a1, a0) = bit_transfer_signed(v[3], v[2]);
+            let l1 = l0 + l1;
+            [[l0, l0, l0, a0], [l1, l1, l1, a0 + a1]]
+        }
+        6 | 10 => {
+            let scale = |c: i32| (c * v[3]) >> 8;
+            let [a0, a1] = if mode == 10 { [v[4], v[5]] } else { [255, 255] };
+            [
+                [scale(v[0]), scale(v[1]), scale(v[2]), a0],
+                [v[0], v[1], v[2], a1],
+            ]
+        }
+        8 | 12 => {
+            let [a0, a1] = if mode == 12 { [v[6], v[7]] } else { [255, 255] };
+            let e0 = [v[0], v[2], v[4], a0];
+            let e1 = [v[1], v[3], v[5], a1];
+            if v[1] + v[3] + v[5] >= v[0] + v[2] + v[4] {
+                [e0, e1]
+            } else {
+                [blue_contract(e1), blue_contract(e0)]
+            }
+        }
+        9 | 13 => {
+            let (r1, r0) = bit_transfer_signed(v[1], v[0]);
+            let (g1, g0) = bit_transfer_signed(v[3], v[2]);
+            let (b1, b0) = bit_transfer_signed(v[5], v[4]);
+            let (a1, a0) = if mode == 13 {
+                bit_transfer_signed(v[7], v[6])
+            } else {
+                (0, 255)
+            };
+            let base = [r0, g0, b0, a0];
+            let offset = [r0 + r1, g0 + g1, b0 + b1, a0 + a1];
+            if r1 + g1 + b1 >= 0 {
+                [base, offset]
+            } else {
+                [blue_contract(offset), blue_contract(base)]
+            }
+        }
+        _ => return None,
+    };
+
+    Some(pair.map(|endpoint| endpoint.map(|c| c.clamp(0, 255) as u8)))
+}
+
+/// Interpolates one channel between two 8 bit endpoints at a weight in 0..=64.
+pub(in crate::texture) fn interpolate(e0: u8, e1: u8, weight: u32, srgb: bool) -> u8 {
+    let expand = |e: u8| {
+        if srgb {
+            (e as u32) << 8 | 0x80
+        } else {
+            e as u32 * 257
+        }
+    };
+    let value = (expand(e0) * (64 - weight) + expand(e1) * weight + 32) >> 6;
+    (value >> 8) as u8
+}
+
+fn decode(
+    bits: u128,
+    block_width: u32,
+    block_height: u32,
+    srgb: bool,
+    texels: &mut [[u8; 4]],
+) -> Option<()> {
+    let block_mode = field(bits, 0, 11);
+    if block_mode & 0x1ff == 0x1fc {
+        // A single color for the whole block, the HDR bit is invalid in the LDR profile
+        if block_mode & 0x200 != 0 {
+            return None;
+        }
+        let color = [64, 80, 96, 112].map(|offset| (field(bits, offset, 16) >> 8) as u8);
+        texels.fill(color);
+        return Some(());
+    }
+
+    let grid = weight_grid(block_mode)?;
+    let partition_count = field(bits, 11, 2) + 1;
+    if grid.width > block_width
+        || grid.height > block_height
+        || (grid.dual_plane && partition_count == 4)
+    {
+        return None;
+    }
+
+    let planes = 1 + grid.dual_plane as u32;
+    let weight_count = grid.width * grid.height * planes;
+    let weight_bits = sequence_bits(grid.range, weight_count);
+    if weight_count > 64 || !(24..=96).contains(&weight_bits) {
+        return None;
+    }
+
+    // Everything that does not fit the fixed fields is stored below the weights
+    let mut below_weights = 128 - weight_bits;
+    let mut modes = [field(bits, 13, 4); 4];
+    let mut seed = 0;
+    let mut color_offset = 17;
+    if partition_count > 1 {
+        seed = field(bits, 13, 10);
+        color_offset = 29;
+        let selector = field(bits, 23, 6);
+        modes = [selector >> 2; 4];
+        if selector & 3 != 0 {
+            // Each partition picks its mode class relative to a base class
+            let extra = 3 * partition_count - 4;
+            below_weights -= extra;
+            let mode_bits = selector >> 2 | field(bits, below_weights, extra) << 4;
+            let base_class = (selector & 3) - 1;
+            for (i, mode) in modes.iter_mut().take(partition_count as usize).enumerate() {
+                let class = base_class + (mode_bits >> i & 1);
+                *mode = class << 2 | (mode_bits >> (partition_count as usize + 2 * i) & 3);
+            }
+        }
+    }
+    let modes = &modes[..partition_count as usize];
+
+    let mut plane2_channel = None;
+    if grid.dual_plane {
+        below_weights -= 2;
+        plane2_channel = Some(field(bits, below_weights, 2) as usize);
+    }
+
+    let value_count: u32 = modes.iter().map(|mode| 2 * (mode / 4 + 1)).sum();
+    if value_count > 18 || below_weights < color_offset {
+        return None;
+    }
+    // The endpoints use the finest range that fits, at least 6 levels
+    let color_range = (4..ISE_RANGES.len())
+        .rev()
+        .find(|&range| sequence_bits(range, value_count) <= below_weights - color_offset)?;
+    let values: Vec<i32> = read_sequence(bits, color_offset, color_range, value_count)
+        .into_iter()
+        .map(|value| unquantize_color(color_range, value) as i32)
+        .collect();
+
+    // Texels of a partition with an HDR mode are errors, the others still decode
+    let mut endpoint_pairs = [None; 4];
+    let mut values = values.as_slice();
+    for (pair, &mode) in endpoint_pairs.iter_mut().zip(modes) {
+        let count = 2 * (mode / 4 + 1) as usize;
+        *pair = endpoints(mode, &values[..count]);
+        values = &values[count..];
+    }
+
+    // The weights are stored from the top of the block downwards
+    let weights: Vec<u32> = read_sequence(bits.reverse_bits(), 0, grid.range, weight_count)
+        .into_iter()
+        .map(|value| unquantize_weight(grid.range, value))
+        .collect();
+
+    let small_block = block_width * block_height < 31;
+    let scale = |size: u32| (1024 + size / 2) / (size - 1);
+    let (scale_s, scale_t) = (scale(block_width), scale(block_height));
+    for t in 0..block_height {
+        for s in 0..block_width {
+            // Bilinear infill of the weight grid
+            let gs = (scale_s * s * (grid.width - 1) + 32) >> 6;
+            let gt = (scale_t * t * (grid.height - 1) + 32) >> 6;
+            let (js, fs) = (gs >> 4, gs & 15);
+            let (jt, ft) = (gt >> 4, gt & 15);
+            let w11 = (fs * ft + 8) >> 4;
+            let corners = [
+                (0, 0, 16 + w11 - fs - ft),
+                (1, 0, fs - w11),
+                (0, 1, ft - w11),
+                (1, 1, w11),
+            ];
+            let weight = |plane: u32| {
+                let sum: u32 = corners
+                    .iter()
+                    .filter(|&&(_, _, factor)| factor > 0)
+                    .map(|&(ds, dt, factor)| {
+                        let index = (js + ds) + (jt + dt) * grid.width;
+                        weights[(index * planes + plane) as usize] * factor
+                    })
+                    .sum();
+                (sum + 8) >> 4
+            };
+            let (weight1, weight2) = (weight(0), if planes == 2 { weight(1) } else { 0 });
+
+            let partition = if partition_count > 1 {
+                select_partition(seed, s, t, partition_count, small_block)
+            } else {
+                0
+            };
+            let texel = &mut texels[(t * block_width + s) as usize];
+            let Some([e0, e1]) = endpoint_pairs[partition as usize] else {
+                *texel = ERROR_COLOR;
+                continue;
+            };
+            *texel = std::array::from_fn(|channel| {
+                let weight = if plane2_channel == Some(channel) {
+                    weight2
+                } else {
+                    weight1
+                };
+                interpolate(e0[channel], e1[channel], weight, srgb)
+            });
+        }
+    }
+
+    Some(())
+}
@@ -0,0 +1,237 @@
+//! BC6H, half float RGB in 14 modes that differ in endpoint precision and
+//! in how the endpoint bits are scattered over the block.
+
+use half::f16;
+
+use super::{BC7_ANCHORS_2, BC7_PARTITIONS_2, Bits};
+
+/// Which value a run of block bits belongs to.
+#[derive(Clone, Copy)]
+enum Field {
+    /// Bits of endpoint `w`, `x`, `y` or `z` in channel 0, 1 or 2.
+    Endpoint(usize, usize),
+    Partition,
+}
+
+const RW: Field = Field::Endpoint(0, 0);
+const GW: Field = Field::Endpoint(0, 1);
+const BW: Field = Field::Endpoint(0, 2);
+const RX: Field = Field::Endpoint(1, 0);
+const GX: Field = Field::Endpoint(1, 1);
+const BX: Field = Field::Endpoint(1, 2);
+const RY: Field = Field::Endpoint(2, 0);
+const GY: Field = Field::Endpoint(2, 1);
+const BY: Field = Field::Endpoint(2, 2);
+const RZ: Field = Field::Endpoint(3, 0);
+const GZ: Field = Field::Endpoint(3, 1);
+const BZ: Field = Field::Endpoint(3, 2);
+const D: Field = Field::Partition;
+
+/// A BC6H mode, the fields after the mode bits are listed in block order as
+/// `(field, a, b)` for the bits `field[a:b]`, stored starting with bit `b`.
+struct Mode {
+    /// Precision of the base endpoint `w`.
+    endpoint_bits: u32,
+    /// Precision of the other endpoints, which are deltas from `w` when transformed.
+    delta_bits: [u32; 3],
+    transformed: bool,
+    two_regions: bool,
+    layout: &'static [(Field, u32, u32)],
+}
+
+#[rustfmt::skip]
+const MODES: [Mode; 14] = [
+    Mode { endpoint_bits: 10, delta_bits: [5, 5, 5], transformed: true, two_regions: true, layout: &[
+        (GY, 4, 4), (BY, 4, 4), (BZ, 4, 4), (RW, 9, 0), (GW, 9, 0), (BW, 9, 0), (RX, 4, 0),
+        (GZ, 4, 4), (GY, 3, 0), (GX, 4, 0), (BZ, 0, 0), (GZ, 3, 0), (BX, 4, 0), (BZ, 1, 1),
+        (BY, 3, 0), (RY, 4, 0), (BZ, 2, 2), (RZ, 4, 0), (BZ, 3, 3), (D, 4, 0),
+    ] },
+    Mode { endpoint_bits: 7, delta_bits: [6, 6, 6], transformed: true, two_regions: true, layout: &[
+        (GY, 5, 5), (GZ, 4, 4), (GZ, 5, 5), (RW, 6, 0), (BZ, 0, 0), (BZ, 1, 1), (BY, 4, 4),
+        (GW, 6, 0), (BY, 5, 5), (BZ, 2, 2), (GY, 4, 4), (BW, 6, 0), (BZ, 3, 3), (BZ, 5, 5),
+        (BZ, 4, 4), (RX, 5, 0), (GY, 3, 0), (GX, 5, 0), (GZ, 3, 0), (BX, 5, 0), (BY, 3, 0),
+        (RY, 5, 0), (RZ, 5, 0), (D, 4, 0),
+    ] },
+    Mode { endpoint_bits: 11, delta_bits: [5, 4, 4], transformed: true, two_regions: true, layout: &[
+        (RW, 9, 0), (GW, 9, 0), (BW, 9, 0), (RX, 4, 0), (RW, 10, 10), (GY, 3, 0), (GX, 3, 0),
+        (GW, 10, 10), (BZ, 0, 0), (GZ, 3, 0), (BX, 3, 0), (BW, 10, 10), (BZ, 1, 1), (BY, 3, 0),
+        (RY, 4, 0), (BZ, 2, 2), (RZ, 4, 0), (BZ, 3, 3), (D, 4, 0),
+    ] },
+    Mode { endpoint_bits: 11, delta_bits: [4, 5, 4], transformed: true, two_regions: true, layout: &[
+        (RW, 9, 0), (GW, 9, 0), (BW, 9, 0), (RX, 3, 0), (RW, 10, 10), (GZ, 4, 4), (GY, 3, 0),
+        (GX, 4, 0), (GW, 10, 10), (GZ, 3, 0), (BX, 3, 0), (BW, 10, 10), (BZ, 1, 1), (BY, 3, 0),
+        (RY, 3, 0), (BZ, 0, 0), (BZ, 2, 2), (RZ, 3, 0), (GY, 4, 4), (BZ, 3, 3), (D, 4, 0),
+    ] },
+    Mode { endpoint_bits: 11, delta_bits: [4, 4, 5], transformed: true, two_regions: true, layout: &[
+        (RW, 9, 0), (GW, 9, 0), (BW, 9, 0), (RX, 3, 0), (RW, 10, 10), (BY, 4, 4), (GY, 3, 0),
+        (GX, 3, 0), (GW, 10, 10), (BZ, 0, 0), (GZ, 3, 0), (BX, 4, 0), (BW, 10, 10), (BY, 3, 0),
+        (RY, 3, 0), (BZ, 1, 1), (BZ, 2, 2), (RZ, 3, 0), (BZ, 4, 4), (BZ, 3, 3), (D, 4, 0),
+    ] },
+    Mode { endpoint_bits: 9, delta_bits: [5, 5, 5], transformed: true, two_regions: true, layout: &[
+        (RW, 8, 0), (BY, 4, 4), (GW, 8, 0), (GY, 4, 4), (BW, 8, 0), (BZ, 4, 4), (RX, 4, 0),
+        (GZ, 4, 4), (GY, 3, 0), (GX, 4, 0), (BZ, 0, 0), (GZ, 3, 0), (BX, 4, 0), (BZ, 1, 1),
+        (BY, 3, 0), (RY, 4, 0), (BZ, 2, 2), (RZ, 4, 0), (BZ, 3, 3), (D, 4, 0),
+    ] },
+    Mode { endpoint_bits: 8, delta_bits: [6, 5, 5], transformed: true, two_regions: true, layout: &[
+        (RW, 7, 0), (GZ, 4, 4), (BY, 4, 4), (GW, 7, 0), (BZ, 2, 2), (GY, 4, 4), (BW, 7, 0),
+        (BZ, 3, 3), (BZ, 4, 4), (RX, 5, 0), (GY, 3, 0), (GX, 4, 0), (BZ, 0, 0), (GZ, 3, 0),
+        (BX, 4, 0), (BZ, 1, 1), (BY, 3, 0), (RY, 5, 0), (RZ, 5, 0), (D, 4, 0),
+    ] },
+    Mode { endpoint_bits: 8, delta_bits: [5, 6, 5], transformed: true, two_regions: true, layout: &[
+        (RW, 7, 0), (BZ, 0, 0), (BY, 4, 4), (GW, 7, 0), (GY, 5, 5), (GY, 4, 4), (BW, 7, 0),
+        (GZ, 5, 5), (BZ, 4, 4), (RX, 4, 0), (GZ, 4, 4), (GY, 3, 0), (GX, 5, 0), (GZ, 3, 0),
+        (BX, 4, 0), (BZ, 1, 1), (BY, 3, 0), (RY, 4, 0), (BZ, 2, 2), (RZ, 4, 0), (BZ, 3, 3),
+        (D, 4, 0),
+    ] },
+    Mode { endpoint_bits: 8, delta_bits: [5, 5, 6], transformed: true, two_regions: true, layout: &[
+        (RW, 7, 0), (BZ, 1, 1), (BY, 4, 4), (GW, 7, 0), (BY, 5, 5), (GY, 4, 4), (BW, 7, 0),
+        (BZ, 5, 5), (BZ, 4, 4), (RX, 4, 0), (GZ, 4, 4), (GY, 3, 0), (GX, 4, 0), (BZ, 0, 0),
+        (GZ, 3, 0), (BX, 5, 0), (BY, 3, 0), (RY, 4, 0), (BZ, 2, 2), (RZ, 4, 0), (BZ, 3, 3),
+        (D, 4, 0),
+    ] },
+    Mode { endpoint_bits: 6, delta_bits: [6, 6, 6], transformed: false, two_regions: true, layout: &[
+        (RW, 5, 0), (GZ, 4, 4), (BZ, 0, 0), (BZ, 1, 1), (BY, 4, 4), (GW, 5, 0), (GY, 5, 5),
+        (BY, 5, 5), (BZ, 2, 2), (GY, 4, 4), (BW, 5, 0), (GZ, 5, 5), (BZ, 3, 3), (BZ, 5, 5),
+        (BZ, 4, 4), (RX, 5, 0), (GY, 3, 0), (GX, 5, 0), (GZ, 3, 0), (BX, 5, 0), (BY, 3, 0),
+        (RY, 5, 0), (RZ, 5, 0), (D, 4, 0),
+    ] },
+    Mode { endpoint_bits: 10, delta_bits: [10, 10, 10], transformed: false, two_regions: false, layout: &[
+        (RW, 9, 0), (GW, 9, 0), (BW, 9, 0), (RX, 9, 0), (GX, 9, 0), (BX, 9, 0),
+    ] },
+    Mode { endpoint_bits: 11, delta_bits: [9, 9, 9], transformed: true, two_regions: false, layout: &[
+        (RW, 9, 0), (GW, 9, 0), (BW, 9, 0), (RX, 8, 0), (RW, 10, 10), (GX, 8, 0), (GW, 10, 10),
+        (BX, 8, 0), (BW, 10, 10),
+    ] },
+    Mode { endpoint_bits: 12, delta_bits: [8, 8, 8], transformed: true, two_regions: false, layout: &[
+        (RW, 9, 0), (GW, 9, 0), (BW, 9, 0), (RX, 7, 0), (RW, 10, 11), (GX, 7, 0), (GW, 10, 11),
+        (BX, 7, 0), (BW, 10, 11),
+    ] },
+    Mode { endpoint_bits: 16, delta_bits: [4, 4, 4], transformed: true, two_regions: false, layout: &[
+        (RW, 9, 0), (GW, 9, 0), (BW, 9, 0), (RX, 3, 0), (RW, 10, 15), (GX, 3, 0), (GW, 10, 15),
+        (BX, 3, 0), (BW, 10, 15),
+    ] },
+];
+
+const WEIGHTS_3: [i32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const WEIGHTS_4: [i32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+fn sign_extend(value: i32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    (value << shift) >> shift
+}
+
+/// Decodes one block, `signed` for `BC6H_SFLOAT`. Reserved modes decode to black.
+pub(super) fn decode_block(block: &[u8], signed: bool) -> [[f16; 4]; 16] {
+    let mut bits = Bits(u128::from_le_bytes(block[..16].try_into().unwrap()));
+    let mode_index = match bits.0 as u32 & 3 {
+        0 => 0,
+        1 => 1,
+        _ => match bits.0 as u32 & 31 {
+            mode @ (2 | 6 | 10 | 14 | 18 | 22 | 26 | 30) => mode as usize / 4 + 2,
+            mode @ (3 | 7 | 11 | 15) => mode as usize / 4 + 10,
+            _ => return [[f16::ZERO, f16::ZERO, f16::ZERO, f16::ONE]; 16],
+        },
+    };
+    let mode = &MODES[mode_index];
+    bits.take(if mode_index < 2 { 2 } else { 5 });
+
+    let mut endpoints = [[0i32; 3]; 4];
+    let mut partition = 0;
+    for &(field, a, b) in mode.layout {
+        let value = match field {
+            Field::Endpoint(endpoint, channel) => &mut endpoints[endpoint][channel],
+            Field::Partition => &mut partition,
+        };
+        if a >= b {
+            for bit in b..=a {
+                *value |= (bits.take(1) as i32) << bit;
+            }
+        } else {
+            for bit in (a..=b).rev() {
+                *value |= (bits.take(1) as i32) << bit;
+            }
+        }
+    }
+
+    let endpoint_count = if mode.two_regions { 4 } else { 2 };
+    let precision = mode.endpoint_bits;
+    for channel in 0..3 {
+        if signed {
+            endpoints[0][channel] = sign_extend(endpoints[0][channel], precision);
+        }
+        let base = endpoints[0][channel];
+        for endpoint in &mut endpoints[1..endpoint_count] {
+            if signed || mode.transformed {
+                endpoint[channel] = sign_extend(endpoint[channel], mode.delta_bits[channel]);
+            }
+            if mode.transformed {
+                // Deltas from the base endpoint wrap around at its precision
+                let value = (base + endpoint[channel]) & ((1 << precision) - 1);
+                endpoint[channel] = if signed {
+                    sign_extend(value, precision)
+                } else {
+                    value
+                };
+            }
+        }
+    }
+
+    let unquantize = |value: i32| -> i32 {
+        if signed {
+            if precision >= 16 {
+                return value;
+            }
+            let magnitude = value.abs();
+            let unquantized = if magnitude == 0 {
+                0
+            } else if magnitude >= (1 << (precision - 1)) - 1 {
+                0x7fff
+            } else {
+                ((magnitude << 15) + 0x4000) >> (precision - 1)
+            };
+            if value < 0 { -unquantized } else { unquantized }
+        } else if precision >= 15 || value == 0 {
+            value
+        } else if value == (1 << precision) - 1 {
+            0xffff
+        } else {
+            ((value << 16) + 0x8000) >> precision
+        }
+    };
+    let endpoints = endpoints.map(|endpoint| endpoint.map(unquantize));
+
+    let index_bits = if mode.two_regions { 3 } else { 4 };
+    let mut indices = [0u8; 16];
+    for (texel, index) in indices.iter_mut().enumerate() {
+        let anchor =
+            texel == 0 || (mode.two_regions && texel == BC7_ANCHORS_2[partition as usize] as usize);
+        *index = bits.take(index_bits - anchor as u32);
+    }
+
+    // Scales to the largest finite half float, or its negative
+    let finish = |value: i32| -> f16 {
+        if signed {
+            let scaled = (value.abs() * 31) >> 5;
+            f16::from_bits(scaled as u16 | if value < 0 { 0x8000 } else { 0 })
+        } else {
+            f16::from_bits(((value * 31) >> 6) as u16)
+        }
+    };
+
+    std::array::from_fn(|texel| {
+        let region = if mode.two_regions {
+            (BC7_PARTITIONS_2[partition as usize] >> texel) as usize & 1
+        } else {
+            0
+        };
+        let weight = if mode.two_regions {
+            WEIGHTS_3[indices[texel] as usize]
+        } else {
+            WEIGHTS_4[indices[texel] as usize]
+        };
+        let [e0, e1] = [endpoints[2 * region], endpoints[2 * region + 1]];
+        let [r, g, b] =
+            std::array::from_fn(|c| finish((e0[c] * (64 - weight) + e1[c] * weight + 32) >> 6));
+        [r, g, b, f16::ONE]
+    })
+}
@@ -0,0 +1,48 @@
+use rs_vulkan::assets::{AssetServer, Handle};
+use rs_vulkan::texture::TextureRole;
+
+/// A device on the software fallback adapter, `None` when there is none.
+fn fallback_device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        compatible_surface: None,
+        power_preference: wgpu::PowerPreference::default(),
+        force_fallback_adapter: true,
+    }))?;
+    pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None)).ok()
+}
+
+#[test]
+fn textures_are_shared_until_the_last_handle_is_dropped() {
+    let Some((device, queue)) = fallback_device() else {
+        eprintln!("Skipping, no fallback adapter available");
+        return;
+    };
+    let mut assets = AssetServer::new(&device, &queue, 1);
+
+    let texture = assets
+        .load_texture("assets/textures/checker.png", TextureRole::Color)
+        .unwrap();
+    // Different spellings of the same file hit the cache
+    let same = assets
+        .load_texture("assets/../assets/textures/checker.png", TextureRole::Color)
+        .unwrap();
+    assert!(Handle::ptr_eq(&texture, &same));
+    assert_eq!(assets.loaded_textures(), 1);
+
+    // The same file in another color space is a separate texture
+    let linear = assets
+        .load_texture("assets/textures/checker.png", TextureRole::Data)
+        .unwrap();
+    assert!(!Handle::ptr_eq(&texture, &linear));
+    assert_eq!(assets.loaded_textures(), 2);
+
+    drop((texture, same, linear));
+    assert_eq!(assets.loaded_textures(), 0);
+
+    let reloaded = assets
+        .load_texture("assets/textures/checker.png", TextureRole::Color)
+        .unwrap();
+    assert_eq!(Handle::handle_count(&reloaded), 1);
+    assert_eq!(assets.loaded_textures(), 1);
+}
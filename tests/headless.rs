@@ -0,0 +1,119 @@
+use std::path::Path;
+
+use rs_vulkan::{HeadlessRenderer, RendererConfig, Scene};
+
+const GOLDEN_DIR: &str = "tests/golden";
+/// Largest difference in any channel a pixel may have before it counts as changed,
+/// rasterizers disagree slightly on edges and filtering.
+const CHANNEL_TOLERANCE: u8 = 8;
+/// Fraction of the pixels allowed to differ by more than [`CHANNEL_TOLERANCE`].
+const MAX_CHANGED_FRACTION: f64 = 0.01;
+
+/// Whether the software fallback adapter exists, the tests are skipped without it.
+fn fallback_adapter_available() -> bool {
+    let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+    pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        compatible_surface: None,
+        power_preference: wgpu::PowerPreference::default(),
+        force_fallback_adapter: true,
+    }))
+    .is_some()
+}
+
+/// Compares `image` against `tests/golden/<name>.png`, or overwrites the golden
+/// image when `UPDATE_GOLDEN` is set.
+fn assert_matches_golden(name: &str, image: &image::RgbaImage) {
+    let path = Path::new(GOLDEN_DIR).join(format!("{name}.png"));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(GOLDEN_DIR).unwrap();
+        image.save(&path).unwrap();
+        return;
+    }
+
+    let golden = image::open(&path)
+        .unwrap_or_else(|err| panic!("Failed to open {}: {err}", path.display()))
+        .to_rgba8();
+    assert_eq!(image.dimensions(), golden.dimensions(), "{name}: size");
+
+    let changed = image
+        .pixels()
+        .zip(golden.pixels())
+        .filter(|(a, b)| {
+            a.0.iter()
+                .zip(b.0)
+                .any(|(a, b)| a.abs_diff(b) > CHANNEL_TOLERANCE)
+        })
+        .count();
+    let fraction = changed as f64 / (image.width() * image.height()) as f64;
+    if fraction > MAX_CHANGED_FRACTION {
+        let actual = std::env::temp_dir().join(format!("{name}.actual.png"));
+        image.save(&actual).unwrap();
+        panic!(
+            "{name}: {:.2}% of the pixels differ from {}, the render was saved to {}",
+            fraction * 100.0,
+            path.display(),
+            actual.display()
+        );
+    }
+}
+
+#[test]
+fn dice_scene_matches_golden() {
+    if !fallback_adapter_available() {
+        eprintln!("Skipping, no fallback adapter available");
+        return;
+    }
+
+    let config = RendererConfig {
+        scene: Scene::load("assets/scenes/dice.ron").unwrap(),
+        ..Default::default()
+    };
+    let renderer = pollster::block_on(HeadlessRenderer::new(160, 120, true, &config)).unwrap();
+    let image = renderer.render_to_image().unwrap();
+
+    assert_matches_golden("dice", &image);
+}
+
+#[test]
+fn empty_size_is_rejected() {
+    let result = pollster::block_on(HeadlessRenderer::new(
+        0,
+        120,
+        true,
+        &RendererConfig::default(),
+    ));
+    assert!(result.is_err());
+}
+
+#[test]
+fn resize_to_empty_size_is_rejected() {
+    if !fallback_adapter_available() {
+        eprintln!("Skipping, no fallback adapter available");
+        return;
+    }
+
+    let config = RendererConfig::default();
+    let mut renderer = pollster::block_on(HeadlessRenderer::new(64, 48, true, &config)).unwrap();
+    assert!(renderer.resize(64, 0).is_err());
+    assert!(renderer.resize(32, 24).is_ok());
+
+    let image = renderer.render_to_image().unwrap();
+    assert_eq!(image.dimensions(), (32, 24));
+}
+
+#[test]
+fn model_without_instances_is_skipped() {
+    if !fallback_adapter_available() {
+        eprintln!("Skipping, no fallback adapter available");
+        return;
+    }
+
+    let mut scene = Scene::default();
+    scene.models[0].instances.clear();
+    let config = RendererConfig {
+        scene,
+        ..Default::default()
+    };
+    let renderer = pollster::block_on(HeadlessRenderer::new(64, 48, true, &config)).unwrap();
+    renderer.render_to_image().unwrap();
+}